@@ -0,0 +1,53 @@
+mod harness;
+
+use harness::bench;
+use nesmulator::{
+    cpu::{Cpu, FlatRam, Registers},
+    Nes,
+};
+
+// A loop mixing the addressing modes and instruction kinds games use most
+const PROGRAM: [u8; 24] = [
+    0xA2, 0x00, //       $0600 LDX #$00
+    0xBD, 0x00, 0x02, // $0602 LDA $0200,X
+    0x69, 0x01, //       $0605 ADC #$01
+    0x9D, 0x00, 0x03, // $0607 STA $0300,X
+    0xE6, 0x10, //       $060A INC $10
+    0xB1, 0x20, //       $060C LDA ($20),Y
+    0x20, 0x17, 0x06, // $060E JSR $0617
+    0xE8, //             $0611 INX
+    0xD0, 0xEE, //       $0612 BNE $0602
+    0x4C, 0x00, 0x06, // $0614 JMP $0600
+    0x60, //             $0617 RTS
+];
+
+const INSTRUCTIONS: u64 = 2_000_000;
+
+fn main() {
+    // The bare CPU on flat RAM
+    let mut bus = FlatRam::new();
+    bus.load(0x0600, &PROGRAM);
+    let mut cpu = Cpu::new();
+    cpu.reset(&mut bus);
+    cpu.set_registers(Registers {
+        pc: 0x0600,
+        ..cpu.registers()
+    });
+    bench("cpu/flat_ram", "instructions", || {
+        for _ in 0..INSTRUCTIONS {
+            cpu.step(&mut bus);
+        }
+        INSTRUCTIONS
+    });
+
+    // The same CPU clocking the PPU and APU along, on the sieve ROM
+    let (_, source) = harness::ROMS[1];
+    let mut nes = Nes::new(&harness::build_rom(source)).unwrap();
+    bench("cpu/nes_sieve", "instructions", || {
+        for _ in 0..INSTRUCTIONS {
+            nes.step_instruction();
+        }
+        nes.drain_audio();
+        INSTRUCTIONS
+    });
+}
@@ -0,0 +1,24 @@
+mod harness;
+
+use harness::bench;
+use nesmulator::Nes;
+
+const FRAMES: u64 = 60;
+
+// Whole frames of the machine running each benchmark ROM
+fn main() {
+    for (name, source) in harness::ROMS {
+        for catch_up in [false, true] {
+            let mut nes = Nes::new(&harness::build_rom(source)).unwrap();
+            nes.set_ppu_catch_up(catch_up);
+            let suffix = if catch_up { "_catch_up" } else { "" };
+            bench(&format!("frames/{}{}", name, suffix), "frames", || {
+                for _ in 0..FRAMES {
+                    nes.run_frame();
+                    nes.drain_audio();
+                }
+                FRAMES
+            });
+        }
+    }
+}
@@ -0,0 +1,99 @@
+// Each benchmark uses only part of this
+#![allow(dead_code)]
+
+use std::{env, fs, path::PathBuf, time::Instant};
+
+use nesmulator::asm;
+
+// In the spirit of criterion: a warm-up run, then timed samples reported as
+// the median rate with the slowest and fastest. Those are kept under
+// target/nes-bench, a median outside the last run's range is reported as a
+// change, anything inside it is noise
+const SAMPLES: usize = 10;
+
+// `sample` does one sample's worth of work and tells how many units it did
+pub fn bench<F: FnMut() -> u64>(name: &str, unit: &str, mut sample: F) {
+    // `cargo bench -- ppu` runs the benchmarks with "ppu" in their name
+    let filter = env::args().skip(1).find(|arg| !arg.starts_with('-'));
+    if filter.is_some_and(|filter| !name.contains(&filter)) {
+        return;
+    }
+
+    sample();
+    let mut rates: Vec<f64> = (0..SAMPLES)
+        .map(|_| {
+            let start = Instant::now();
+            let work = sample();
+            work as f64 / start.elapsed().as_secs_f64()
+        })
+        .collect();
+    rates.sort_by(f64::total_cmp);
+    let (slowest, fastest) = (rates[0], rates[SAMPLES - 1]);
+    let median = (rates[SAMPLES / 2 - 1] + rates[SAMPLES / 2]) / 2.0;
+
+    println!(
+        "{:20} {:>9} {}/s  [{} .. {}]",
+        name,
+        format_rate(median),
+        unit,
+        format_rate(slowest),
+        format_rate(fastest)
+    );
+
+    // Median, slowest and fastest of the last run
+    let path = baseline_dir().join(name.replace('/', "-"));
+    let previous: Option<Vec<f64>> = fs::read_to_string(&path)
+        .ok()
+        .and_then(|text| text.split_whitespace().map(|n| n.parse().ok()).collect());
+    if let Some([previous, previous_slowest, previous_fastest]) = previous.as_deref() {
+        let verdict = if median < *previous_slowest {
+            "regressed"
+        } else if median > *previous_fastest {
+            "improved"
+        } else {
+            "within noise"
+        };
+        let change = median / previous - 1.0;
+        println!("{:20} change {:+.1}%, {}", "", change * 100.0, verdict);
+    }
+    let _ = fs::create_dir_all(baseline_dir());
+    let _ = fs::write(&path, format!("{} {} {}\n", median, slowest, fastest));
+}
+
+fn baseline_dir() -> PathBuf {
+    env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target"))
+        .join("nes-bench")
+}
+
+fn format_rate(rate: f64) -> String {
+    if rate >= 1e6 {
+        format!("{:.2} M", rate / 1e6)
+    } else if rate >= 1e3 {
+        format!("{:.2} K", rate / 1e3)
+    } else {
+        format!("{:.1}", rate)
+    }
+}
+
+// Homebrew ROMs made for the benchmarks, assembled when they run
+pub const ROMS: [(&str, &str); 3] = [
+    ("sprites", include_str!("../roms/sprites.s")),
+    ("sieve", include_str!("../roms/sieve.s")),
+    ("tones", include_str!("../roms/tones.s")),
+];
+
+const INIT: &str = include_str!("../roms/init.s");
+
+// iNES image with 16 KiB of PRG and CHR RAM, vertical mirroring
+pub fn build_rom(source: &str) -> Vec<u8> {
+    let source = format!("{}\n{}\n.org $FFFA\n.word nmi, reset, irq\n", INIT, source);
+    let assembly = asm::assemble(&source, 0xC000).unwrap_or_else(|err| panic!("{}", err));
+    assert_eq!(assembly.bytes.len(), 0x4000);
+
+    let mut rom = vec![b'N', b'E', b'S', 0x1A, 1, 0, 0x01, 0];
+    rom.resize(16, 0);
+    rom.extend(assembly.bytes);
+    rom
+}
@@ -0,0 +1,70 @@
+mod harness;
+
+use harness::bench;
+use nesmulator::{
+    mapper::{new_mapper, Mapper},
+    ppu::Ppu,
+    rom::Rom,
+};
+
+const FRAMES: usize = 60;
+
+// The PPU on its own with tiles, both nametables, a palette and 64 sprites
+// loaded through its registers
+fn setup(mask: u8) -> (Ppu, Box<dyn Mapper>) {
+    let (_, source) = harness::ROMS[0];
+    let rom = Rom::new(&harness::build_rom(source)).unwrap();
+    let mut mapper = new_mapper(rom).unwrap();
+    let mut ppu = Ppu::new();
+
+    let mut upload = |ppu: &mut Ppu, addr: u16, data: &mut dyn Iterator<Item = u8>| {
+        ppu.write_to_address((addr >> 8) as u8);
+        ppu.write_to_address(addr as u8);
+        for value in data {
+            ppu.write(&mut *mapper, value);
+        }
+    };
+    upload(
+        &mut ppu,
+        0x0000,
+        &mut (0..0x1000u32).map(|i| (i ^ (i >> 8)) as u8),
+    );
+    upload(
+        &mut ppu,
+        0x2000,
+        &mut (0..0x800u32).map(|i| (i + (i >> 8)) as u8),
+    );
+    upload(&mut ppu, 0x3F00, &mut (0..0x20u8).map(|i| (i * 3) & 0x3F));
+
+    for i in 0..64u8 {
+        for value in [i * 3, i, i & 3, i * 4] {
+            ppu.write_to_oam_data(value);
+        }
+    }
+    ppu.write_to_control(0);
+    ppu.write_to_mask(mask);
+    (ppu, mapper)
+}
+
+fn run_frames(ppu: &mut Ppu, mapper: &mut dyn Mapper) -> u64 {
+    let mut dots = 0;
+    for _ in 0..FRAMES {
+        loop {
+            dots += 1;
+            if ppu.tick(mapper) {
+                break;
+            }
+        }
+    }
+    dots
+}
+
+fn main() {
+    let (mut ppu, mut mapper) = setup(0b0001_1110);
+    bench("ppu/rendering", "dots", || {
+        run_frames(&mut ppu, &mut *mapper)
+    });
+
+    let (mut ppu, mut mapper) = setup(0);
+    bench("ppu/blank", "dots", || run_frames(&mut ppu, &mut *mapper));
+}
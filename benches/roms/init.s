@@ -0,0 +1,105 @@
+; Start-up shared by the benchmark ROMs. NROM with 16 KiB of PRG at $C000
+; and CHR RAM. Each ROM defines main, nmi and irq, the vectors are added
+; after it. Written for these benchmarks, public domain.
+
+PPUCTRL   = $2000
+PPUMASK   = $2001
+PPUSTATUS = $2002
+OAMADDR   = $2003
+PPUSCROLL = $2005
+PPUADDR   = $2006
+PPUDATA   = $2007
+OAMDMA    = $4014
+APUSTATUS = $4015
+FRAMECTR  = $4017
+
+frame  = $00        ; bumped by nmi
+page   = $01
+scroll = $02
+
+        .org $C000
+reset:  sei
+        cld
+        ldx #$FF
+        txs
+        inx
+        stx PPUCTRL
+        stx PPUMASK
+        stx $4010
+        lda #$40
+        sta FRAMECTR
+wait1:  bit PPUSTATUS
+        bpl wait1
+
+        txa
+clear:  sta $00,x
+        sta $0100,x
+        sta $0300,x
+        sta $0400,x
+        sta $0500,x
+        sta $0600,x
+        sta $0700,x
+        inx
+        bne clear
+        ; Sprites off screen
+        lda #$FF
+hide:   sta $0200,x
+        inx
+        bne hide
+wait2:  bit PPUSTATUS
+        bpl wait2
+
+        ; 4 KiB of tiles, each row a mix of the tile and row numbers
+        lda #$00
+        sta PPUADDR
+        sta PPUADDR
+        ldy #$10
+tiles:  txa
+        eor page
+        sta PPUDATA
+        inx
+        bne tiles
+        inc page
+        dey
+        bne tiles
+
+        ; Both nametables, tile numbers running along the rows
+        lda #$20
+        sta PPUADDR
+        lda #$00
+        sta PPUADDR
+        ldy #$08
+names:  txa
+        clc
+        adc page
+        sta PPUDATA
+        inx
+        bne names
+        inc page
+        dey
+        bne names
+
+        lda #$3F
+        sta PPUADDR
+        lda #$00
+        sta PPUADDR
+pal:    lda palette,x
+        sta PPUDATA
+        inx
+        cpx #$20
+        bne pal
+
+        lda #$00
+        sta PPUSCROLL
+        sta PPUSCROLL
+        lda #%1000_0000
+        sta PPUCTRL
+        lda #%0001_1110
+        sta PPUMASK
+        jmp main
+
+palette:
+        .byte $0F, $01, $11, $21, $0F, $06, $16, $26
+        .byte $0F, $09, $19, $29, $0F, $04, $14, $24
+        .byte $0F, $02, $12, $22, $0F, $07, $17, $27
+        .byte $0F, $0A, $1A, $2A, $0F, $05, $15, $25
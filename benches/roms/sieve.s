@@ -0,0 +1,57 @@
+; Sieve of Eratosthenes over the numbers below 1024, again and again, with
+; the background shown. Busy CPU, idle PPU. Written for these benchmarks,
+; public domain.
+
+num  = $10          ; 16 bit
+ptr  = $12          ; 16 bit, into the flags at $0300
+runs = $14
+
+main:   lda #$00
+        ldx #$00
+clr:    sta $0300,x
+        sta $0400,x
+        sta $0500,x
+        sta $0600,x
+        inx
+        bne clr
+
+        lda #$02
+        sta num
+        lda #$00
+        sta num+1
+        ldy #$00
+outer:  lda num
+        sta ptr
+        lda num+1
+        clc
+        adc #$03
+        sta ptr+1
+        lda (ptr),y
+        bne skip
+        ; Cross out the multiples
+mark:   clc
+        lda ptr
+        adc num
+        sta ptr
+        lda ptr+1
+        adc num+1
+        sta ptr+1
+        cmp #$07
+        bcs skip
+        lda #$01
+        sta (ptr),y
+        jmp mark
+skip:   inc num
+        bne check
+        inc num+1
+check:  lda num+1
+        cmp #$04
+        bcc outer
+
+        inc runs
+        jmp main
+
+nmi:    inc frame
+        rti
+
+irq:    rti
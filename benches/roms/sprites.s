@@ -0,0 +1,52 @@
+; 64 moving sprites uploaded by OAM DMA and a background scrolling a
+; pixel a frame. Written for these benchmarks, public domain.
+
+main:   ldx #$00
+place:  txa
+        asl a
+        asl a
+        sta $0200,x         ; Y
+        sta $0201,x         ; tile
+        and #$03
+        sta $0202,x         ; attributes
+        txa
+        sta $0203,x         ; X
+        inx
+        inx
+        inx
+        inx
+        bne place
+
+loop:   lda frame
+idle:   cmp frame
+        beq idle
+        ; Sprites drift right, every other one down too
+        ldx #$00
+move:   inc $0203,x
+        txa
+        and #$04
+        beq next
+        inc $0200,x
+next:   inx
+        inx
+        inx
+        inx
+        bne move
+        jmp loop
+
+nmi:    pha
+        lda #$00
+        sta OAMADDR
+        lda #$02
+        sta OAMDMA
+        inc scroll
+        bit PPUSTATUS
+        lda scroll
+        sta PPUSCROLL
+        lda #$00
+        sta PPUSCROLL
+        inc frame
+        pla
+        rti
+
+irq:    rti
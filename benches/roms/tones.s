@@ -0,0 +1,70 @@
+; All five APU channels playing, with the frame counter IRQ stepping a
+; melody and the DMC looping over PRG. Written for these benchmarks,
+; public domain.
+
+note = $10
+
+main:   lda #%0001_1111
+        sta APUSTATUS
+        lda #%1011_1111     ; pulse 1, duty 50%, constant volume
+        sta $4000
+        lda #%0111_1000     ; pulse 2, duty 25%, volume 8
+        sta $4004
+        lda #$FF            ; triangle, linear counter held
+        sta $4008
+        lda #%0011_0100     ; noise, volume 4
+        sta $400C
+        lda #$05
+        sta $400E
+        lda #$08
+        sta $400F
+        lda #$F0
+        sta $400A
+        lda #$08
+        sta $400B
+        ; DMC loops over $F000 at the fastest rate
+        lda #%0100_1111
+        sta $4010
+        lda #$C0
+        sta $4012
+        lda #$FF
+        sta $4013
+        lda #%0001_1111
+        sta APUSTATUS
+
+        ; Four step mode with the IRQ, about once a frame
+        lda #$00
+        sta FRAMECTR
+        cli
+loop:   jmp loop
+
+irq:    pha
+        txa
+        pha
+        lda APUSTATUS       ; acknowledges the IRQ
+        inc note
+        lda note
+        and #$0F
+        tax
+        lda melody,x
+        sta $4002
+        lda #$01
+        sta $4003
+        lda melody,x
+        lsr a
+        sta $4006
+        lda #$00
+        sta $4007
+        pla
+        tax
+        pla
+        rti
+
+nmi:    inc frame
+        rti
+
+melody: .byte $FD, $E1, $C9, $BD, $A9, $96, $86, $7E
+        .byte $86, $96, $A9, $BD, $C9, $E1, $FD, $7E
+
+        .org $F000
+sample: .byte $AA, $55, $F0, $0F, $CC, $33, $FF, $00
@@ -0,0 +1,194 @@
+use crate::savestate::{Section, Snapshot};
+
+// NTSC rates in CPU cycles
+const RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+// https://www.nesdev.org/wiki/APU_DMC
+pub struct Dmc {
+    irq_enabled: bool,
+    looped: bool,
+    timer_period: u16,
+    timer: u16,
+    output_level: u8,
+    sample_addr: u16,
+    sample_length: u16,
+    current_addr: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+    pub irq_flag: bool,
+}
+
+impl Dmc {
+    pub fn new() -> Self {
+        Dmc {
+            irq_enabled: false,
+            looped: false,
+            timer_period: RATE_TABLE[0],
+            timer: 0,
+            output_level: 0,
+            sample_addr: 0xC000,
+            sample_length: 1,
+            current_addr: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+            irq_flag: false,
+        }
+    }
+
+    // IL-- RRRR
+    pub fn write_control(&mut self, value: u8) {
+        self.irq_enabled = value & 0b1000_0000 != 0;
+        self.looped = value & 0b0100_0000 != 0;
+        self.timer_period = RATE_TABLE[(value & 0b1111) as usize];
+        if !self.irq_enabled {
+            self.irq_flag = false;
+        }
+    }
+
+    // -DDD DDDD
+    pub fn write_direct_load(&mut self, value: u8) {
+        self.output_level = value & 0b0111_1111;
+    }
+
+    // Sample address = %11AAAAAA.AA000000
+    pub fn write_sample_addr(&mut self, value: u8) {
+        self.sample_addr = 0xC000 | ((value as u16) << 6);
+    }
+
+    // Sample length = %LLLL.LLLL0001
+    pub fn write_sample_length(&mut self, value: u8) {
+        self.sample_length = ((value as u16) << 4) | 1;
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq_flag = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_addr = self.sample_addr;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    // Address the memory reader wants to fetch, the bus answers with `fill_sample_buffer`
+    pub fn pending_fetch(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_addr)
+        } else {
+            None
+        }
+    }
+
+    pub fn fill_sample_buffer(&mut self, value: u8) {
+        self.sample_buffer = Some(value);
+        // Address wraps around to $8000, not $0000
+        self.current_addr = if self.current_addr == 0xFFFF {
+            0x8000
+        } else {
+            self.current_addr + 1
+        };
+
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looped {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq_flag = true;
+            }
+        }
+    }
+
+    // Clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period - 1;
+
+        if !self.silence {
+            if self.shift_register & 1 == 1 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(value) => {
+                    self.silence = false;
+                    self.shift_register = value;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        self.output_level
+    }
+}
+
+impl Snapshot for Dmc {
+    fn save(&self, section: &mut Section) {
+        section.put_bool("irq_enabled", self.irq_enabled);
+        section.put_bool("looped", self.looped);
+        section.put_u16("timer_period", self.timer_period);
+        section.put_u16("timer", self.timer);
+        section.put_u8("output_level", self.output_level);
+        section.put_u16("sample_addr", self.sample_addr);
+        section.put_u16("sample_length", self.sample_length);
+        section.put_u16("current_addr", self.current_addr);
+        section.put_u16("bytes_remaining", self.bytes_remaining);
+        section.put_bool("sample_buffer_full", self.sample_buffer.is_some());
+        section.put_u8("sample_buffer", self.sample_buffer.unwrap_or(0));
+        section.put_u8("shift_register", self.shift_register);
+        section.put_u8("bits_remaining", self.bits_remaining);
+        section.put_bool("silence", self.silence);
+        section.put_bool("irq_flag", self.irq_flag);
+    }
+
+    fn load(&mut self, section: &Section) -> Result<(), String> {
+        self.irq_enabled = section.get_bool("irq_enabled")?;
+        self.looped = section.get_bool("looped")?;
+        self.timer_period = section.get_u16("timer_period")?;
+        self.timer = section.get_u16("timer")?;
+        self.output_level = section.get_u8("output_level")?;
+        self.sample_addr = section.get_u16("sample_addr")?;
+        self.sample_length = section.get_u16("sample_length")?;
+        self.current_addr = section.get_u16("current_addr")?;
+        self.bytes_remaining = section.get_u16("bytes_remaining")?;
+        self.sample_buffer = if section.get_bool("sample_buffer_full")? {
+            Some(section.get_u8("sample_buffer")?)
+        } else {
+            None
+        };
+        self.shift_register = section.get_u8("shift_register")?;
+        self.bits_remaining = section.get_u8("bits_remaining")?;
+        self.silence = section.get_bool("silence")?;
+        self.irq_flag = section.get_bool("irq_flag")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+use crate::savestate::{Section, Snapshot};
+
+// https://www.nesdev.org/wiki/APU_Length_Counter
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+pub struct LengthCounter {
+    enabled: bool,
+    halt: bool,
+    counter: u8,
+}
+
+impl LengthCounter {
+    pub fn new() -> Self {
+        LengthCounter {
+            enabled: false,
+            halt: false,
+            counter: 0,
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+    pub fn set_halt(&mut self, halt: bool) {
+        self.halt = halt;
+    }
+
+    pub fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(index >> 3) as usize];
+        }
+    }
+
+    // Clocked by half frames
+    pub fn clock(&mut self) {
+        if !self.halt && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.counter > 0
+    }
+}
+
+// https://www.nesdev.org/wiki/APU_Envelope
+pub struct Envelope {
+    start: bool,
+    looped: bool,
+    constant: bool,
+    period: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Self {
+        Envelope {
+            start: false,
+            looped: false,
+            constant: false,
+            period: 0,
+            divider: 0,
+            decay: 0,
+        }
+    }
+
+    // --LC VVVV
+    pub fn update(&mut self, value: u8) {
+        self.looped = value & 0b0010_0000 != 0;
+        self.constant = value & 0b0001_0000 != 0;
+        self.period = value & 0b0000_1111;
+    }
+
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    // Clocked by quarter frames
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.period;
+            return;
+        }
+
+        if self.divider > 0 {
+            self.divider -= 1;
+            return;
+        }
+
+        self.divider = self.period;
+        if self.decay > 0 {
+            self.decay -= 1;
+        } else if self.looped {
+            self.decay = 15;
+        }
+    }
+
+    pub fn volume(&self) -> u8 {
+        if self.constant {
+            self.period
+        } else {
+            self.decay
+        }
+    }
+}
+
+impl Snapshot for LengthCounter {
+    fn save(&self, section: &mut Section) {
+        section.put_bool("enabled", self.enabled);
+        section.put_bool("halt", self.halt);
+        section.put_u8("counter", self.counter);
+    }
+
+    fn load(&mut self, section: &Section) -> Result<(), String> {
+        self.enabled = section.get_bool("enabled")?;
+        self.halt = section.get_bool("halt")?;
+        self.counter = section.get_u8("counter")?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Envelope {
+    fn save(&self, section: &mut Section) {
+        section.put_bool("start", self.start);
+        section.put_bool("looped", self.looped);
+        section.put_bool("constant", self.constant);
+        section.put_u8("period", self.period);
+        section.put_u8("divider", self.divider);
+        section.put_u8("decay", self.decay);
+    }
+
+    fn load(&mut self, section: &Section) -> Result<(), String> {
+        self.start = section.get_bool("start")?;
+        self.looped = section.get_bool("looped")?;
+        self.constant = section.get_bool("constant")?;
+        self.period = section.get_u8("period")?;
+        self.divider = section.get_u8("divider")?;
+        self.decay = section.get_u8("decay")?;
+        Ok(())
+    }
+}
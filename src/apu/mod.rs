@@ -0,0 +1,290 @@
+mod dmc;
+mod envelope;
+mod noise;
+mod pulse;
+mod triangle;
+
+use crate::savestate::{Section, Snapshot};
+use dmc::Dmc;
+use noise::Noise;
+use pulse::Pulse;
+use triangle::Triangle;
+
+pub const CPU_FREQUENCY: f64 = 1_789_773.0;
+pub const SAMPLE_RATE: f64 = 44_100.0;
+
+// Frame counter steps in CPU cycles
+// https://www.nesdev.org/wiki/APU_Frame_Counter
+const STEP_1: u32 = 7457;
+const STEP_2: u32 = 14913;
+const STEP_3: u32 = 22371;
+const STEP_4: u32 = 29829;
+const STEP_5: u32 = 37281;
+
+pub struct Apu {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    // false: 4-step sequence, true: 5-step sequence
+    five_step_mode: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    frame_cycle: u32,
+    cycle: u64,
+    sample_timer: f64,
+    sample_sum: f32,
+    sample_count: u32,
+    samples: Vec<f32>,
+    muted: bool,
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Apu {
+            pulse1: Pulse::new(true),
+            pulse2: Pulse::new(false),
+            triangle: Triangle::new(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+            five_step_mode: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            frame_cycle: 0,
+            cycle: 0,
+            sample_timer: 0.0,
+            sample_sum: 0.0,
+            sample_count: 0,
+            samples: Vec::new(),
+            muted: false,
+        }
+    }
+
+    pub fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4000 => self.pulse1.write_control(value),
+            0x4001 => self.pulse1.write_sweep(value),
+            0x4002 => self.pulse1.write_timer_lo(value),
+            0x4003 => self.pulse1.write_timer_hi(value),
+
+            0x4004 => self.pulse2.write_control(value),
+            0x4005 => self.pulse2.write_sweep(value),
+            0x4006 => self.pulse2.write_timer_lo(value),
+            0x4007 => self.pulse2.write_timer_hi(value),
+
+            0x4008 => self.triangle.write_linear_counter(value),
+            0x400A => self.triangle.write_timer_lo(value),
+            0x400B => self.triangle.write_timer_hi(value),
+
+            0x400C => self.noise.write_control(value),
+            0x400E => self.noise.write_period(value),
+            0x400F => self.noise.write_length(value),
+
+            0x4010 => self.dmc.write_control(value),
+            0x4011 => self.dmc.write_direct_load(value),
+            0x4012 => self.dmc.write_sample_addr(value),
+            0x4013 => self.dmc.write_sample_length(value),
+
+            // ---D NT21
+            0x4015 => {
+                self.pulse1.length.set_enabled(value & 0b0000_0001 != 0);
+                self.pulse2.length.set_enabled(value & 0b0000_0010 != 0);
+                self.triangle.length.set_enabled(value & 0b0000_0100 != 0);
+                self.noise.length.set_enabled(value & 0b0000_1000 != 0);
+                self.dmc.set_enabled(value & 0b0001_0000 != 0);
+            }
+            // MI-- ----
+            0x4017 => {
+                self.five_step_mode = value & 0b1000_0000 != 0;
+                self.irq_inhibit = value & 0b0100_0000 != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+
+                self.frame_cycle = 0;
+                if self.five_step_mode {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // IF-D NT21
+    pub fn read_status(&mut self) -> u8 {
+        let mut status = 0;
+        if self.pulse1.length.is_active() {
+            status |= 0b0000_0001;
+        }
+        if self.pulse2.length.is_active() {
+            status |= 0b0000_0010;
+        }
+        if self.triangle.length.is_active() {
+            status |= 0b0000_0100;
+        }
+        if self.noise.length.is_active() {
+            status |= 0b0000_1000;
+        }
+        if self.dmc.is_active() {
+            status |= 0b0001_0000;
+        }
+        if self.frame_irq {
+            status |= 0b0100_0000;
+        }
+        if self.dmc.irq_flag {
+            status |= 0b1000_0000;
+        }
+
+        // Reading status clears the frame interrupt flag
+        self.frame_irq = false;
+
+        status
+    }
+
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc.irq_flag
+    }
+
+    pub fn dmc_pending_fetch(&self) -> Option<u16> {
+        self.dmc.pending_fetch()
+    }
+    pub fn dmc_fill_sample_buffer(&mut self, value: u8) {
+        self.dmc.fill_sample_buffer(value);
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.envelope.clock();
+        self.pulse2.envelope.clock();
+        self.noise.envelope.clock();
+        self.triangle.clock_linear_counter();
+    }
+    fn clock_half_frame(&mut self) {
+        self.pulse1.length.clock();
+        self.pulse1.clock_sweep();
+        self.pulse2.length.clock();
+        self.pulse2.clock_sweep();
+        self.triangle.length.clock();
+        self.noise.length.clock();
+    }
+
+    fn clock_frame_counter(&mut self) {
+        self.frame_cycle += 1;
+
+        match (self.frame_cycle, self.five_step_mode) {
+            (STEP_1, _) | (STEP_3, _) => self.clock_quarter_frame(),
+            (STEP_2, _) | (STEP_5, true) => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            (STEP_4, false) => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+                if !self.irq_inhibit {
+                    self.frame_irq = true;
+                }
+                self.frame_cycle = 0;
+            }
+            _ => (),
+        }
+
+        if self.five_step_mode && self.frame_cycle >= STEP_5 {
+            self.frame_cycle = 0;
+        }
+    }
+
+    // https://www.nesdev.org/wiki/APU_Mixer
+    fn mix(&self) -> f32 {
+        let pulse = (self.pulse1.output() + self.pulse2.output()) as f32;
+        let pulse_out = if pulse == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulse + 100.0)
+        };
+
+        let tnd = self.triangle.output() as f32 / 8227.0
+            + self.noise.output() as f32 / 12241.0
+            + self.dmc.output() as f32 / 22638.0;
+        let tnd_out = if tnd == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / tnd + 100.0)
+        };
+
+        pulse_out + tnd_out
+    }
+
+    // Advances by one CPU cycle
+    pub fn tick(&mut self) {
+        self.cycle += 1;
+
+        self.triangle.clock_timer();
+        self.dmc.clock_timer();
+        if self.cycle.is_multiple_of(2) {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+            self.noise.clock_timer();
+        }
+        self.clock_frame_counter();
+
+        // Downsample by averaging every output between two samples
+        self.sample_sum += self.mix();
+        self.sample_count += 1;
+        self.sample_timer += SAMPLE_RATE;
+        if self.sample_timer >= CPU_FREQUENCY {
+            self.sample_timer -= CPU_FREQUENCY;
+            if !self.muted {
+                self.samples
+                    .push(self.sample_sum / self.sample_count as f32);
+            }
+            self.sample_sum = 0.0;
+            self.sample_count = 0;
+        }
+    }
+
+    pub fn drain_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    // Keeps running but produces no samples
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+}
+
+// Samples not yet drained are not part of the state
+impl Snapshot for Apu {
+    fn save(&self, section: &mut Section) {
+        section.put_snapshot("pulse1", &self.pulse1);
+        section.put_snapshot("pulse2", &self.pulse2);
+        section.put_snapshot("triangle", &self.triangle);
+        section.put_snapshot("noise", &self.noise);
+        section.put_snapshot("dmc", &self.dmc);
+        section.put_bool("five_step_mode", self.five_step_mode);
+        section.put_bool("irq_inhibit", self.irq_inhibit);
+        section.put_bool("frame_irq", self.frame_irq);
+        section.put_u32("frame_cycle", self.frame_cycle);
+        section.put_u64("cycle", self.cycle);
+        section.put_f64("sample_timer", self.sample_timer);
+        section.put_f32("sample_sum", self.sample_sum);
+        section.put_u32("sample_count", self.sample_count);
+    }
+
+    fn load(&mut self, section: &Section) -> Result<(), String> {
+        section.get_snapshot("pulse1", &mut self.pulse1)?;
+        section.get_snapshot("pulse2", &mut self.pulse2)?;
+        section.get_snapshot("triangle", &mut self.triangle)?;
+        section.get_snapshot("noise", &mut self.noise)?;
+        section.get_snapshot("dmc", &mut self.dmc)?;
+        self.five_step_mode = section.get_bool("five_step_mode")?;
+        self.irq_inhibit = section.get_bool("irq_inhibit")?;
+        self.frame_irq = section.get_bool("frame_irq")?;
+        self.frame_cycle = section.get_u32("frame_cycle")?;
+        self.cycle = section.get_u64("cycle")?;
+        self.sample_timer = section.get_f64("sample_timer")?;
+        self.sample_sum = section.get_f32("sample_sum")?;
+        self.sample_count = section.get_u32("sample_count")?;
+        Ok(())
+    }
+}
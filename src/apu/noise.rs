@@ -0,0 +1,90 @@
+use super::envelope::{Envelope, LengthCounter};
+use crate::savestate::{Section, Snapshot};
+
+// NTSC periods in CPU cycles
+const PERIOD_TABLE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+// https://www.nesdev.org/wiki/APU_Noise
+pub struct Noise {
+    mode: bool,
+    shift_register: u16,
+    timer_period: u16,
+    timer: u16,
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        Noise {
+            mode: false,
+            shift_register: 1,
+            timer_period: PERIOD_TABLE[0],
+            timer: 0,
+            envelope: Envelope::new(),
+            length: LengthCounter::new(),
+        }
+    }
+
+    // --LC VVVV
+    pub fn write_control(&mut self, value: u8) {
+        self.length.set_halt(value & 0b0010_0000 != 0);
+        self.envelope.update(value);
+    }
+
+    // M--- PPPP
+    pub fn write_period(&mut self, value: u8) {
+        self.mode = value & 0b1000_0000 != 0;
+        self.timer_period = PERIOD_TABLE[(value & 0b1111) as usize];
+    }
+
+    // LLLL L---
+    pub fn write_length(&mut self, value: u8) {
+        self.length.load(value);
+        self.envelope.restart();
+    }
+
+    // Clocked every second CPU cycle, so the table period is halved
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period / 2;
+
+            let tap = if self.mode { 6 } else { 1 };
+            let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+            self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length.is_active() || self.shift_register & 1 == 1 {
+            return 0;
+        }
+
+        self.envelope.volume()
+    }
+}
+
+impl Snapshot for Noise {
+    fn save(&self, section: &mut Section) {
+        section.put_bool("mode", self.mode);
+        section.put_u16("shift_register", self.shift_register);
+        section.put_u16("timer_period", self.timer_period);
+        section.put_u16("timer", self.timer);
+        section.put_snapshot("envelope", &self.envelope);
+        section.put_snapshot("length", &self.length);
+    }
+
+    fn load(&mut self, section: &Section) -> Result<(), String> {
+        self.mode = section.get_bool("mode")?;
+        self.shift_register = section.get_u16("shift_register")?;
+        self.timer_period = section.get_u16("timer_period")?;
+        self.timer = section.get_u16("timer")?;
+        section.get_snapshot("envelope", &mut self.envelope)?;
+        section.get_snapshot("length", &mut self.length)?;
+        Ok(())
+    }
+}
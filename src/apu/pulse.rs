@@ -0,0 +1,162 @@
+use super::envelope::{Envelope, LengthCounter};
+use crate::savestate::{Section, Snapshot};
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+// https://www.nesdev.org/wiki/APU_Pulse
+pub struct Pulse {
+    // Pulse 1 negates with one's complement, pulse 2 with two's complement
+    ones_complement: bool,
+    duty: u8,
+    sequence_step: u8,
+    timer_period: u16,
+    timer: u16,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+}
+
+impl Pulse {
+    pub fn new(ones_complement: bool) -> Self {
+        Pulse {
+            ones_complement,
+            duty: 0,
+            sequence_step: 0,
+            timer_period: 0,
+            timer: 0,
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_divider: 0,
+            sweep_reload: false,
+            envelope: Envelope::new(),
+            length: LengthCounter::new(),
+        }
+    }
+
+    // DDLC VVVV
+    pub fn write_control(&mut self, value: u8) {
+        self.duty = value >> 6;
+        self.length.set_halt(value & 0b0010_0000 != 0);
+        self.envelope.update(value);
+    }
+
+    // EPPP NSSS
+    pub fn write_sweep(&mut self, value: u8) {
+        self.sweep_enabled = value & 0b1000_0000 != 0;
+        self.sweep_period = (value >> 4) & 0b111;
+        self.sweep_negate = value & 0b0000_1000 != 0;
+        self.sweep_shift = value & 0b111;
+        self.sweep_reload = true;
+    }
+
+    pub fn write_timer_lo(&mut self, value: u8) {
+        self.timer_period = (self.timer_period & 0xFF00) | value as u16;
+    }
+
+    // LLLL LHHH
+    pub fn write_timer_hi(&mut self, value: u8) {
+        self.timer_period = (self.timer_period & 0x00FF) | ((value as u16 & 0b111) << 8);
+        self.length.load(value);
+        self.sequence_step = 0;
+        self.envelope.restart();
+    }
+
+    fn sweep_target(&self) -> u16 {
+        let change = self.timer_period >> self.sweep_shift;
+        if self.sweep_negate {
+            let change = if self.ones_complement {
+                change + 1
+            } else {
+                change
+            };
+            self.timer_period.saturating_sub(change)
+        } else {
+            self.timer_period + change
+        }
+    }
+
+    fn is_muted(&self) -> bool {
+        self.timer_period < 8 || self.sweep_target() > 0x07FF
+    }
+
+    // Clocked every second CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.sequence_step = (self.sequence_step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    // Clocked by half frames
+    pub fn clock_sweep(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.is_muted()
+        {
+            self.timer_period = self.sweep_target();
+        }
+
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length.is_active()
+            || self.is_muted()
+            || DUTY_TABLE[self.duty as usize][self.sequence_step as usize] == 0
+        {
+            return 0;
+        }
+
+        self.envelope.volume()
+    }
+}
+
+impl Snapshot for Pulse {
+    fn save(&self, section: &mut Section) {
+        section.put_u8("duty", self.duty);
+        section.put_u8("sequence_step", self.sequence_step);
+        section.put_u16("timer_period", self.timer_period);
+        section.put_u16("timer", self.timer);
+        section.put_bool("sweep_enabled", self.sweep_enabled);
+        section.put_u8("sweep_period", self.sweep_period);
+        section.put_bool("sweep_negate", self.sweep_negate);
+        section.put_u8("sweep_shift", self.sweep_shift);
+        section.put_u8("sweep_divider", self.sweep_divider);
+        section.put_bool("sweep_reload", self.sweep_reload);
+        section.put_snapshot("envelope", &self.envelope);
+        section.put_snapshot("length", &self.length);
+    }
+
+    fn load(&mut self, section: &Section) -> Result<(), String> {
+        self.duty = section.get_u8("duty")? & 0b11;
+        self.sequence_step = section.get_u8("sequence_step")? % 8;
+        self.timer_period = section.get_u16("timer_period")?;
+        self.timer = section.get_u16("timer")?;
+        self.sweep_enabled = section.get_bool("sweep_enabled")?;
+        self.sweep_period = section.get_u8("sweep_period")?;
+        self.sweep_negate = section.get_bool("sweep_negate")?;
+        self.sweep_shift = section.get_u8("sweep_shift")?;
+        self.sweep_divider = section.get_u8("sweep_divider")?;
+        self.sweep_reload = section.get_bool("sweep_reload")?;
+        section.get_snapshot("envelope", &mut self.envelope)?;
+        section.get_snapshot("length", &mut self.length)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+use super::envelope::LengthCounter;
+use crate::savestate::{Section, Snapshot};
+
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+// https://www.nesdev.org/wiki/APU_Triangle
+pub struct Triangle {
+    control: bool,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+    sequence_step: u8,
+    timer_period: u16,
+    timer: u16,
+    pub length: LengthCounter,
+}
+
+impl Triangle {
+    pub fn new() -> Self {
+        Triangle {
+            control: false,
+            linear_reload_value: 0,
+            linear_counter: 0,
+            linear_reload: false,
+            sequence_step: 0,
+            timer_period: 0,
+            timer: 0,
+            length: LengthCounter::new(),
+        }
+    }
+
+    // CRRR RRRR
+    pub fn write_linear_counter(&mut self, value: u8) {
+        self.control = value & 0b1000_0000 != 0;
+        self.length.set_halt(self.control);
+        self.linear_reload_value = value & 0b0111_1111;
+    }
+
+    pub fn write_timer_lo(&mut self, value: u8) {
+        self.timer_period = (self.timer_period & 0xFF00) | value as u16;
+    }
+
+    // LLLL LHHH
+    pub fn write_timer_hi(&mut self, value: u8) {
+        self.timer_period = (self.timer_period & 0x00FF) | ((value as u16 & 0b111) << 8);
+        self.length.load(value);
+        self.linear_reload = true;
+    }
+
+    // Unlike other channels triangle is clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            if self.length.is_active() && self.linear_counter > 0 {
+                self.sequence_step = (self.sequence_step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    // Clocked by quarter frames
+    pub fn clock_linear_counter(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        // Ultrasonic frequencies are silenced to avoid popping
+        if self.timer_period < 2 {
+            return 7;
+        }
+
+        SEQUENCE[self.sequence_step as usize]
+    }
+}
+
+impl Snapshot for Triangle {
+    fn save(&self, section: &mut Section) {
+        section.put_bool("control", self.control);
+        section.put_u8("linear_reload_value", self.linear_reload_value);
+        section.put_u8("linear_counter", self.linear_counter);
+        section.put_bool("linear_reload", self.linear_reload);
+        section.put_u8("sequence_step", self.sequence_step);
+        section.put_u16("timer_period", self.timer_period);
+        section.put_u16("timer", self.timer);
+        section.put_snapshot("length", &self.length);
+    }
+
+    fn load(&mut self, section: &Section) -> Result<(), String> {
+        self.control = section.get_bool("control")?;
+        self.linear_reload_value = section.get_u8("linear_reload_value")?;
+        self.linear_counter = section.get_u8("linear_counter")?;
+        self.linear_reload = section.get_bool("linear_reload")?;
+        self.sequence_step = section.get_u8("sequence_step")? % 32;
+        self.timer_period = section.get_u16("timer_period")?;
+        self.timer = section.get_u16("timer")?;
+        section.get_snapshot("length", &mut self.length)?;
+        Ok(())
+    }
+}
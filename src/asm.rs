@@ -0,0 +1,539 @@
+use std::collections::HashMap;
+
+use crate::cpu::{
+    opcode::{OpCode, CPU_OPS_CODES},
+    AddressingMode,
+};
+
+// Mini assembler for tests and debugger patches
+//
+//   ; comment
+//   PPUCTRL = $2000
+//   .org $C000
+//   reset:  lda #%1000_0000     ; numbers are $hex, %binary or decimal
+//           sta PPUCTRL
+//   loop:   lda (ptr),y
+//           bne loop
+//           jmp (vector)
+//   table:  .byte 1, 2, <reset, >reset
+//           .word reset, table+2
+//
+// Operands that fit in a byte use zero page modes when the instruction has
+// them, unless they refer to a label defined further down.
+pub struct Assembly {
+    pub origin: u16,
+    pub bytes: Vec<u8>,
+    pub labels: HashMap<String, u16>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Implied,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    IndirectX,
+    IndirectY,
+    Indirect,
+    Relative,
+}
+
+impl Mode {
+    // Branches, implied and JMP indirect share NoneAddressing in the table,
+    // told apart by instruction length
+    fn matches(self, opcode: &OpCode) -> bool {
+        let mode = &opcode.addressing_mode;
+        match self {
+            Mode::Implied => *mode == AddressingMode::NoneAddressing && opcode.bytes == 1,
+            Mode::Relative => *mode == AddressingMode::NoneAddressing && opcode.bytes == 2,
+            Mode::Indirect => *mode == AddressingMode::NoneAddressing && opcode.bytes == 3,
+            Mode::Immediate => *mode == AddressingMode::Immediate,
+            Mode::ZeroPage => *mode == AddressingMode::ZeroPage,
+            Mode::ZeroPageX => *mode == AddressingMode::ZeroPage_X,
+            Mode::ZeroPageY => *mode == AddressingMode::ZeroPage_Y,
+            Mode::Absolute => *mode == AddressingMode::Absolute,
+            Mode::AbsoluteX => *mode == AddressingMode::Absolute_X,
+            Mode::AbsoluteY => *mode == AddressingMode::Absolute_Y,
+            Mode::IndirectX => *mode == AddressingMode::Indirect_X,
+            Mode::IndirectY => *mode == AddressingMode::Indirect_Y,
+        }
+    }
+}
+
+fn find_opcode(mnemonic: &str, mode: Mode) -> Option<&'static OpCode> {
+    CPU_OPS_CODES
+        .iter()
+        .find(|opcode| opcode.mnemonic == mnemonic && mode.matches(opcode))
+}
+
+enum Item {
+    Instruction {
+        opcode: &'static OpCode,
+        mode: Mode,
+        operand: Option<String>,
+    },
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+}
+
+// Line number, address and what goes there
+struct Placed {
+    line: usize,
+    addr: u16,
+    item: Item,
+}
+
+pub fn assemble(source: &str, origin: u16) -> Result<Assembly, String> {
+    let mut labels = HashMap::new();
+    let mut items = Vec::new();
+    let mut origin = origin;
+    let mut pc = origin as u32;
+
+    // First pass places everything and collects labels
+    for (number, line) in source.lines().enumerate() {
+        let line_error = |err: String| format!("Line {}: {}", number + 1, err);
+        let mut line = line.split(';').next().unwrap_or("").trim();
+
+        // name = value
+        if let Some((name, value)) = line.split_once('=') {
+            let name = name.trim();
+            if is_identifier(name) {
+                let value = evaluate(value.trim(), &labels)
+                    .map_err(line_error)?
+                    .ok_or_else(|| line_error(format!("{} uses an undefined label", name)))?;
+                define(&mut labels, name, value).map_err(line_error)?;
+                continue;
+            }
+        }
+
+        // name:
+        if let Some((name, rest)) = line.split_once(':') {
+            if is_identifier(name.trim()) {
+                if pc > 0xFFFF {
+                    return Err(line_error("Code runs past $FFFF".to_string()));
+                }
+                define(&mut labels, name.trim(), pc as i64).map_err(line_error)?;
+                line = rest.trim();
+            }
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        let (word, rest) = match line.split_once(char::is_whitespace) {
+            Some((word, rest)) => (word, rest.trim()),
+            None => (line, ""),
+        };
+
+        let (item, size) = match word.to_ascii_lowercase().as_str() {
+            ".org" => {
+                let addr = evaluate(rest, &labels)
+                    .map_err(line_error)?
+                    .ok_or_else(|| line_error(".org uses an undefined label".to_string()))?;
+                if items.is_empty() {
+                    origin = addr as u16;
+                } else if (addr as u32) < pc {
+                    return Err(line_error(".org can't move backwards".to_string()));
+                }
+                pc = addr as u32;
+                continue;
+            }
+            ".byte" | ".db" => {
+                let values = split_list(rest);
+                let size = values.len() as u32;
+                (Item::Bytes(values), size)
+            }
+            ".word" | ".dw" => {
+                let values = split_list(rest);
+                let size = values.len() as u32 * 2;
+                (Item::Words(values), size)
+            }
+            mnemonic => {
+                let mnemonic = mnemonic.to_ascii_uppercase();
+                let (opcode, mode, operand) =
+                    parse_instruction(&mnemonic, rest, &labels).map_err(line_error)?;
+                let size = opcode.bytes as u32;
+                (
+                    Item::Instruction {
+                        opcode,
+                        mode,
+                        operand,
+                    },
+                    size,
+                )
+            }
+        };
+
+        if pc + size > 0x10000 {
+            return Err(line_error("Code runs past $FFFF".to_string()));
+        }
+        items.push(Placed {
+            line: number + 1,
+            addr: pc as u16,
+            item,
+        });
+        pc += size;
+    }
+
+    // Second pass emits bytes with every label known
+    let mut bytes = vec![0; (pc - origin as u32) as usize];
+    for placed in &items {
+        let line_error = |err: String| format!("Line {}: {}", placed.line, err);
+        let value = |text: &str| {
+            evaluate(text, &labels)
+                .map_err(line_error)?
+                .ok_or_else(|| line_error(format!("Undefined label in \"{}\"", text)))
+        };
+
+        let mut out = Vec::new();
+        match &placed.item {
+            Item::Instruction {
+                opcode,
+                mode,
+                operand,
+            } => {
+                out.push(opcode.code);
+                if let Some(operand) = operand {
+                    let operand = value(operand)?;
+                    match (mode, opcode.bytes) {
+                        (Mode::Relative, _) => {
+                            let offset = operand - (placed.addr as i64 + 2);
+                            if !(-128..=127).contains(&offset) {
+                                return Err(line_error(format!(
+                                    "Branch target is {} bytes away",
+                                    offset
+                                )));
+                            }
+                            out.push(offset as u8);
+                        }
+                        (_, 2) => out.push(to_byte(operand).map_err(line_error)?),
+                        _ => out.extend_from_slice(&to_word(operand).map_err(line_error)?),
+                    }
+                }
+            }
+            Item::Bytes(values) => {
+                for text in values {
+                    out.push(to_byte(value(text)?).map_err(line_error)?);
+                }
+            }
+            Item::Words(values) => {
+                for text in values {
+                    out.extend_from_slice(&to_word(value(text)?).map_err(line_error)?);
+                }
+            }
+        }
+
+        let offset = (placed.addr - origin) as usize;
+        bytes[offset..offset + out.len()].copy_from_slice(&out);
+    }
+
+    let labels = labels
+        .into_iter()
+        .map(|(name, value)| (name, value as u16))
+        .collect();
+    Ok(Assembly {
+        origin,
+        bytes,
+        labels,
+    })
+}
+
+// Picks the addressing mode from operand syntax
+fn parse_instruction(
+    mnemonic: &str,
+    operand: &str,
+    labels: &HashMap<String, i64>,
+) -> Result<(&'static OpCode, Mode, Option<String>), String> {
+    let find = |mode: Mode| find_opcode(mnemonic, mode);
+    let unsupported = || format!("{} has no such addressing mode", mnemonic);
+    if !CPU_OPS_CODES
+        .iter()
+        .any(|opcode| opcode.mnemonic == mnemonic)
+    {
+        return Err(format!("Unknown instruction \"{}\"", mnemonic));
+    }
+
+    if operand.is_empty() || operand.eq_ignore_ascii_case("a") {
+        let opcode = find(Mode::Implied).ok_or_else(unsupported)?;
+        return Ok((opcode, Mode::Implied, None));
+    }
+
+    if let Some(value) = operand.strip_prefix('#') {
+        let opcode = find(Mode::Immediate).ok_or_else(unsupported)?;
+        return Ok((opcode, Mode::Immediate, Some(value.trim().to_string())));
+    }
+
+    let operand: String = operand.chars().filter(|c| !c.is_whitespace()).collect();
+    let upper = operand.to_ascii_uppercase();
+    let (mode, value) = if operand.starts_with('(') {
+        if upper.ends_with(",X)") {
+            (Mode::IndirectX, &operand[1..operand.len() - 3])
+        } else if upper.ends_with("),Y") {
+            (Mode::IndirectY, &operand[1..operand.len() - 3])
+        } else if upper.ends_with(')') {
+            (Mode::Indirect, &operand[1..operand.len() - 1])
+        } else {
+            return Err(format!("Can't parse operand \"{}\"", operand));
+        }
+    } else if upper.ends_with(",X") {
+        (Mode::AbsoluteX, &operand[..operand.len() - 2])
+    } else if upper.ends_with(",Y") {
+        (Mode::AbsoluteY, &operand[..operand.len() - 2])
+    } else if find(Mode::Relative).is_some() {
+        (Mode::Relative, &operand[..])
+    } else {
+        (Mode::Absolute, &operand[..])
+    };
+    let value = value.to_string();
+
+    // Zero page when the value is already known to fit
+    let zero_page = match mode {
+        Mode::Absolute => Some(Mode::ZeroPage),
+        Mode::AbsoluteX => Some(Mode::ZeroPageX),
+        Mode::AbsoluteY => Some(Mode::ZeroPageY),
+        _ => None,
+    };
+    if let Some(zero_page) = zero_page {
+        let fits = matches!(evaluate(&value, labels), Ok(Some(0..=0xFF)));
+        if let (true, Some(opcode)) = (fits, find(zero_page)) {
+            return Ok((opcode, zero_page, Some(value)));
+        }
+    }
+
+    let opcode = find(mode).ok_or_else(unsupported)?;
+    Ok((opcode, mode, Some(value)))
+}
+
+fn define(labels: &mut HashMap<String, i64>, name: &str, value: i64) -> Result<(), String> {
+    if labels.insert(name.to_string(), value).is_some() {
+        return Err(format!("Label {} is defined twice", name));
+    }
+    Ok(())
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn split_list(text: &str) -> Vec<String> {
+    text.split(',')
+        .map(|value| value.trim().to_string())
+        .collect()
+}
+
+// Numbers and labels joined by + and -, optionally prefixed by < or > for
+// the low or high byte. None if a label is not defined yet
+fn evaluate(text: &str, labels: &HashMap<String, i64>) -> Result<Option<i64>, String> {
+    let (text, part) = match text.chars().next() {
+        Some('<') => (&text[1..], Some(false)),
+        Some('>') => (&text[1..], Some(true)),
+        _ => (text, None),
+    };
+
+    let mut total = 0;
+    let mut defined = true;
+    let mut sign = 1;
+    let mut rest = text.trim();
+    if rest.is_empty() {
+        return Err("Operand is missing".to_string());
+    }
+    if let Some(negated) = rest.strip_prefix('-') {
+        sign = -1;
+        rest = negated.trim_start();
+    }
+    loop {
+        let end = rest.find(['+', '-']).unwrap_or(rest.len());
+        let term = rest[..end].trim();
+        let value = match term.chars().next() {
+            Some('$') => i64::from_str_radix(&term[1..], 16).ok(),
+            Some('%') => i64::from_str_radix(&term[1..].replace('_', ""), 2).ok(),
+            Some(c) if c.is_ascii_digit() => term.parse().ok(),
+            _ if is_identifier(term) => match labels.get(term) {
+                Some(value) => Some(*value),
+                None => {
+                    defined = false;
+                    Some(0)
+                }
+            },
+            _ => None,
+        };
+        total += sign * value.ok_or_else(|| format!("Can't parse \"{}\"", term))?;
+
+        if end == rest.len() {
+            break;
+        }
+        sign = if rest.as_bytes()[end] == b'+' { 1 } else { -1 };
+        rest = &rest[end + 1..];
+    }
+
+    if !defined {
+        return Ok(None);
+    }
+    Ok(Some(match part {
+        Some(false) => total & 0xFF,
+        Some(true) => (total >> 8) & 0xFF,
+        None => total,
+    }))
+}
+
+fn to_byte(value: i64) -> Result<u8, String> {
+    match value {
+        -128..=255 => Ok(value as u8),
+        _ => Err(format!("{} doesn't fit in a byte", value)),
+    }
+}
+
+fn to_word(value: i64) -> Result<[u8; 2], String> {
+    match value {
+        -32768..=65535 => Ok((value as u16).to_le_bytes()),
+        _ => Err(format!("{} doesn't fit in a word", value)),
+    }
+}
+
+// iNES image for tests, 16 KiB of PRG at $C000 and CHR RAM. The vectors
+// point at the labels nmi, reset and irq, or $C000 without them
+#[cfg(test)]
+pub(crate) fn test_rom(source: &str) -> Vec<u8> {
+    let assembly = assemble(source, 0xC000).unwrap_or_else(|err| panic!("{}", err));
+    let mut prg = assembly.bytes;
+    prg.resize(0x4000, 0);
+    for (i, label) in ["nmi", "reset", "irq"].iter().enumerate() {
+        let addr = assembly.labels.get(*label).copied().unwrap_or(0xC000);
+        prg[0x3FFA + i * 2..0x3FFC + i * 2].copy_from_slice(&addr.to_le_bytes());
+    }
+
+    let mut rom = vec![b'N', b'E', b'S', 0x1A, 1, 0, 0x01, 0];
+    rom.resize(16, 0);
+    rom.extend(prg);
+    rom
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm;
+
+    fn bytes(source: &str) -> Vec<u8> {
+        assemble(source, 0xC000).unwrap().bytes
+    }
+
+    fn error(source: &str) -> String {
+        assemble(source, 0xC000).err().unwrap()
+    }
+
+    // Disassembling any opcode and assembling the text gives the bytes back
+    #[test]
+    fn round_trips_every_opcode() {
+        for opcode in CPU_OPS_CODES {
+            let original = &[opcode.code, 0x12, 0x34][..opcode.bytes as usize];
+            let text = disasm::disassemble_bytes(original, 0xC000)[0].text(None);
+            assert_eq!(bytes(&text), original, "{}", text);
+        }
+    }
+
+    #[test]
+    fn addressing_modes() {
+        assert_eq!(bytes("nop"), [0xEA]);
+        assert_eq!(bytes("asl a"), [0x0A]);
+        assert_eq!(bytes("asl"), [0x0A]);
+        assert_eq!(bytes("lda #$10"), [0xA9, 0x10]);
+        assert_eq!(bytes("lda $10"), [0xA5, 0x10]);
+        assert_eq!(bytes("lda $10,x"), [0xB5, 0x10]);
+        assert_eq!(bytes("ldx $10,y"), [0xB6, 0x10]);
+        assert_eq!(bytes("lda $1234"), [0xAD, 0x34, 0x12]);
+        assert_eq!(bytes("lda $1234,X"), [0xBD, 0x34, 0x12]);
+        assert_eq!(bytes("lda $1234, y"), [0xB9, 0x34, 0x12]);
+        assert_eq!(bytes("lda ($10,x)"), [0xA1, 0x10]);
+        assert_eq!(bytes("lda ($10),y"), [0xB1, 0x10]);
+        assert_eq!(bytes("jmp ($1234)"), [0x6C, 0x34, 0x12]);
+        assert_eq!(bytes("bne $C000"), [0xD0, 0xFE]);
+        // No zero page form, so absolute even for a small address
+        assert_eq!(bytes("lda $10,y"), [0xB9, 0x10, 0x00]);
+    }
+
+    #[test]
+    fn numbers_labels_and_directives() {
+        let assembly = assemble(
+            "PPUCTRL = $2000
+             .org $8000
+             reset: lda #%1000_0000 ; comment
+                    sta PPUCTRL
+             loop:  bne loop
+                    jmp later
+             table: .byte 1, -1, <reset, >reset
+                    .word reset, table+2
+             later: .db 255",
+            0,
+        )
+        .unwrap();
+        assert_eq!(assembly.origin, 0x8000);
+        assert_eq!(assembly.labels["loop"], 0x8005);
+        assert_eq!(assembly.labels["later"], 0x8012);
+        assert_eq!(
+            assembly.bytes,
+            [
+                0xA9, 0x80, 0x8D, 0x00, 0x20, 0xD0, 0xFE, 0x4C, 0x12, 0x80, 0x01, 0xFF, 0x00, 0x80,
+                0x00, 0x80, 0x0C, 0x80, 0xFF
+            ]
+        );
+    }
+
+    // A label defined further down is taken as absolute
+    #[test]
+    fn forward_references_are_absolute() {
+        assert_eq!(bytes("lda zp\nzp = $10"), [0xAD, 0x10, 0x00]);
+        assert_eq!(bytes("zp = $10\nlda zp"), [0xA5, 0x10]);
+    }
+
+    #[test]
+    fn org_fills_the_gap() {
+        assert_eq!(bytes("nop\n.org $C003\nnop"), [0xEA, 0, 0, 0xEA]);
+        assert!(error("nop\nnop\n.org $C001").contains("backwards"));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error("nop\nfoo $10"), "Line 2: Unknown instruction \"FOO\"");
+        assert_eq!(
+            error("lda ($10)"),
+            "Line 1: LDA has no such addressing mode"
+        );
+        assert_eq!(
+            error("stx $1234,x"),
+            "Line 1: STX has no such addressing mode"
+        );
+        assert_eq!(error("inx #1"), "Line 1: INX has no such addressing mode");
+        assert_eq!(error("lda ($10"), "Line 1: Can't parse operand \"($10\"");
+        assert_eq!(error("lda #$1G"), "Line 1: Can't parse \"$1G\"");
+        assert_eq!(error("lda #"), "Line 1: Operand is missing");
+        assert_eq!(error("lda #$100"), "Line 1: 256 doesn't fit in a byte");
+        assert_eq!(error("jmp $10000"), "Line 1: 65536 doesn't fit in a word");
+        assert_eq!(
+            error("jmp nowhere"),
+            "Line 1: Undefined label in \"nowhere\""
+        );
+        assert_eq!(error("a:\na: nop"), "Line 2: Label a is defined twice");
+        assert_eq!(
+            error(".org $FFFF\nnop\nnop"),
+            "Line 3: Code runs past $FFFF"
+        );
+    }
+
+    #[test]
+    fn branches_out_of_range() {
+        let far = format!("loop: {}\nbne loop", "nop\n".repeat(127));
+        assert_eq!(error(&far), "Line 129: Branch target is -129 bytes away");
+        let near = format!("loop: {}\nbne loop", "nop\n".repeat(126));
+        assert_eq!(bytes(&near)[126..], [0xD0, 0x80]);
+        assert_eq!(
+            error("bne target\n.org $C082\ntarget: nop"),
+            "Line 1: Branch target is 128 bytes away"
+        );
+    }
+}
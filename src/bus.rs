@@ -1,58 +1,232 @@
-use crate::{ppu::Ppu, rom::Rom};
+use crate::{
+    apu::Apu,
+    cdl::{self, CodeDataLog},
+    cheat::Cheats,
+    cpu::{CpuBus, ReadKind},
+    joypad::Joypad,
+    mapper::{self, Mapper},
+    poweron::RamFill,
+    ppu::Ppu,
+    rom::Rom,
+    savestate::{Section, Snapshot},
+    watch::{WatchHit, Watchpoints},
+};
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BusAccess {
+    pub kind: Access,
+    pub addr: u16,
+    pub value: u8,
+}
+
+// CPU address space. Every CPU access takes one cycle of the shared system clock,
+// which steps the PPU, the APU and the cartridge along with it
 pub struct Bus {
     cpu_wram: [u8; 2048],
     open_bus: u8,
     ppu_open_bus: u8,
-    prg_rom: Vec<u8>,
+    mapper: Box<dyn Mapper>,
     ppu: Ppu,
+    apu: Apu,
+    joypad1: Joypad,
+    joypad2: Joypad,
+    cycles: u64,
+    frame_complete: bool,
+    // Catch-up mode leaves the PPU behind and only runs it up to the CPU
+    // when the CPU could tell: on PPU accesses and cartridge writes, and
+    // before the PPU moves the NMI line or ends a frame by itself. Frames
+    // come out the same for a lot less work
+    ppu_catch_up: bool,
+    ppu_dots_behind: u32,
+    ppu_dots_until_event: u32,
+    // CPU accesses recorded for debugging tools, None when nobody listens
+    access_log: Option<Vec<BusAccess>>,
+    watchpoints: Watchpoints,
+    cheats: Cheats,
+    // Code/data logger, with the instruction being executed so its operand
+    // fetches count as code and watchpoints can tell who accessed
+    cdl: Option<CodeDataLog>,
+    instruction_addr: u16,
+    indirect_access: bool,
 }
 
 impl Bus {
-    pub fn new(rom: Rom) -> Self {
-        Bus {
+    pub fn new(rom: Rom) -> Result<Self, String> {
+        Ok(Bus {
             cpu_wram: [0; 2048],
             open_bus: 0,
             ppu_open_bus: 0,
-            prg_rom: rom.prg_rom,
-            ppu: Ppu::new(rom.chr_rom, rom.screen_mirroring),
+            mapper: mapper::new_mapper(rom)?,
+            ppu: Ppu::new(),
+            apu: Apu::new(),
+            joypad1: Joypad::new(),
+            joypad2: Joypad::new(),
+            cycles: 0,
+            frame_complete: false,
+            ppu_catch_up: false,
+            ppu_dots_behind: 0,
+            ppu_dots_until_event: 0,
+            access_log: None,
+            watchpoints: Watchpoints::new(),
+            cheats: Cheats::new(),
+            cdl: None,
+            instruction_addr: 0,
+            indirect_access: false,
+        })
+    }
+
+    // Console RAM, PPU memories and cartridge RAM, in that order
+    pub fn fill_ram(&mut self, fill: &mut RamFill) {
+        fill.fill(&mut self.cpu_wram);
+        self.ppu.fill_ram(fill);
+        self.mapper.fill_ram(fill);
+    }
+
+    fn read_prg_rom(&mut self, addr: u16) -> u8 {
+        let value = self.mapper.cpu_read(addr).unwrap_or(self.open_bus);
+        if self.cheats.patches_rom() {
+            self.cheats.patch_rom_read(addr, value)
+        } else {
+            value
         }
     }
 
-    fn read_prg_rom(&self, mut addr: u16) -> u8 {
-        addr -= 0x8000;
-        if self.prg_rom.len() == 0x4000 && addr >= 0x4000 {
-            addr %= 0x4000;
+    pub fn set_access_log(&mut self, enabled: bool) {
+        self.access_log = if enabled { Some(Vec::new()) } else { None };
+    }
+    pub fn take_access_log(&mut self) -> Vec<BusAccess> {
+        self.access_log
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    pub fn cheats(&self) -> &Cheats {
+        &self.cheats
+    }
+    pub fn cheats_mut(&mut self) -> &mut Cheats {
+        &mut self.cheats
+    }
+
+    // Writes straight to RAM, no CPU cycle is spent
+    fn apply_freezes(&mut self) {
+        for i in 0..self.cheats.freezes().len() {
+            let freeze = self.cheats.freezes()[i];
+            let current = match freeze.addr {
+                0x0000..=0x1FFF => Some(self.cpu_wram[(freeze.addr & 0x07FF) as usize]),
+                _ => self.mapper.cpu_peek(freeze.addr),
+            };
+            if freeze.compare.is_some() && freeze.compare != current {
+                continue;
+            }
+
+            match freeze.addr {
+                0x0000..=0x1FFF => self.cpu_wram[(freeze.addr & 0x07FF) as usize] = freeze.value,
+                _ => self.mapper.cpu_write(freeze.addr, freeze.value),
+            }
         }
+    }
+
+    pub fn watchpoints(&self) -> &Watchpoints {
+        &self.watchpoints
+    }
+    pub fn watchpoints_mut(&mut self) -> &mut Watchpoints {
+        &mut self.watchpoints
+    }
+
+    fn check_watchpoints(&mut self, kind: Access, addr: u16, value: u8) {
+        self.sync_ppu();
+        self.watchpoints.check(WatchHit {
+            kind,
+            addr,
+            value,
+            pc: self.instruction_addr,
+            cycle: self.cycles,
+            scanline: self.ppu.scanline(),
+            dot: self.ppu.dot(),
+        });
+    }
 
-        self.prg_rom[addr as usize]
+    pub fn set_code_data_log(&mut self, log: Option<CodeDataLog>) {
+        self.ppu.set_chr_fetch_log(log.is_some());
+        self.cdl = log;
+    }
+    pub fn take_code_data_log(&mut self) -> Option<CodeDataLog> {
+        self.ppu.set_chr_fetch_log(false);
+        self.cdl.take()
+    }
+    pub fn code_data_log(&self) -> Option<&CodeDataLog> {
+        self.cdl.as_ref()
+    }
+
+    fn log_prg(&mut self, addr: u16, flags: u8) {
+        if let (Some(log), Some(offset)) = (&mut self.cdl, self.mapper.prg_rom_offset(addr)) {
+            log.log_prg(offset, addr, flags);
+        }
+    }
+
+    fn log_chr_fetches(&mut self) {
+        let (Some(log), Some(fetches)) = (&mut self.cdl, self.ppu.chr_fetches_mut()) else {
+            return;
+        };
+        for (addr, flags) in fetches.drain(..) {
+            if let Some(offset) = self.mapper.chr_rom_offset(addr) {
+                log.log_chr(offset, flags);
+            }
+        }
     }
 
-    // https://www.youtube.com/watch?v=fWqBmmPQP40&t=41m44s
-    // TODO: remove debug code
-    pub fn write_initial_pc_addr(&mut self, addr: u16) {
-        self.mem_write_u16(0xFFFC, addr);
+    pub fn set_ppu_catch_up(&mut self, enabled: bool) {
+        self.sync_ppu();
+        self.ppu_catch_up = enabled;
+        self.ppu_dots_until_event = 0;
     }
-    pub fn read_initial_pc_addr(&mut self) -> u16 {
-        self.mem_read_u16(0xFFFC)
+    pub fn ppu_catch_up(&self) -> bool {
+        self.ppu_catch_up
     }
 
-    pub fn mem_read_u16(&mut self, addr: u16) -> u16 {
-        let lo = self.mem_read(addr);
-        let hi = self.mem_read(addr + 1);
+    // Runs the PPU up to the CPU
+    pub fn sync_ppu(&mut self) {
+        if self.ppu_dots_behind == 0 {
+            return;
+        }
+        for _ in 0..std::mem::take(&mut self.ppu_dots_behind) {
+            self.tick_ppu();
+        }
+        if self.cdl.is_some() {
+            self.log_chr_fetches();
+        }
 
-        u16::from_le_bytes([lo, hi])
+        self.ppu_dots_until_event = self.ppu.dots_until_event();
+        if !self.cheats.freezes().is_empty() {
+            let freeze = self.ppu.dots_until(self.cheats.freeze_scanline(), 0);
+            self.ppu_dots_until_event = self.ppu_dots_until_event.min(freeze);
+        }
     }
-    pub fn mem_write_u16(&mut self, addr: u16, value: u16) {
-        let lo = (value & 0xFF) as u8;
-        let hi = (value >> 8) as u8;
 
-        self.mem_write(addr, lo);
-        self.mem_write(addr + 1, hi);
+    fn tick_ppu(&mut self) {
+        if self.ppu.tick(&mut *self.mapper) {
+            self.frame_complete = true;
+        }
+        if !self.cheats.freezes().is_empty()
+            && self.ppu.dot() == 0
+            && self.ppu.scanline() == self.cheats.freeze_scanline()
+        {
+            self.apply_freezes();
+        }
     }
 
     // XXX Maybe I misunderstood open bus behavior
-    pub fn mem_read(&mut self, addr: u16) -> u8 {
+    fn read(&mut self, addr: u16) -> u8 {
+        if let 0x2000..=0x3FFF = addr {
+            self.sync_ppu();
+        }
         match addr {
             // RAM to it's mirrors end
             0x0000..=0x1FFF => {
@@ -72,21 +246,21 @@ impl Bus {
 
                 // Reading the PPU's status port loads bits 7-5 only
                 self.ppu_open_bus &= 0b0001_1111;
-                self.ppu_open_bus |= status;
-                self.open_bus = status;
+                self.ppu_open_bus |= status & 0b1110_0000;
+                self.open_bus = self.ppu_open_bus;
 
-                status
+                self.ppu_open_bus
             }
             // OAM data
             0x2004 => {
-                let value = self.ppu.read_from_oam_data(addr);
+                let value = self.ppu.read_from_oam_data();
                 self.ppu_open_bus = value;
                 self.open_bus = value;
                 value
             }
             // Data
             0x2007 => {
-                let value = self.ppu.read();
+                let value = self.ppu.read(&mut *self.mapper);
                 self.ppu_open_bus = value;
                 self.open_bus = value;
                 value
@@ -95,10 +269,30 @@ impl Bus {
             0x2008..=0x3FFF => {
                 let mirrored_down_addr = addr & 0b0010_0000_0000_0111;
                 // Open bus will be modified after mirrored read
-                self.mem_read(mirrored_down_addr)
+                self.read(mirrored_down_addr)
+            }
+            // APU status, bit 5 is open bus
+            0x4015 => {
+                let value = self.apu.read_status() | (self.open_bus & 0b0010_0000);
+                self.open_bus = value;
+                value
             }
-            0x4016..=0x4017 => {
-                todo!("Emulate joypads and open bus behavior (affects bits 4-0 only) here")
+            // Joypads affect bits 4-0 only
+            0x4016 => {
+                let value = self.joypad1.read() | (self.open_bus & 0b1110_0000);
+                self.open_bus = value;
+                value
+            }
+            0x4017 => {
+                let value = self.joypad2.read() | (self.open_bus & 0b1110_0000);
+                self.open_bus = value;
+                value
+            }
+            // Cartridge space
+            0x4020..=0x7FFF => {
+                let value = self.mapper.cpu_read(addr).unwrap_or(self.open_bus);
+                self.open_bus = value;
+                value
             }
             // ROM PRG
             0x8000..=0xFFFF => {
@@ -110,7 +304,32 @@ impl Bus {
         }
     }
 
-    pub fn mem_write(&mut self, addr: u16, value: u8) {
+    // Cartridge has RAM at $6000-$7FFF and it's enabled
+    pub fn has_prg_ram(&self) -> bool {
+        self.mapper.cpu_peek(0x6000).is_some()
+    }
+
+    // Write to RAM or PRG RAM without advancing the clock or touching any
+    // register, for debugging tools. False if there is no RAM there to write
+    pub fn poke(&mut self, addr: u16, value: u8) -> bool {
+        match addr {
+            0x0000..=0x1FFF => {
+                self.cpu_wram[(addr & 0b0000_0111_1111_1111) as usize] = value;
+                true
+            }
+            // Mappers only have RAM here, which may be disabled or protected
+            0x6000..=0x7FFF => {
+                self.mapper.cpu_write(addr, value);
+                self.mapper.cpu_peek(addr) == Some(value)
+            }
+            _ => false,
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        if let 0x2000..=0x3FFF | 0x4014 | 0x4020..=0xFFFF = addr {
+            self.sync_ppu();
+        }
         match addr {
             // RAM to it's mirrors end
             0x0000..=0x1FFF => {
@@ -130,6 +349,11 @@ impl Bus {
             }
             // Status (read-only)
             0x2002 => self.ppu_open_bus = value,
+            // OAM address
+            0x2003 => {
+                self.ppu_open_bus = value;
+                self.ppu.write_to_oam_addr(value);
+            }
             // OAM data
             0x2004 => {
                 self.ppu_open_bus = value;
@@ -148,27 +372,219 @@ impl Bus {
             // Data
             0x2007 => {
                 self.ppu_open_bus = value;
-                self.ppu.write(value);
+                self.ppu.write(&mut *self.mapper, value);
             }
             // Mirrors of PPU's registers
             0x2008..=0x3FFF => {
                 let mirrored_down_addr = addr & 0b0010_0000_0000_0111;
-                self.mem_write(mirrored_down_addr, value);
+                self.write(mirrored_down_addr, value);
             }
             // OAM DMA
-            0x4014 => {
-                self.ppu_open_bus = value;
-                // Copy all from XX00 to XXFF to the PPU's OAM
-                let page = &self.cpu_wram[((value as usize) << 8)..((value as usize) << 8 + 256)];
-                self.ppu.write_oam_dma(page);
+            0x4014 => self.oam_dma(value),
+            // Joypads strobe
+            0x4016 => {
+                self.joypad1.write(value);
+                self.joypad2.write(value);
             }
-            // ROM PRG
+            // APU
+            0x4000..=0x4013 | 0x4015 | 0x4017 => self.apu.write_register(addr, value),
+            // APU and I/O functionality that is normally disabled
+            0x4018..=0x401F => (),
+            // Cartridge space
+            0x4020..=0xFFFF => self.mapper.cpu_write(addr, value),
+        }
+    }
+
+    // Copy all from XX00 to XXFF to the PPU's OAM, CPU is suspended meanwhile
+    fn oam_dma(&mut self, page: u8) {
+        // Halt cycle plus alignment cycle if DMA started on odd cycle
+        self.tick();
+        if self.cycles % 2 == 1 {
+            self.tick();
+        }
+
+        let base = (page as u16) << 8;
+        for i in 0..256 {
+            let value = self.mem_read(base + i, ReadKind::Data);
+            self.tick();
+            self.sync_ppu();
+            self.ppu.write_to_oam_data(value);
+            if !self.watchpoints.is_empty() {
+                self.check_watchpoints(Access::Write, 0x2004, value);
+            }
+        }
+    }
+
+    // Returns true once per completed frame
+    pub fn poll_frame_complete(&mut self) -> bool {
+        std::mem::take(&mut self.frame_complete)
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+    pub fn joypad1_mut(&mut self) -> &mut Joypad {
+        &mut self.joypad1
+    }
+    pub fn joypad2_mut(&mut self) -> &mut Joypad {
+        &mut self.joypad2
+    }
+}
+
+impl CpuBus for Bus {
+    // One CPU read cycle
+    fn mem_read(&mut self, addr: u16, kind: ReadKind) -> u8 {
+        self.tick();
+        let value = self.read(addr);
+        // Dummy reads are neither code nor data
+        if self.cdl.is_some() && kind != ReadKind::Dummy {
+            let flags = match kind {
+                ReadKind::Opcode | ReadKind::Operand => cdl::CODE,
+                _ if self.indirect_access => cdl::DATA | cdl::INDIRECT_DATA,
+                _ => cdl::DATA,
+            };
+            self.log_prg(addr, flags);
+        }
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(Access::Read, addr, value);
+        }
+        if let Some(log) = &mut self.access_log {
+            log.push(BusAccess {
+                kind: Access::Read,
+                addr,
+                value,
+            });
+        }
+        value
+    }
+
+    // One CPU write cycle
+    fn mem_write(&mut self, addr: u16, value: u8) {
+        self.tick();
+        self.write(addr, value);
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(Access::Write, addr, value);
+        }
+        if let Some(log) = &mut self.access_log {
+            log.push(BusAccess {
+                kind: Access::Write,
+                addr,
+                value,
+            });
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => self.cpu_wram[(addr & 0b0000_0111_1111_1111) as usize],
+            0x2002 => (self.ppu.peek_status() & 0b1110_0000) | (self.ppu_open_bus & 0b0001_1111),
+            0x2004 => self.ppu.read_from_oam_data(),
+            0x2000..=0x2007 => self.ppu_open_bus,
+            0x2008..=0x3FFF => self.peek(addr & 0b0010_0000_0000_0111),
+            0x4020..=0x7FFF => self.mapper.cpu_peek(addr).unwrap_or(self.open_bus),
             0x8000..=0xFFFF => {
-                panic!("Attempt to write to Cartridge ROM space ({:x})", addr)
+                let value = self.mapper.cpu_peek(addr).unwrap_or(self.open_bus);
+                self.cheats.patch_rom_read(addr, value)
             }
-            _ => {
-                panic!("Attempt to write to {:x}", addr)
+            _ => self.open_bus,
+        }
+    }
+
+    // Advances the whole system by one CPU cycle
+    fn tick(&mut self) {
+        self.cycles += 1;
+
+        // PPU runs 3 dots per CPU cycle on NTSC
+        if self.ppu_catch_up {
+            self.ppu_dots_behind += 3;
+            if self.ppu_dots_behind >= self.ppu_dots_until_event || self.mapper.irq_follows_ppu() {
+                self.sync_ppu();
+            }
+        } else {
+            for _ in 0..3 {
+                self.tick_ppu();
             }
+            if self.cdl.is_some() {
+                self.log_chr_fetches();
+            }
+        }
+        self.apu.tick();
+        self.mapper.tick();
+
+        if let Some(addr) = self.apu.dmc_pending_fetch() {
+            let value = self.read(addr);
+            if self.cdl.is_some() {
+                self.log_prg(addr, cdl::PCM_DATA);
+            }
+            self.apu.dmc_fill_sample_buffer(value);
+
+            // CPU is stalled while the sample is fetched
+            for _ in 0..3 {
+                self.tick();
+            }
+        }
+    }
+
+    fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    fn nmi_line(&self) -> bool {
+        self.ppu.nmi_line()
+    }
+
+    fn irq_line(&self) -> bool {
+        self.apu.irq() || self.mapper.irq()
+    }
+
+    fn begin_instruction(&mut self, addr: u16) {
+        self.instruction_addr = addr;
+        self.indirect_access = false;
+    }
+
+    fn mark_indirect_access(&mut self) {
+        self.indirect_access = true;
+    }
+
+    fn log_indirect_code(&mut self, addr: u16) {
+        if self.cdl.is_some() {
+            self.log_prg(addr, cdl::INDIRECT_CODE);
         }
     }
 }
+
+impl Snapshot for Bus {
+    // A PPU left behind would be saved in the past, callers sync it first
+    fn save(&self, section: &mut Section) {
+        debug_assert_eq!(self.ppu_dots_behind, 0, "PPU not synced before saving");
+        section.put_bytes("wram", &self.cpu_wram);
+        section.put_u8("open_bus", self.open_bus);
+        section.put_u8("ppu_open_bus", self.ppu_open_bus);
+        section.put_u64("cycles", self.cycles);
+        section.put_bool("frame_complete", self.frame_complete);
+        section.put_snapshot("ppu", &self.ppu);
+        section.put_snapshot("apu", &self.apu);
+        section.put_snapshot("mapper", &*self.mapper);
+        section.put_snapshot("joypad1", &self.joypad1);
+        section.put_snapshot("joypad2", &self.joypad2);
+    }
+
+    fn load(&mut self, section: &Section) -> Result<(), String> {
+        section.get_bytes_into("wram", &mut self.cpu_wram)?;
+        self.open_bus = section.get_u8("open_bus")?;
+        self.ppu_open_bus = section.get_u8("ppu_open_bus")?;
+        self.cycles = section.get_u64("cycles")?;
+        self.frame_complete = section.get_bool("frame_complete")?;
+        section.get_snapshot("ppu", &mut self.ppu)?;
+        section.get_snapshot("apu", &mut self.apu)?;
+        section.get_snapshot("mapper", &mut *self.mapper)?;
+        section.get_snapshot("joypad1", &mut self.joypad1)?;
+        section.get_snapshot("joypad2", &mut self.joypad2)?;
+        self.ppu_dots_behind = 0;
+        self.ppu_dots_until_event = 0;
+        Ok(())
+    }
+}
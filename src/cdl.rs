@@ -0,0 +1,123 @@
+use std::{fs, path::Path};
+
+// FCEUX code/data log: one flag byte per PRG ROM byte followed by one per
+// CHR ROM byte, same order as in the .nes file
+// https://fceux.com/web/help/CodeDataLogger.html
+
+// PRG: xPdcAADC
+pub const CODE: u8 = 0b0000_0001;
+pub const DATA: u8 = 0b0000_0010;
+// AA is the CPU 8Kb window the byte was last accessed through
+const BANK_SHIFT: u8 = 2;
+const BANK_MASK: u8 = 0b0000_1100;
+pub const INDIRECT_CODE: u8 = 0b0001_0000;
+pub const INDIRECT_DATA: u8 = 0b0010_0000;
+pub const PCM_DATA: u8 = 0b0100_0000;
+
+// CHR: xxxxxxRD
+pub const RENDERED: u8 = 0b0000_0001;
+pub const CHR_READ: u8 = 0b0000_0010;
+
+pub struct CodeDataLog {
+    pub prg: Vec<u8>,
+    pub chr: Vec<u8>,
+}
+
+impl CodeDataLog {
+    pub fn new(prg_size: usize, chr_size: usize) -> Self {
+        CodeDataLog {
+            prg: vec![0; prg_size],
+            chr: vec![0; chr_size],
+        }
+    }
+
+    // An existing log is extended rather than started over
+    pub fn load(path: &Path, prg_size: usize, chr_size: usize) -> Result<Self, String> {
+        let data =
+            fs::read(path).map_err(|err| format!("Can't read {}: {}", path.display(), err))?;
+        if data.len() != prg_size + chr_size {
+            return Err(format!(
+                "{} is {} bytes, expected {} for this ROM",
+                path.display(),
+                data.len(),
+                prg_size + chr_size
+            ));
+        }
+
+        let (prg, chr) = data.split_at(prg_size);
+        Ok(CodeDataLog {
+            prg: prg.to_vec(),
+            chr: chr.to_vec(),
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let mut data = self.prg.clone();
+        data.extend_from_slice(&self.chr);
+        fs::write(path, data).map_err(|err| format!("Can't write {}: {}", path.display(), err))
+    }
+
+    pub fn log_prg(&mut self, offset: usize, addr: u16, flags: u8) {
+        let bank = ((addr >> 13) as u8 & 0b11) << BANK_SHIFT;
+        let entry = &mut self.prg[offset];
+        *entry = (*entry & !BANK_MASK) | bank | flags;
+    }
+
+    pub fn log_chr(&mut self, offset: usize, flags: u8) {
+        self.chr[offset] |= flags;
+    }
+
+    // Bytes of PRG seen as code, as data and CHR bytes seen at all
+    pub fn coverage(&self) -> (usize, usize, usize) {
+        let count = |log: &[u8], flags: u8| log.iter().filter(|&&entry| entry & flags != 0).count();
+        (
+            count(&self.prg, CODE),
+            count(&self.prg, DATA | PCM_DATA),
+            count(&self.chr, RENDERED | CHR_READ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm, Nes};
+
+    // NROM with 16 KiB PRG at $C000, mirrored at $8000
+    fn log_one_frame(source: &str) -> Vec<u8> {
+        let program = asm::assemble(source, 0xC000).unwrap();
+        let mut prg = vec![0; 0x4000];
+        prg[..program.bytes.len()].copy_from_slice(&program.bytes);
+        prg[0x3FFC..0x3FFE].copy_from_slice(&0xC000u16.to_le_bytes());
+
+        let mut rom = b"NES\x1A\x01\x01\0\0\0\0\0\0\0\0\0\0".to_vec();
+        rom.extend_from_slice(&prg);
+        rom.extend_from_slice(&[0; 0x2000]);
+        let mut nes = Nes::new(&rom).unwrap();
+        nes.start_code_data_log(None).unwrap();
+        nes.run_frame();
+        nes.code_data_log().unwrap().prg.clone()
+    }
+
+    #[test]
+    fn reads_are_logged_by_what_the_cpu_reads_them_for() {
+        let prg = log_one_frame(
+            "       jsr sub
+             loop:  jmp loop
+             sub:   lda table
+                    lda #$01
+                    rts
+             after: .byte $FF
+             table: .byte $42",
+        );
+        let flags = |addr: u16| prg[(addr - 0xC000) as usize] & (CODE | DATA);
+
+        // JSR, JMP and both LDAs with their operands
+        for addr in 0xC000..0xC00C {
+            assert_eq!(flags(addr), CODE, "${:04X}", addr);
+        }
+        // RTS reads the byte after it and drops it
+        assert_eq!(flags(0xC00C), 0);
+        assert_eq!(flags(0xC00D), DATA);
+    }
+}
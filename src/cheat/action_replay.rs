@@ -0,0 +1,45 @@
+use super::Freeze;
+
+// Pro Action Replay codes are 8 hex digits, 00AAAAVV: a flag byte the NES
+// version leaves unused, the address and the value it is frozen at
+pub fn decode(code: &str) -> Result<Freeze, String> {
+    if code.len() != 8 || !code.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+        return Err(format!("{} is not 8 hex digits", code));
+    }
+
+    let number = u32::from_str_radix(code, 16).unwrap();
+    Freeze::new((number >> 8) as u16, number as u8, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_address_and_value() {
+        let freeze = decode("00075A09").unwrap();
+        assert_eq!(
+            (freeze.addr, freeze.value, freeze.compare),
+            (0x075A, 0x09, None)
+        );
+        let freeze = decode("0061ffFF").unwrap();
+        assert_eq!((freeze.addr, freeze.value), (0x61FF, 0xFF));
+        // The flag byte is ignored
+        assert_eq!(decode("FF075A09").unwrap().addr, 0x075A);
+    }
+
+    #[test]
+    fn invalid_codes() {
+        assert_eq!(
+            decode("075A09"),
+            Err("075A09 is not 8 hex digits".to_string())
+        );
+        assert_eq!(
+            decode("00075A0G"),
+            Err("00075A0G is not 8 hex digits".to_string())
+        );
+        assert!(decode("+0075A09").is_err());
+        assert_eq!(decode("00800001"), Err("$8000 is not RAM".to_string()));
+        assert_eq!(decode("00200001"), Err("$2000 is not RAM".to_string()));
+    }
+}
@@ -0,0 +1,180 @@
+// Letters stand for nibbles in this order
+// https://www.nesdev.org/wiki/Game_Genie
+const LETTERS: &[u8; 16] = b"APZLGITYEOXUKSVN";
+
+// Replaces the ROM byte at `addr` with `value`. With a compare byte only when
+// the ROM holds it, so other banks mapped at the same address are left alone
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GameGenieCode {
+    pub addr: u16,
+    pub value: u8,
+    pub compare: Option<u8>,
+}
+
+impl GameGenieCode {
+    pub fn decode(code: &str) -> Result<Self, String> {
+        let n = code
+            .bytes()
+            .map(|letter| {
+                LETTERS
+                    .iter()
+                    .position(|&l| l == letter.to_ascii_uppercase())
+                    .map(|nibble| nibble as u16)
+            })
+            .collect::<Option<Vec<u16>>>()
+            .ok_or_else(|| format!("{} has letters a Game Genie doesn't", code))?;
+        if n.len() != 6 && n.len() != 8 {
+            return Err(format!("{} is not 6 or 8 letters long", code));
+        }
+
+        let addr = 0x8000
+            | ((n[3] & 7) << 12)
+            | ((n[5] & 7) << 8)
+            | ((n[4] & 8) << 8)
+            | ((n[2] & 7) << 4)
+            | ((n[1] & 8) << 4)
+            | (n[4] & 7)
+            | (n[3] & 8);
+        let value = ((n[1] & 7) << 4) | ((n[0] & 8) << 4) | (n[0] & 7);
+
+        Ok(if n.len() == 6 {
+            GameGenieCode {
+                addr,
+                value: (value | (n[5] & 8)) as u8,
+                compare: None,
+            }
+        } else {
+            let compare = ((n[7] & 7) << 4) | ((n[6] & 8) << 4) | (n[6] & 7) | (n[5] & 8);
+            GameGenieCode {
+                addr,
+                value: (value | (n[7] & 8)) as u8,
+                compare: Some(compare as u8),
+            }
+        })
+    }
+
+    pub fn apply(&self, addr: u16, value: u8) -> u8 {
+        if addr == self.addr && self.compare.is_none_or(|compare| compare == value) {
+            self.value
+        } else {
+            value
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code(addr: u16, value: u8, compare: Option<u8>) -> GameGenieCode {
+        GameGenieCode {
+            addr,
+            value,
+            compare,
+        }
+    }
+
+    // Letters for the nibbles, the inverse of `decode` written bit by bit
+    fn encode(patch: &GameGenieCode) -> String {
+        let (a, v) = (patch.addr, patch.value as u16);
+        let mut n = [
+            (v & 7) | (v >> 4 & 8),
+            (v >> 4 & 7) | (a >> 4 & 8),
+            a >> 4 & 7,
+            (a >> 12 & 7) | (a & 8),
+            (a & 7) | (a >> 8 & 8),
+            a >> 8 & 7,
+        ]
+        .to_vec();
+        match patch.compare {
+            None => n[5] |= v & 8,
+            Some(c) => {
+                let c = c as u16;
+                n[2] |= 8;
+                n[5] |= c & 8;
+                n.push((c & 7) | (c >> 4 & 8));
+                n.push((c >> 4 & 7) | (v & 8));
+            }
+        }
+        n.iter()
+            .map(|&nibble| LETTERS[nibble as usize] as char)
+            .collect()
+    }
+
+    #[test]
+    fn six_letters() {
+        // Super Mario Bros. infinite lives, DEC $075A at $91D9 becomes LDA
+        assert_eq!(
+            GameGenieCode::decode("SXIOPO"),
+            Ok(code(0x91D9, 0xAD, None))
+        );
+        assert_eq!(
+            GameGenieCode::decode("sxiopo"),
+            Ok(code(0x91D9, 0xAD, None))
+        );
+        assert_eq!(
+            GameGenieCode::decode("AAAAAA"),
+            Ok(code(0x8000, 0x00, None))
+        );
+        assert_eq!(
+            GameGenieCode::decode("NNNNNN"),
+            Ok(code(0xFFFF, 0xFF, None))
+        );
+    }
+
+    #[test]
+    fn eight_letters() {
+        assert_eq!(
+            GameGenieCode::decode("AAEAULPA"),
+            Ok(code(0x8B03, 0x00, Some(0x01)))
+        );
+        assert_eq!(
+            GameGenieCode::decode("NNNNNNNN"),
+            Ok(code(0xFFFF, 0xFF, Some(0xFF)))
+        );
+        assert_eq!(
+            GameGenieCode::decode("AAAAAAAA"),
+            Ok(code(0x8000, 0x00, Some(0x00)))
+        );
+    }
+
+    // Every address, value and compare bit lands somewhere of its own
+    #[test]
+    fn every_bit_round_trips() {
+        for bit in 0..15 {
+            let patch = code(0x8000 | 1 << bit, 0x5A, None);
+            assert_eq!(GameGenieCode::decode(&encode(&patch)), Ok(patch));
+        }
+        for bit in 0..8 {
+            let patch = code(0xA5A5, 1 << bit, None);
+            assert_eq!(GameGenieCode::decode(&encode(&patch)), Ok(patch));
+            let patch = code(0xA5A5, 1 << bit, Some(!(1 << bit)));
+            assert_eq!(GameGenieCode::decode(&encode(&patch)), Ok(patch));
+        }
+        assert_eq!(encode(&code(0x91D9, 0xAD, None)), "SXIOPO");
+    }
+
+    #[test]
+    fn invalid_codes() {
+        assert_eq!(
+            GameGenieCode::decode("SXIOPB"),
+            Err("SXIOPB has letters a Game Genie doesn't".to_string())
+        );
+        assert_eq!(
+            GameGenieCode::decode("SXIOP"),
+            Err("SXIOP is not 6 or 8 letters long".to_string())
+        );
+        assert!(GameGenieCode::decode("SXIOPOA").is_err());
+        assert!(GameGenieCode::decode("").is_err());
+        assert!(GameGenieCode::decode("SXIÖPO").is_err());
+    }
+
+    #[test]
+    fn compare_byte_guards_the_patch() {
+        let patch = code(0x91D9, 0xAD, Some(0xCE));
+        assert_eq!(patch.apply(0x91D9, 0xCE), 0xAD);
+        assert_eq!(patch.apply(0x91D9, 0x00), 0x00);
+        assert_eq!(patch.apply(0x91DA, 0xCE), 0xCE);
+        assert_eq!(code(0x91D9, 0xAD, None).apply(0x91D9, 0x12), 0xAD);
+    }
+}
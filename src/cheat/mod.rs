@@ -0,0 +1,396 @@
+mod action_replay;
+mod game_genie;
+
+use std::{fs, path::Path};
+
+pub use game_genie::GameGenieCode;
+
+// Start of vblank, right before the NMI handler gets to look at RAM
+const DEFAULT_FREEZE_SCANLINE: u16 = 241;
+
+// RAM byte written back once per frame. With a compare byte only when it
+// holds that value
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Freeze {
+    pub addr: u16,
+    pub value: u8,
+    pub compare: Option<u8>,
+}
+
+impl Freeze {
+    // Console RAM and cartridge PRG RAM only, registers are left alone
+    pub fn new(addr: u16, value: u8, compare: Option<u8>) -> Result<Self, String> {
+        match addr {
+            0x0000..=0x1FFF | 0x6000..=0x7FFF => Ok(Freeze {
+                addr,
+                value,
+                compare,
+            }),
+            _ => Err(format!("${:04X} is not RAM", addr)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CheatCode {
+    // Replaces what the CPU reads from ROM
+    RomPatch(GameGenieCode),
+    Freeze(Freeze),
+}
+
+impl CheatCode {
+    // Game Genie letters, Pro Action Replay digits or AAAA:VV[:CC], the last
+    // patches ROM from $8000 up and freezes RAM below
+    pub fn parse(code: &str) -> Result<Self, String> {
+        if code.contains(':') {
+            return parse_raw(code);
+        }
+
+        let hex = code.len() == 8 && code.bytes().all(|digit| digit.is_ascii_hexdigit());
+        match GameGenieCode::decode(code) {
+            Ok(patch) => Ok(CheatCode::RomPatch(patch)),
+            Err(_) if hex => Ok(CheatCode::Freeze(action_replay::decode(code)?)),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn parts(&self) -> (u16, u8, Option<u8>) {
+        match *self {
+            CheatCode::RomPatch(patch) => (patch.addr, patch.value, patch.compare),
+            CheatCode::Freeze(freeze) => (freeze.addr, freeze.value, freeze.compare),
+        }
+    }
+}
+
+fn parse_raw(code: &str) -> Result<CheatCode, String> {
+    let bad_code = || format!("{} is not AAAA:VV or AAAA:VV:CC", code);
+    let numbers = code
+        .split(':')
+        .map(|number| u16::from_str_radix(number.trim(), 16).ok())
+        .collect::<Option<Vec<u16>>>()
+        .ok_or_else(bad_code)?;
+    let (addr, value, compare) = match numbers[..] {
+        [addr, value] => (addr, value, None),
+        [addr, value, compare] => (addr, value, Some(compare)),
+        _ => return Err(bad_code()),
+    };
+    let byte = |number: u16| u8::try_from(number).map_err(|_| bad_code());
+    let (value, compare) = (byte(value)?, compare.map(byte).transpose()?);
+
+    Ok(if addr >= 0x8000 {
+        CheatCode::RomPatch(GameGenieCode {
+            addr,
+            value,
+            compare,
+        })
+    } else {
+        CheatCode::Freeze(Freeze::new(addr, value, compare)?)
+    })
+}
+
+#[derive(Clone)]
+pub struct Cheat {
+    pub code: String,
+    pub description: String,
+    pub enabled: bool,
+    kind: CheatCode,
+}
+
+impl Cheat {
+    pub fn kind(&self) -> CheatCode {
+        self.kind
+    }
+}
+
+// Cheat codes sitting between the cartridge and the CPU
+#[derive(Clone)]
+pub struct Cheats {
+    list: Vec<Cheat>,
+    // Enabled codes, kept apart so the bus only walks what matters
+    rom_patches: Vec<GameGenieCode>,
+    freezes: Vec<Freeze>,
+    freeze_scanline: u16,
+}
+
+impl Cheats {
+    pub fn new() -> Self {
+        Cheats {
+            list: Vec::new(),
+            rom_patches: Vec::new(),
+            freezes: Vec::new(),
+            freeze_scanline: DEFAULT_FREEZE_SCANLINE,
+        }
+    }
+
+    // Returns the index of the new code, which starts enabled
+    pub fn add(&mut self, code: &str, description: &str) -> Result<usize, String> {
+        let kind = CheatCode::parse(code)?;
+        self.push(code.to_ascii_uppercase(), description, true, kind);
+        Ok(self.list.len() - 1)
+    }
+
+    fn push(&mut self, code: String, description: &str, enabled: bool, kind: CheatCode) {
+        self.list.push(Cheat {
+            code,
+            description: description.to_string(),
+            enabled,
+            kind,
+        });
+        self.update();
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Cheat> {
+        if index >= self.list.len() {
+            return None;
+        }
+        let cheat = self.list.remove(index);
+        self.update();
+        Some(cheat)
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> Result<(), String> {
+        let cheat = self
+            .list
+            .get_mut(index)
+            .ok_or_else(|| format!("There is no cheat {}", index))?;
+        cheat.enabled = enabled;
+        self.update();
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+        self.update();
+    }
+
+    pub fn list(&self) -> &[Cheat] {
+        &self.list
+    }
+
+    // Freezes are written when the PPU starts this scanline (0-261)
+    pub fn set_freeze_scanline(&mut self, scanline: u16) {
+        self.freeze_scanline = scanline;
+    }
+    pub fn freeze_scanline(&self) -> u16 {
+        self.freeze_scanline
+    }
+
+    // `.cht` files are read as FCEUX writes them, anything else as one code
+    // per line followed by an optional description with # starting a comment.
+    // Returns how many codes were added and the `.cht` lines that were skipped
+    pub fn load(&mut self, path: &Path) -> Result<(usize, Vec<String>), String> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("Can't read {}: {}", path.display(), err))?;
+
+        let before = self.list.len();
+        let skipped = if path.extension().is_some_and(|ext| ext == "cht") {
+            self.parse_cht(&text)
+        } else {
+            self.parse_code_list(&text)?;
+            Vec::new()
+        };
+        Ok((self.list.len() - before, skipped))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, self.to_cht())
+            .map_err(|err| format!("Can't write {}: {}", path.display(), err))
+    }
+
+    pub fn parse_code_list(&mut self, text: &str) -> Result<(), String> {
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (code, description) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            self.add(code, description.trim())
+                .map_err(|err| format!("Line {}: {}", number + 1, err))?;
+        }
+        Ok(())
+    }
+
+    // [S][C][:]aaaa:vv[:cc]:name, S substitutes reads instead of writing RAM,
+    // C says a compare byte is there, : marks a disabled cheat. FCEUX takes
+    // codes this can't apply, so such lines are skipped and returned with the
+    // reason rather than losing the whole file
+    pub fn parse_cht(&mut self, text: &str) -> Vec<String> {
+        let mut skipped = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            if let Err(err) = self.parse_cht_line(line) {
+                skipped.push(format!("Line {} skipped: {}", number + 1, err));
+            }
+        }
+        skipped
+    }
+
+    fn parse_cht_line(&mut self, line: &str) -> Result<(), String> {
+        let mut rest = line.trim();
+        if rest.is_empty() {
+            return Ok(());
+        }
+
+        let substitute = rest.starts_with('S');
+        rest = rest.strip_prefix('S').unwrap_or(rest);
+        let has_compare = rest.starts_with('C');
+        rest = rest.strip_prefix('C').unwrap_or(rest);
+        let enabled = !rest.starts_with(':');
+        rest = rest.strip_prefix(':').unwrap_or(rest);
+
+        let fields = if has_compare { 4 } else { 3 };
+        let parts: Vec<&str> = rest.splitn(fields, ':').collect();
+        if parts.len() < fields {
+            return Err(format!("expected {} fields", fields));
+        }
+        let code = parts[..fields - 1].join(":");
+        let kind = match parse_raw(&code)? {
+            // Read substitution of RAM is as good as freezing it
+            CheatCode::Freeze(freeze) if substitute => CheatCode::Freeze(freeze),
+            CheatCode::RomPatch(_) if !substitute => return Err(format!("{} writes to ROM", code)),
+            kind => kind,
+        };
+        self.push(code.to_ascii_uppercase(), parts[fields - 1], enabled, kind);
+        Ok(())
+    }
+
+    pub fn to_cht(&self) -> String {
+        let mut text = String::new();
+        for cheat in &self.list {
+            let (addr, value, compare) = cheat.kind.parts();
+            if let CheatCode::RomPatch(_) = cheat.kind {
+                text.push('S');
+            }
+            if compare.is_some() {
+                text.push('C');
+            }
+            if !cheat.enabled {
+                text.push(':');
+            }
+            text += &format!("{:04x}:{:02x}:", addr, value);
+            if let Some(compare) = compare {
+                text += &format!("{:02x}:", compare);
+            }
+            text += &cheat.description;
+            text.push('\n');
+        }
+        text
+    }
+
+    fn update(&mut self) {
+        let enabled = self.list.iter().filter(|cheat| cheat.enabled);
+        self.rom_patches = enabled
+            .clone()
+            .filter_map(|cheat| match cheat.kind {
+                CheatCode::RomPatch(patch) => Some(patch),
+                _ => None,
+            })
+            .collect();
+        self.freezes = enabled
+            .filter_map(|cheat| match cheat.kind {
+                CheatCode::Freeze(freeze) => Some(freeze),
+                _ => None,
+            })
+            .collect();
+    }
+
+    pub fn patches_rom(&self) -> bool {
+        !self.rom_patches.is_empty()
+    }
+
+    // What the CPU sees at $8000-$FFFF instead of the ROM byte `value`
+    pub fn patch_rom_read(&self, addr: u16, value: u8) -> u8 {
+        self.rom_patches
+            .iter()
+            .fold(value, |value, patch| patch.apply(addr, value))
+    }
+
+    pub fn freezes(&self) -> &[Freeze] {
+        &self.freezes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_kind_of_code() {
+        let patch = |addr, value, compare| {
+            CheatCode::RomPatch(GameGenieCode {
+                addr,
+                value,
+                compare,
+            })
+        };
+        let freeze =
+            |addr, value, compare| CheatCode::Freeze(Freeze::new(addr, value, compare).unwrap());
+
+        assert_eq!(CheatCode::parse("SXIOPO"), Ok(patch(0x91D9, 0xAD, None)));
+        assert_eq!(CheatCode::parse("00075A09"), Ok(freeze(0x075A, 0x09, None)));
+        // Letters a Game Genie has are read as one, even when they are hex
+        assert_eq!(
+            CheatCode::parse("AAAAAAAA"),
+            Ok(patch(0x8000, 0x00, Some(0x00)))
+        );
+        assert_eq!(CheatCode::parse("075A:09"), Ok(freeze(0x075A, 0x09, None)));
+        assert_eq!(
+            CheatCode::parse("6000:01:02"),
+            Ok(freeze(0x6000, 0x01, Some(0x02)))
+        );
+        assert_eq!(
+            CheatCode::parse("C000:EA:A9"),
+            Ok(patch(0xC000, 0xEA, Some(0xA9)))
+        );
+    }
+
+    #[test]
+    fn invalid_codes() {
+        assert!(CheatCode::parse("").is_err());
+        assert!(CheatCode::parse("SXIOP").is_err());
+        assert!(CheatCode::parse("0007").is_err());
+        assert_eq!(
+            CheatCode::parse("075A:100"),
+            Err("075A:100 is not AAAA:VV or AAAA:VV:CC".to_string())
+        );
+        assert!(CheatCode::parse("075A").is_err());
+        assert!(CheatCode::parse("075A:09:01:02").is_err());
+        assert!(CheatCode::parse("12345:00").is_err());
+        assert_eq!(
+            CheatCode::parse("4016:01"),
+            Err("$4016 is not RAM".to_string())
+        );
+    }
+
+    // Lines the cheats can't use are skipped, the rest still loads
+    #[test]
+    fn cht_with_unusable_lines() {
+        let mut cheats = Cheats::new();
+        let skipped = cheats.parse_cht(
+            "075a:09:Lives
+             :0300:05:Ammo
+             2002:00:Register
+             c000:ea:ROM without S
+
+             garbage
+             Sc000:ea:Patch
+             SC8000:01:02:Compared patch",
+        );
+        assert_eq!(
+            skipped,
+            [
+                "Line 3 skipped: $2002 is not RAM",
+                "Line 4 skipped: c000:ea writes to ROM",
+                "Line 6 skipped: expected 3 fields",
+            ]
+        );
+
+        let list = cheats.list();
+        let codes: Vec<&str> = list.iter().map(|cheat| cheat.code.as_str()).collect();
+        assert_eq!(codes, ["075A:09", "0300:05", "C000:EA", "8000:01:02"]);
+        assert_eq!(list[1].description, "Ammo");
+        assert!(list[0].enabled && !list[1].enabled);
+        assert_eq!(cheats.rom_patches.len(), 2);
+        assert_eq!(cheats.freezes().len(), 1);
+    }
+}
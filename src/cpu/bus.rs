@@ -0,0 +1,119 @@
+use crate::bus::{Access, BusAccess};
+
+// What the CPU reads a byte for. Dummy reads are the cycles where the
+// 6502 puts an address on the bus and drops the value
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReadKind {
+    Opcode,
+    Operand,
+    Data,
+    Dummy,
+}
+
+// Everything the CPU is wired to. Reads and writes take one cycle each, `tick`
+// is a cycle with nothing on the bus
+pub trait CpuBus {
+    fn mem_read(&mut self, addr: u16, kind: ReadKind) -> u8;
+    fn mem_write(&mut self, addr: u16, value: u8);
+    // Reads without side effects or a cycle, for debugging tools
+    fn peek(&self, addr: u16) -> u8;
+    fn tick(&mut self);
+    fn cycles(&self) -> u64;
+
+    // NMI is taken on the rising edge of this, the CPU does the edge detection
+    fn nmi_line(&self) -> bool;
+    // IRQ is level triggered, it stays up until the source is acknowledged
+    fn irq_line(&self) -> bool;
+
+    // Instruction at `pc` is about to be fetched, for debugging tools
+    fn begin_instruction(&mut self, _pc: u16) {}
+    // The current instruction reads through a pointer, (zp,X) or (zp),Y
+    fn mark_indirect_access(&mut self) {}
+    // Target of JMP ($nnnn)
+    fn log_indirect_code(&mut self, _addr: u16) {}
+
+    fn mem_read_u16(&mut self, addr: u16, kind: ReadKind) -> u16 {
+        let lo = self.mem_read(addr, kind);
+        let hi = self.mem_read(addr.wrapping_add(1), kind);
+
+        u16::from_le_bytes([lo, hi])
+    }
+}
+
+// 64 KiB of RAM and nothing else, for running the CPU on its own. The
+// interrupt lines are driven by hand
+pub struct FlatRam {
+    ram: Box<[u8]>,
+    cycles: u64,
+    pub nmi: bool,
+    pub irq: bool,
+    access_log: Option<Vec<BusAccess>>,
+}
+
+impl FlatRam {
+    pub fn new() -> Self {
+        FlatRam {
+            ram: vec![0; 0x10000].into_boxed_slice(),
+            cycles: 0,
+            nmi: false,
+            irq: false,
+            access_log: None,
+        }
+    }
+
+    // Writes without taking a cycle
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        self.ram[addr as usize] = value;
+    }
+    pub fn load(&mut self, addr: u16, data: &[u8]) {
+        for (offset, &value) in data.iter().enumerate() {
+            self.poke(addr.wrapping_add(offset as u16), value);
+        }
+    }
+
+    pub fn set_access_log(&mut self, enabled: bool) {
+        self.access_log = if enabled { Some(Vec::new()) } else { None };
+    }
+    pub fn take_access_log(&mut self) -> Vec<BusAccess> {
+        self.access_log
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    fn log(&mut self, kind: Access, addr: u16, value: u8) {
+        if let Some(log) = &mut self.access_log {
+            log.push(BusAccess { kind, addr, value });
+        }
+    }
+}
+
+impl CpuBus for FlatRam {
+    fn mem_read(&mut self, addr: u16, _kind: ReadKind) -> u8 {
+        self.tick();
+        let value = self.ram[addr as usize];
+        self.log(Access::Read, addr, value);
+        value
+    }
+    fn mem_write(&mut self, addr: u16, value: u8) {
+        self.tick();
+        self.ram[addr as usize] = value;
+        self.log(Access::Write, addr, value);
+    }
+    fn peek(&self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+    fn tick(&mut self) {
+        self.cycles += 1;
+    }
+    fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    fn nmi_line(&self) -> bool {
+        self.nmi
+    }
+    fn irq_line(&self) -> bool {
+        self.irq
+    }
+}
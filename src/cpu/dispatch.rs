@@ -0,0 +1,240 @@
+use std::marker::PhantomData;
+
+use super::{AddressingMode, Cpu, CpuBus, CpuFlag};
+
+type Handler<B> = fn(&mut Cpu, &mut B, &AddressingMode);
+
+// What to run for each opcode, built at compile time for every bus type so
+// an instruction costs one indexed call. Opcodes without one halt the CPU
+pub(super) struct Dispatch<B>(PhantomData<B>);
+
+impl<B: CpuBus> Dispatch<B> {
+    pub(super) const TABLE: [Option<Handler<B>>; 256] = {
+        let mut table = [None; 256];
+
+        // BRK
+        set(&mut table, &[0x00], |cpu, bus, _| cpu.brk(bus));
+        // NOP
+        set(&mut table, &[0xEA], |_, _, _| {});
+
+        // BIT
+        set(&mut table, &[0x24, 0x2C], |cpu, bus, mode| {
+            cpu.bit(bus, mode)
+        });
+
+        // TAX
+        set(&mut table, &[0xAA], |cpu, _, _| cpu.tax());
+        // TAY
+        set(&mut table, &[0xA8], |cpu, _, _| cpu.tay());
+        // TSX
+        set(&mut table, &[0xBA], |cpu, _, _| cpu.tsx());
+        // TXA
+        set(&mut table, &[0x8A], |cpu, _, _| cpu.txa());
+        // TXS
+        set(&mut table, &[0x9A], |cpu, _, _| cpu.txs());
+        // TYA
+        set(&mut table, &[0x98], |cpu, _, _| cpu.tya());
+
+        // CLC
+        set(&mut table, &[0x18], |cpu, _, _| {
+            cpu.clear_flag(CpuFlag::CARRY)
+        });
+        // CLD
+        set(&mut table, &[0xD8], |cpu, _, _| {
+            cpu.clear_flag(CpuFlag::DECIMAL_MODE)
+        });
+        // CLI
+        set(&mut table, &[0x58], |cpu, _, _| {
+            cpu.clear_flag(CpuFlag::INTERRUPT_DISABLE)
+        });
+        // CLV
+        set(&mut table, &[0xB8], |cpu, _, _| {
+            cpu.clear_flag(CpuFlag::OVERFLOW)
+        });
+
+        // SEC
+        set(&mut table, &[0x38], |cpu, _, _| {
+            cpu.set_flag(CpuFlag::CARRY)
+        });
+        // SED
+        set(&mut table, &[0xF8], |cpu, _, _| {
+            cpu.set_flag(CpuFlag::DECIMAL_MODE)
+        });
+        // SEI
+        set(&mut table, &[0x78], |cpu, _, _| {
+            cpu.set_flag(CpuFlag::INTERRUPT_DISABLE)
+        });
+
+        // LDA
+        set(
+            &mut table,
+            &[0xA9, 0xA5, 0xB5, 0xAD, 0xBD, 0xB9, 0xA1, 0xB1],
+            |cpu, bus, mode| cpu.lda(bus, mode),
+        );
+        // LDX
+        set(
+            &mut table,
+            &[0xA2, 0xA6, 0xB6, 0xAE, 0xBE],
+            |cpu, bus, mode| cpu.ldx(bus, mode),
+        );
+        // LDY
+        set(
+            &mut table,
+            &[0xA0, 0xA4, 0xB4, 0xAC, 0xBC],
+            |cpu, bus, mode| cpu.ldy(bus, mode),
+        );
+
+        // STA
+        set(
+            &mut table,
+            &[0x85, 0x95, 0x8D, 0x9D, 0x99, 0x81, 0x91],
+            |cpu, bus, mode| cpu.sta(bus, mode),
+        );
+        // STX
+        set(&mut table, &[0x86, 0x96, 0x8E], |cpu, bus, mode| {
+            cpu.stx(bus, mode)
+        });
+        // STY
+        set(&mut table, &[0x84, 0x94, 0x8C], |cpu, bus, mode| {
+            cpu.sty(bus, mode)
+        });
+
+        // ASL
+        set(
+            &mut table,
+            &[0x0A, 0x06, 0x16, 0x0E, 0x1E],
+            |cpu, bus, mode| cpu.asl(bus, mode),
+        );
+        // LSR
+        set(
+            &mut table,
+            &[0x4A, 0x46, 0x56, 0x4E, 0x5E],
+            |cpu, bus, mode| cpu.lsr(bus, mode),
+        );
+        // ROL
+        set(
+            &mut table,
+            &[0x2A, 0x26, 0x36, 0x2E, 0x3E],
+            |cpu, bus, mode| cpu.rol(bus, mode),
+        );
+        // ROR
+        set(
+            &mut table,
+            &[0x6A, 0x66, 0x76, 0x6E, 0x7E],
+            |cpu, bus, mode| cpu.ror(bus, mode),
+        );
+
+        // PHA
+        set(&mut table, &[0x48], |cpu, bus, _| cpu.pha(bus));
+        // PLA
+        set(&mut table, &[0x68], |cpu, bus, _| cpu.pla(bus));
+
+        // PHP
+        set(&mut table, &[0x08], |cpu, bus, _| cpu.php(bus));
+        // PLP
+        set(&mut table, &[0x28], |cpu, bus, _| cpu.plp(bus));
+
+        // AND
+        set(
+            &mut table,
+            &[0x29, 0x25, 0x35, 0x2D, 0x3D, 0x39, 0x21, 0x31],
+            |cpu, bus, mode| cpu.and(bus, mode),
+        );
+        // ORA
+        set(
+            &mut table,
+            &[0x09, 0x05, 0x15, 0x0D, 0x1D, 0x19, 0x01, 0x11],
+            |cpu, bus, mode| cpu.ora(bus, mode),
+        );
+        // EOR
+        set(
+            &mut table,
+            &[0x49, 0x45, 0x55, 0x4D, 0x5D, 0x59, 0x41, 0x51],
+            |cpu, bus, mode| cpu.eor(bus, mode),
+        );
+
+        // BPL
+        set(&mut table, &[0x10], |cpu, bus, _| cpu.bpl(bus));
+        // BMI
+        set(&mut table, &[0x30], |cpu, bus, _| cpu.bmi(bus));
+        // BVC
+        set(&mut table, &[0x50], |cpu, bus, _| cpu.bvc(bus));
+        // BVS
+        set(&mut table, &[0x70], |cpu, bus, _| cpu.bvs(bus));
+        // BCC
+        set(&mut table, &[0x90], |cpu, bus, _| cpu.bcc(bus));
+        // BCS
+        set(&mut table, &[0xB0], |cpu, bus, _| cpu.bcs(bus));
+        // BNE
+        set(&mut table, &[0xD0], |cpu, bus, _| cpu.bne(bus));
+        // BEQ
+        set(&mut table, &[0xF0], |cpu, bus, _| cpu.beq(bus));
+
+        // CMP
+        set(
+            &mut table,
+            &[0xC9, 0xC5, 0xD5, 0xCD, 0xDD, 0xD9, 0xC1, 0xD1],
+            |cpu, bus, mode| cpu.cmp(bus, mode),
+        );
+        // CPX
+        set(&mut table, &[0xE0, 0xE4, 0xEC], |cpu, bus, mode| {
+            cpu.cpx(bus, mode)
+        });
+        // CPY
+        set(&mut table, &[0xC0, 0xC4, 0xCC], |cpu, bus, mode| {
+            cpu.cpy(bus, mode)
+        });
+
+        // INC
+        set(&mut table, &[0xE6, 0xF6, 0xEE, 0xFE], |cpu, bus, mode| {
+            cpu.inc(bus, mode)
+        });
+        // INX
+        set(&mut table, &[0xE8], |cpu, _, _| cpu.inx());
+        // INY
+        set(&mut table, &[0xC8], |cpu, _, _| cpu.iny());
+
+        // DEC
+        set(&mut table, &[0xC6, 0xD6, 0xCE, 0xDE], |cpu, bus, mode| {
+            cpu.dec(bus, mode)
+        });
+        // DEX
+        set(&mut table, &[0xCA], |cpu, _, _| cpu.dex());
+        // DEY
+        set(&mut table, &[0x88], |cpu, _, _| cpu.dey());
+
+        // JMP
+        set(&mut table, &[0x4C, 0x6C], |cpu, bus, mode| {
+            cpu.jmp(bus, mode)
+        });
+        // JSR
+        set(&mut table, &[0x20], |cpu, bus, _| cpu.jsr(bus));
+        // RTI
+        set(&mut table, &[0x40], |cpu, bus, _| cpu.rti(bus));
+        // RTS
+        set(&mut table, &[0x60], |cpu, bus, _| cpu.rts(bus));
+
+        // ADC
+        set(
+            &mut table,
+            &[0x69, 0x65, 0x75, 0x6D, 0x7D, 0x79, 0x61, 0x71],
+            |cpu, bus, mode| cpu.adc(bus, mode),
+        );
+        // SBC
+        set(
+            &mut table,
+            &[0xE9, 0xE5, 0xF5, 0xED, 0xFD, 0xF9, 0xE1, 0xF1],
+            |cpu, bus, mode| cpu.sbc(bus, mode),
+        );
+
+        table
+    };
+}
+
+const fn set<B: CpuBus>(table: &mut [Option<Handler<B>>; 256], codes: &[u8], handler: Handler<B>) {
+    let mut i = 0;
+    while i < codes.len() {
+        table[codes[i] as usize] = Some(handler);
+        i += 1;
+    }
+}
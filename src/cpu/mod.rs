@@ -1,10 +1,14 @@
+mod bus;
+mod dispatch;
 pub mod opcode;
 mod stackptr;
 
-use crate::{bus::Bus, rom::Rom};
+use crate::savestate::{Section, Snapshot};
+
+pub use bus::{CpuBus, FlatRam, ReadKind};
 
 use bitflags::bitflags;
-use opcode::OPCODES_MAP;
+use dispatch::Dispatch;
 use stackptr::StackPtr;
 
 bitflags! {
@@ -31,7 +35,7 @@ bitflags! {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 #[allow(non_camel_case_types)]
 pub enum AddressingMode {
     Immediate,
@@ -46,47 +50,156 @@ pub enum AddressingMode {
     NoneAddressing,
 }
 
+// Programmer visible state, for debugging tools
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Registers {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    pub p: u8,
+    pub pc: u16,
+}
+
+// Opcode the CPU stopped on and where it was. Like a 6502 hitting a JAM,
+// the CPU does nothing more until reset while the rest of the system runs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Halt {
+    pub opcode: u8,
+    pub pc: u16,
+}
+
+impl Halt {
+    pub fn describe(&self) -> String {
+        format!(
+            "CPU halted on opcode ${:02X} at ${:04X}",
+            self.opcode, self.pc
+        )
+    }
+}
+
+// Chips the core can act as
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CpuVariant {
+    // NES CPU, an NMOS 6502 with decimal mode cut out. D can be set and
+    // pushed but ADC and SBC stay binary
+    Ricoh2A03,
+    // Decimal mode included, with its undocumented flags
+    Nmos6502,
+}
+
+impl CpuVariant {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "2a03" | "2A03" => Ok(CpuVariant::Ricoh2A03),
+            "6502" => Ok(CpuVariant::Nmos6502),
+            _ => Err(format!("\"{}\" is not 2a03 or 6502", text)),
+        }
+    }
+}
+
 pub struct Cpu {
+    variant: CpuVariant,
     register_a: u8,
     register_x: u8,
     register_y: u8,
     stackptr: StackPtr,
     status: CpuFlag,
     pc: u16, // Program Counter
-    bus: Bus,
+    // Interrupt lines are sampled at the end of every cycle, what was seen
+    // one cycle before the last decides if an interrupt follows the
+    // instruction. NMI is latched on its rising edge
+    nmi_line: bool,
+    nmi_pending: bool,
+    prev_nmi_pending: bool,
+    irq_pending: bool,
+    prev_irq_pending: bool,
+    halted: Option<Halt>,
 }
 
 impl Cpu {
-    pub fn new(rom: Rom) -> Self {
+    pub fn new() -> Self {
+        Cpu::with_variant(CpuVariant::Ricoh2A03)
+    }
+
+    pub fn with_variant(variant: CpuVariant) -> Self {
         Cpu {
+            variant,
             register_a: 0,
             register_x: 0,
             register_y: 0,
             stackptr: StackPtr::new(),
             status: CpuFlag::from_bits_truncate(0b0010_0100),
             pc: 0,
-            bus: Bus::new(rom),
+            nmi_line: false,
+            nmi_pending: false,
+            prev_nmi_pending: false,
+            irq_pending: false,
+            prev_irq_pending: false,
+            halted: None,
         }
     }
 
-    fn mem_read(&mut self, addr: u16) -> u8 {
-        self.bus.mem_read(addr)
+    // Every bus cycle of the CPU goes through these so the interrupt lines
+    // get polled. The bus is told what each read is for
+    fn read_as<B: CpuBus>(&mut self, bus: &mut B, addr: u16, kind: ReadKind) -> u8 {
+        let value = bus.mem_read(addr, kind);
+        self.poll_interrupts(bus);
+        value
+    }
+    fn read<B: CpuBus>(&mut self, bus: &mut B, addr: u16) -> u8 {
+        self.read_as(bus, addr, ReadKind::Data)
+    }
+    fn fetch<B: CpuBus>(&mut self, bus: &mut B, addr: u16) -> u8 {
+        self.read_as(bus, addr, ReadKind::Operand)
     }
-    fn mem_read_u16(&mut self, addr: u16) -> u16 {
-        self.bus.mem_read_u16(addr)
+    fn dummy_read<B: CpuBus>(&mut self, bus: &mut B, addr: u16) {
+        self.read_as(bus, addr, ReadKind::Dummy);
     }
+    fn read_u16<B: CpuBus>(&mut self, bus: &mut B, addr: u16, kind: ReadKind) -> u16 {
+        let lo = self.read_as(bus, addr, kind);
+        let hi = self.read_as(bus, addr.wrapping_add(1), kind);
 
-    fn mem_write(&mut self, addr: u16, value: u8) {
-        self.bus.mem_write(addr, value);
+        u16::from_le_bytes([lo, hi])
     }
-    fn mem_write_u16(&mut self, addr: u16, value: u16) {
-        self.bus.mem_write_u16(addr, value);
+    fn write<B: CpuBus>(&mut self, bus: &mut B, addr: u16, value: u8) {
+        bus.mem_write(addr, value);
+        self.poll_interrupts(bus);
     }
 
-    fn branch(&mut self) {
-        let jump = self.mem_read(self.pc) as i8;
-        let jump_addr = self.pc.wrapping_add(1).wrapping_add(jump as u16);
+    fn poll_interrupts<B: CpuBus>(&mut self, bus: &mut B) {
+        self.prev_nmi_pending = self.nmi_pending;
+        let nmi_line = bus.nmi_line();
+        if nmi_line && !self.nmi_line {
+            self.nmi_pending = true;
+        }
+        self.nmi_line = nmi_line;
+
+        self.prev_irq_pending = self.irq_pending;
+        self.irq_pending = bus.irq_line() && !self.status.contains(CpuFlag::INTERRUPT_DISABLE);
+    }
 
+    // The offset is read either way. Taking the branch reads the next opcode
+    // while PC is moved, and once more if the high byte has to be fixed
+    fn branch<B: CpuBus>(&mut self, bus: &mut B, condition: bool) {
+        let jump = self.fetch(bus, self.pc) as i8;
+        if !condition {
+            return;
+        }
+        let next_addr = self.pc.wrapping_add(1);
+        let jump_addr = next_addr.wrapping_add(jump as u16);
+
+        // An interrupt first seen on the offset read waits one more
+        // instruction when the branch takes three cycles
+        let delay_nmi = self.nmi_pending && !self.prev_nmi_pending;
+        let delay_irq = self.irq_pending && !self.prev_irq_pending;
+        self.dummy_read(bus, next_addr);
+        if next_addr & 0xFF00 != jump_addr & 0xFF00 {
+            self.dummy_read(bus, next_addr & 0xFF00 | jump_addr & 0x00FF);
+        } else {
+            self.prev_nmi_pending &= !delay_nmi;
+            self.prev_irq_pending &= !delay_irq;
+        }
         self.pc = jump_addr;
     }
 
@@ -104,23 +217,23 @@ impl Cpu {
         self.status.remove(flag);
     }
 
-    fn stack_push(&mut self, value: u8) {
-        self.mem_write(self.stackptr.addr(), value);
+    fn stack_push<B: CpuBus>(&mut self, bus: &mut B, value: u8) {
+        self.write(bus, self.stackptr.addr(), value);
         self.stackptr.inc();
     }
-    fn stack_push_u16(&mut self, value: u16) {
+    fn stack_push_u16<B: CpuBus>(&mut self, bus: &mut B, value: u16) {
         let hi = (value >> 8) as u8;
         let lo = (value & 0xff) as u8;
-        self.stack_push(hi);
-        self.stack_push(lo);
+        self.stack_push(bus, hi);
+        self.stack_push(bus, lo);
     }
-    fn stack_pop(&mut self) -> u8 {
+    fn stack_pop<B: CpuBus>(&mut self, bus: &mut B) -> u8 {
         self.stackptr.dec();
-        self.mem_read(self.stackptr.addr())
+        self.read(bus, self.stackptr.addr())
     }
-    fn stack_pop_u16(&mut self) -> u16 {
-        let lo = self.stack_pop() as u16;
-        let hi = self.stack_pop() as u16;
+    fn stack_pop_u16<B: CpuBus>(&mut self, bus: &mut B) -> u16 {
+        let lo = self.stack_pop(bus) as u16;
+        let hi = self.stack_pop(bus) as u16;
         hi << 8 | lo
     }
 
@@ -137,54 +250,66 @@ impl Cpu {
         self.update_zero_and_negative_flags(self.register_y);
     }
 
-    pub fn get_absolute_address(&mut self, mode: &AddressingMode, addr: u16) -> u16 {
+    // Address of the operand found at `addr`. Indexing first reads from the
+    // address before the carry into the high byte is added, reads skip that
+    // cycle when there is no carry, stores and read-modify-write never do
+    pub fn get_absolute_address<B: CpuBus>(
+        &mut self,
+        bus: &mut B,
+        mode: &AddressingMode,
+        addr: u16,
+        write: bool,
+    ) -> u16 {
         match mode {
             AddressingMode::Immediate => addr,
-            AddressingMode::ZeroPage => self.mem_read(addr) as u16,
-            AddressingMode::Absolute => self.mem_read_u16(addr),
+            AddressingMode::ZeroPage => self.fetch(bus, addr) as u16,
+            AddressingMode::Absolute => self.read_u16(bus, addr, ReadKind::Operand),
 
             AddressingMode::ZeroPage_X => {
-                let addr = self.mem_read(addr);
+                let base = self.fetch(bus, addr);
+                self.dummy_read(bus, base as u16);
 
-                addr.wrapping_add(self.register_x) as u16
+                base.wrapping_add(self.register_x) as u16
             }
 
             AddressingMode::ZeroPage_Y => {
-                let addr = self.mem_read(addr);
+                let base = self.fetch(bus, addr);
+                self.dummy_read(bus, base as u16);
 
-                addr.wrapping_add(self.register_y) as u16
+                base.wrapping_add(self.register_y) as u16
             }
 
             AddressingMode::Absolute_X => {
-                let base = self.mem_read_u16(addr);
-
-                base.wrapping_add(self.register_x as u16)
+                let base = self.read_u16(bus, addr, ReadKind::Operand);
+                self.add_index(bus, base, self.register_x, write)
             }
 
             AddressingMode::Absolute_Y => {
-                let base = self.mem_read_u16(addr);
-
-                base.wrapping_add(self.register_y as u16)
+                let base = self.read_u16(bus, addr, ReadKind::Operand);
+                self.add_index(bus, base, self.register_y, write)
             }
 
             AddressingMode::Indirect_X => {
-                let base = self.mem_read(addr);
+                let base = self.fetch(bus, addr);
+                bus.mark_indirect_access();
+                self.dummy_read(bus, base as u16);
 
                 let ptr: u8 = base.wrapping_add(self.register_x);
-                let lo = self.mem_read(ptr as u16);
-                let hi = self.mem_read(ptr.wrapping_add(1) as u16);
+                let lo = self.read(bus, ptr as u16);
+                let hi = self.read(bus, ptr.wrapping_add(1) as u16);
 
                 u16::from_le_bytes([lo, hi])
             }
 
             AddressingMode::Indirect_Y => {
-                let base = self.mem_read(addr);
+                let base = self.fetch(bus, addr);
+                bus.mark_indirect_access();
 
-                let lo = self.mem_read(base as u16);
-                let hi = self.mem_read(base.wrapping_add(1) as u16);
+                let lo = self.read(bus, base as u16);
+                let hi = self.read(bus, base.wrapping_add(1) as u16);
                 let deref_base = u16::from_le_bytes([lo, hi]);
 
-                deref_base.wrapping_add(self.register_y as u16)
+                self.add_index(bus, deref_base, self.register_y, write)
             }
 
             AddressingMode::NoneAddressing => {
@@ -192,236 +317,214 @@ impl Cpu {
             }
         }
     }
-    fn get_address(&mut self, mode: &AddressingMode) -> u16 {
-        self.get_absolute_address(mode, self.pc)
+    fn add_index<B: CpuBus>(&mut self, bus: &mut B, base: u16, index: u8, write: bool) -> u16 {
+        let addr = base.wrapping_add(index as u16);
+        if write || base & 0xFF00 != addr & 0xFF00 {
+            self.dummy_read(bus, base & 0xFF00 | addr & 0x00FF);
+        }
+        addr
+    }
+    // Value of the operand, an immediate one is part of the instruction
+    fn read_value<B: CpuBus>(&mut self, bus: &mut B, mode: &AddressingMode) -> u8 {
+        let addr = self.get_absolute_address(bus, mode, self.pc, false);
+        if *mode == AddressingMode::Immediate {
+            self.fetch(bus, addr)
+        } else {
+            self.read(bus, addr)
+        }
+    }
+    fn get_write_address<B: CpuBus>(&mut self, bus: &mut B, mode: &AddressingMode) -> u16 {
+        self.get_absolute_address(bus, mode, self.pc, true)
     }
 
-    pub fn nmi(&mut self) {
-        self.stack_push_u16(self.pc);
+    // IRQ or NMI between instructions. The opcode is read and dropped. The
+    // first instruction of the handler always runs before the next interrupt,
+    // its opcode fetch is what polls the lines again
+    fn interrupt<B: CpuBus>(&mut self, bus: &mut B) {
+        bus.begin_instruction(self.pc);
+        self.dummy_read(bus, self.pc);
+        self.dummy_read(bus, self.pc);
+        self.interrupt_sequence(bus, false);
+        self.prev_nmi_pending = false;
+        self.prev_irq_pending = false;
+    }
+
+    // The next step enters an interrupt handler instead of running an
+    // instruction
+    pub fn interrupt_pending(&self) -> bool {
+        self.halted.is_none() && (self.prev_nmi_pending || self.prev_irq_pending)
+    }
 
+    // Pushes PC and P. An NMI seen by the time PC is pushed takes the vector
+    // over, even from a BRK or IRQ that is already under way
+    // https://www.nesdev.org/wiki/CPU_interrupts#Interrupt_hijacking
+    fn interrupt_sequence<B: CpuBus>(&mut self, bus: &mut B, brk: bool) {
+        self.stack_push_u16(bus, self.pc);
+
+        let vector = if std::mem::take(&mut self.nmi_pending) {
+            0xFFFA
+        } else {
+            0xFFFE
+        };
+        let mut flags = self.status | CpuFlag::BREAK2;
+        flags.set(CpuFlag::BREAK, brk);
+        self.stack_push(bus, flags.bits);
         self.set_flag(CpuFlag::INTERRUPT_DISABLE);
-        self.clear_flag(CpuFlag::BREAK);
-        self.set_flag(CpuFlag::BREAK2);
-        self.stack_push(self.status.bits);
 
-        // TODO remove when modules relationships will be restructured
-        self.pc = self.bus.mem_read_u16(0xFFFA);
+        self.pc = self.read_u16(bus, vector, ReadKind::Data);
     }
 
-    pub fn irq(&mut self) {
-        self.stack_push_u16(self.pc);
+    pub fn variant(&self) -> CpuVariant {
+        self.variant
+    }
 
-        self.set_flag(CpuFlag::INTERRUPT_DISABLE);
-        self.clear_flag(CpuFlag::BREAK);
-        self.set_flag(CpuFlag::BREAK2);
-        self.stack_push(self.status.bits);
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
 
-        // TODO remove when modules relationships will be restructured
-        self.pc = self.bus.mem_read_u16(0xFFFE);
+    pub fn halted(&self) -> Option<Halt> {
+        self.halted
+    }
+    fn halt(&mut self, opcode: u8, pc: u16) {
+        self.halted = Some(Halt { opcode, pc });
+        self.pc = pc;
     }
 
-    pub fn reset(&mut self) {
+    pub fn registers(&self) -> Registers {
+        Registers {
+            a: self.register_a,
+            x: self.register_x,
+            y: self.register_y,
+            sp: self.stackptr.rel_addr(),
+            p: self.status.bits,
+            pc: self.pc,
+        }
+    }
+    pub fn set_registers(&mut self, registers: Registers) {
+        self.register_a = registers.a;
+        self.register_x = registers.x;
+        self.register_y = registers.y;
+        self.stackptr.set(registers.sp);
+        self.status = CpuFlag::from_bits_truncate(registers.p);
+        self.pc = registers.pc;
+    }
+
+    pub fn reset<B: CpuBus>(&mut self, bus: &mut B) {
         self.register_a = 0;
         self.register_x = 0;
         self.register_y = 0;
         self.stackptr.reset();
         self.status = CpuFlag::from_bits_truncate(0b0010_0100);
+        self.nmi_pending = false;
+        self.prev_nmi_pending = false;
+        self.irq_pending = false;
+        self.prev_irq_pending = false;
+        self.halted = None;
 
-        self.pc = self.bus.read_initial_pc_addr();
+        self.pc = self.read_u16(bus, 0xFFFC, ReadKind::Data);
     }
 
-    pub fn load(&mut self, program: Vec<u8>) {
-        for i in 0..(program.len() as u16) {
-            self.mem_write(0x0600 + i, program[i as usize]);
+    pub fn run<B: CpuBus>(&mut self, bus: &mut B) {
+        self.run_with_callback(bus, |_, _| {});
+    }
+    pub fn run_with_callback<B: CpuBus, F>(&mut self, bus: &mut B, mut callback: F)
+    where
+        F: FnMut(&mut Cpu, &mut B),
+    {
+        while self.step(bus) {
+            callback(self, bus);
         }
-        // TODO: remove debug code
-        self.bus.write_initial_pc_addr(0x0600);
     }
 
-    pub fn load_and_run(&mut self, program: Vec<u8>) {
-        self.load(program);
-        self.reset();
-        self.run();
-    }
+    // Executes one instruction or enters an interrupt handler, returns false
+    // on BRK and once halted. A halted CPU leaves the bus idle for a cycle
+    // instead
+    pub fn step<B: CpuBus>(&mut self, bus: &mut B) -> bool {
+        if self.halted.is_some() {
+            bus.tick();
+            return false;
+        }
+        if self.interrupt_pending() {
+            self.interrupt(bus);
+            return true;
+        }
 
-    pub fn run(&mut self) {
-        self.run_with_callback(|_| {});
-    }
-    pub fn run_with_callback<F>(&mut self, mut callback: F)
-    where
-        F: FnMut(&mut Cpu),
-    {
-        loop {
-            let opcode = self.mem_read(self.pc);
-            self.pc += 1;
-            let program_counter_old = self.pc;
-
-            let instr = OPCODES_MAP
-                .get(&opcode)
-                .unwrap_or_else(|| panic!("Opcode {:x} is not recognized", opcode));
-
-            match instr.code {
-                // BRK
-                0x00 => return,
-                //NOP
-                0xEA => (),
-
-                // BIT
-                0x24 | 0x2C => self.bit(&instr.addressing_mode),
-
-                // TAX
-                0xAA => self.tax(),
-                // TAY
-                0xA8 => self.tay(),
-                // TSX
-                0xBA => self.tsx(),
-                // TXA
-                0x8A => self.txa(),
-                // TXS
-                0x9A => self.txs(),
-                // TYA
-                0x98 => self.tya(),
-
-                // CLC
-                0x18 => self.clear_flag(CpuFlag::CARRY),
-                // CLD
-                0xD8 => self.clear_flag(CpuFlag::DECIMAL_MODE),
-                // CLI
-                0x58 => self.clear_flag(CpuFlag::INTERRUPT_DISABLE),
-                // CLV
-                0xB8 => self.clear_flag(CpuFlag::OVERFLOW),
-
-                // SEC
-                0x38 => self.set_flag(CpuFlag::CARRY),
-                // SED
-                0xF8 => self.set_flag(CpuFlag::DECIMAL_MODE),
-                // SEI
-                0x78 => self.set_flag(CpuFlag::INTERRUPT_DISABLE),
-
-                // LDA
-                0xA9 | 0xA5 | 0xB5 | 0xAD | 0xBD | 0xB9 | 0xA1 | 0xB1 => {
-                    self.lda(&instr.addressing_mode)
-                }
-                // LDX
-                0xA2 | 0xA6 | 0xB6 | 0xAE | 0xBE => self.ldx(&instr.addressing_mode),
-                // LDY
-                0xA0 | 0xA4 | 0xB4 | 0xAC | 0xBC => self.ldy(&instr.addressing_mode),
-
-                // STA
-                0x85 | 0x95 | 0x8D | 0x9D | 0x99 | 0x81 | 0x91 => self.sta(&instr.addressing_mode),
-                // STX
-                0x86 | 0x96 | 0x8E => self.stx(&instr.addressing_mode),
-                // STY
-                0x84 | 0x94 | 0x8C => self.sty(&instr.addressing_mode),
-
-                // ASL
-                0x0A | 0x06 | 0x16 | 0x0E | 0x1E => self.asl(&instr.addressing_mode),
-                // LSR
-                0x4A | 0x46 | 0x56 | 0x4E | 0x5E => self.lsr(&instr.addressing_mode),
-                // ROL
-                0x2A | 0x26 | 0x36 | 0x2E | 0x3E => self.rol(&instr.addressing_mode),
-                // ROR
-                0x6A | 0x66 | 0x76 | 0x6E | 0x7E => self.ror(&instr.addressing_mode),
-
-                // PHA
-                0x48 => self.pha(),
-                // PLA
-                0x68 => self.pla(),
-
-                // PHP
-                0x08 => self.php(),
-                // PLP
-                0x28 => self.plp(),
-
-                // AND
-                0x29 | 0x25 | 0x35 | 0x2D | 0x3D | 0x39 | 0x21 | 0x31 => {
-                    self.and(&instr.addressing_mode)
-                }
-                // ORA
-                0x09 | 0x05 | 0x15 | 0x0D | 0x1D | 0x19 | 0x01 | 0x11 => {
-                    self.ora(&instr.addressing_mode)
-                }
-                // EOR
-                0x49 | 0x45 | 0x55 | 0x4D | 0x5D | 0x59 | 0x41 | 0x51 => {
-                    self.eor(&instr.addressing_mode)
-                }
+        bus.begin_instruction(self.pc);
+        let opcode = self.read_as(bus, self.pc, ReadKind::Opcode);
+        let (Some(instr), Some(handler)) = (
+            opcode::lookup(opcode),
+            Dispatch::<B>::TABLE[opcode as usize],
+        ) else {
+            self.halt(opcode, self.pc);
+            return false;
+        };
+        self.pc = self.pc.wrapping_add(1);
+        let program_counter_old = self.pc;
+
+        // Every cycle is a bus access. Instructions without an operand read
+        // the byte after the opcode anyway
+        if instr.bytes == 1 {
+            self.dummy_read(bus, self.pc);
+        }
 
-                // BPL
-                0x10 => self.bpl(),
-                // BMI
-                0x30 => self.bmi(),
-                // BVC
-                0x50 => self.bvc(),
-                // BVS
-                0x70 => self.bvs(),
-                // BCC
-                0x90 => self.bcc(),
-                // BCS
-                0xB0 => self.bcs(),
-                // BNE
-                0xD0 => self.bne(),
-                // BEQ
-                0xF0 => self.beq(),
-
-                // CMP
-                0xC9 | 0xC5 | 0xD5 | 0xCD | 0xDD | 0xD9 | 0xC1 | 0xD1 => {
-                    self.cmp(&instr.addressing_mode)
-                }
-                // CPX
-                0xE0 | 0xE4 | 0xEC => self.cpx(&instr.addressing_mode),
-                // CPY
-                0xC0 | 0xC4 | 0xCC => self.cpy(&instr.addressing_mode),
-
-                // INC
-                0xE6 | 0xF6 | 0xEE | 0xFE => self.inc(&instr.addressing_mode),
-                // INX
-                0xE8 => self.inx(),
-                // INY
-                0xC8 => self.iny(),
-
-                // DEC
-                0xC6 | 0xD6 | 0xCE | 0xDE => self.dec(&instr.addressing_mode),
-                // DEX
-                0xCA => self.dex(),
-                // DEY
-                0x88 => self.dey(),
-
-                // JMP
-                0x4C | 0x6C => self.jmp(&instr.addressing_mode),
-                // JSR
-                0x20 => self.jsr(),
-                // RTI
-                0x40 => self.rti(),
-                // RTS
-                0x60 => self.rts(),
-
-                // ADC
-                0x69 | 0x65 | 0x75 | 0x6D | 0x7D | 0x79 | 0x61 | 0x71 => {
-                    self.adc(&instr.addressing_mode)
-                }
-                // SBC
-                0xE9 | 0xE5 | 0xF5 | 0xED | 0xFD | 0xF9 | 0xE1 | 0xF1 => {
-                    self.sbc(&instr.addressing_mode)
-                }
+        handler(self, bus, &instr.addressing_mode);
 
-                _ => unimplemented!(),
-            }
+        if self.pc == program_counter_old {
+            self.pc = self.pc.wrapping_add((instr.bytes - 1) as u16);
+        }
 
-            if self.pc == program_counter_old {
-                self.pc += (instr.bytes - 1) as u16;
-            }
+        instr.code != 0x00 && self.halted.is_none()
+    }
+}
 
-            callback(self);
+impl Snapshot for Cpu {
+    fn save(&self, section: &mut Section) {
+        section.put_u8("a", self.register_a);
+        section.put_u8("x", self.register_x);
+        section.put_u8("y", self.register_y);
+        section.put_u8("sp", self.stackptr.rel_addr());
+        section.put_u8("status", self.status.bits);
+        section.put_u16("pc", self.pc);
+        section.put_bool("nmi_line", self.nmi_line);
+        section.put_bool("nmi_pending", self.nmi_pending);
+        section.put_bool("prev_nmi_pending", self.prev_nmi_pending);
+        section.put_bool("irq_pending", self.irq_pending);
+        section.put_bool("prev_irq_pending", self.prev_irq_pending);
+        // Only there when halted, so states and hashes of a running CPU
+        // stay as they were
+        if let Some(halt) = self.halted {
+            section.put_u8("halted_on", halt.opcode);
         }
     }
+
+    fn load(&mut self, section: &Section) -> Result<(), String> {
+        self.register_a = section.get_u8("a")?;
+        self.register_x = section.get_u8("x")?;
+        self.register_y = section.get_u8("y")?;
+        self.stackptr.set(section.get_u8("sp")?);
+        self.status = CpuFlag::from_bits_truncate(section.get_u8("status")?);
+        self.pc = section.get_u16("pc")?;
+        // Older states have none of these. A high line is assumed so an NMI
+        // already under way isn't taken a second time
+        self.nmi_line = section.get_bool("nmi_line").unwrap_or(true);
+        self.nmi_pending = section.get_bool("nmi_pending").unwrap_or(false);
+        self.prev_nmi_pending = section.get_bool("prev_nmi_pending").unwrap_or(false);
+        self.irq_pending = section.get_bool("irq_pending").unwrap_or(false);
+        self.prev_irq_pending = section.get_bool("prev_irq_pending").unwrap_or(false);
+        // A halted CPU stays on the opcode it stopped on
+        self.halted = section.get_u8("halted_on").ok().map(|opcode| Halt {
+            opcode,
+            pc: self.pc,
+        });
+        Ok(())
+    }
 }
 
 // OpCodes interpretation
 impl Cpu {
     // Bit Test
-    fn bit(&mut self, mode: &AddressingMode) {
-        let addr = self.get_address(mode);
-        let value = self.mem_read(addr);
+    fn bit<B: CpuBus>(&mut self, bus: &mut B, mode: &AddressingMode) {
+        let value = self.read_value(bus, mode);
 
         self.status.set(CpuFlag::ZERO, value & self.register_a == 0);
         self.status
@@ -456,45 +559,42 @@ impl Cpu {
     }
 
     // Load Accumulator
-    fn lda(&mut self, mode: &AddressingMode) {
-        let addr = self.get_address(mode);
-        let value = self.mem_read(addr);
+    fn lda<B: CpuBus>(&mut self, bus: &mut B, mode: &AddressingMode) {
+        let value = self.read_value(bus, mode);
 
         self.set_register_a(value);
     }
     // Load X Register
-    fn ldx(&mut self, mode: &AddressingMode) {
-        let addr = self.get_address(mode);
-        let value = self.mem_read(addr);
+    fn ldx<B: CpuBus>(&mut self, bus: &mut B, mode: &AddressingMode) {
+        let value = self.read_value(bus, mode);
 
         self.set_register_x(value);
     }
     // Load Y Register
-    fn ldy(&mut self, mode: &AddressingMode) {
-        let addr = self.get_address(mode);
-        let value = self.mem_read(addr);
+    fn ldy<B: CpuBus>(&mut self, bus: &mut B, mode: &AddressingMode) {
+        let value = self.read_value(bus, mode);
 
         self.set_register_y(value);
     }
 
     // Store Accumulator
-    fn sta(&mut self, mode: &AddressingMode) {
-        let addr = self.get_address(mode);
-        self.mem_write(addr, self.register_a);
+    fn sta<B: CpuBus>(&mut self, bus: &mut B, mode: &AddressingMode) {
+        let addr = self.get_write_address(bus, mode);
+        self.write(bus, addr, self.register_a);
     }
     // Store X Register
-    fn stx(&mut self, mode: &AddressingMode) {
-        let addr = self.get_address(mode);
-        self.mem_write(addr, self.register_x);
+    fn stx<B: CpuBus>(&mut self, bus: &mut B, mode: &AddressingMode) {
+        let addr = self.get_write_address(bus, mode);
+        self.write(bus, addr, self.register_x);
     }
     // Store Y Register
-    fn sty(&mut self, mode: &AddressingMode) {
-        let addr = self.get_address(mode);
-        self.mem_write(addr, self.register_y);
+    fn sty<B: CpuBus>(&mut self, bus: &mut B, mode: &AddressingMode) {
+        let addr = self.get_write_address(bus, mode);
+        self.write(bus, addr, self.register_y);
     }
 
     // Arithmetic Shift Left
-    fn asl(&mut self, mode: &AddressingMode) {
+    fn asl<B: CpuBus>(&mut self, bus: &mut B, mode: &AddressingMode) {
         match mode {
             AddressingMode::NoneAddressing => {
                 let mut value = self.register_a;
@@ -504,18 +604,20 @@ impl Cpu {
                 self.set_register_a(value);
             }
             _ => {
-                let addr = self.get_address(mode);
-                let mut value = self.mem_read(addr);
+                let addr = self.get_write_address(bus, mode);
+                let mut value = self.read(bus, addr);
+                // Written back unchanged before the result
+                self.write(bus, addr, value);
                 self.status.set(CpuFlag::CARRY, value >> 7 == 1);
 
                 value <<= 1;
-                self.mem_write(addr, value);
+                self.write(bus, addr, value);
                 self.update_zero_and_negative_flags(value);
             }
         }
     }
     // Logical Shift Right
-    fn lsr(&mut self, mode: &AddressingMode) {
+    fn lsr<B: CpuBus>(&mut self, bus: &mut B, mode: &AddressingMode) {
         match mode {
             AddressingMode::NoneAddressing => {
                 // self.set_register_a(self.register_a >> 1);
@@ -526,18 +628,20 @@ impl Cpu {
                 self.set_register_a(value);
             }
             _ => {
-                let addr = self.get_address(mode);
-                let mut value = self.mem_read(addr);
+                let addr = self.get_write_address(bus, mode);
+                let mut value = self.read(bus, addr);
+                // Written back unchanged before the result
+                self.write(bus, addr, value);
                 self.status.set(CpuFlag::CARRY, value & 1 == 1);
 
                 value >>= 1;
-                self.mem_write(addr, value);
+                self.write(bus, addr, value);
                 self.update_zero_and_negative_flags(value);
             }
         }
     }
     // Rotate left
-    fn rol(&mut self, mode: &AddressingMode) {
+    fn rol<B: CpuBus>(&mut self, bus: &mut B, mode: &AddressingMode) {
         match mode {
             AddressingMode::NoneAddressing => {
                 let mut value = self.register_a;
@@ -554,8 +658,10 @@ impl Cpu {
                 self.set_register_a(value);
             }
             _ => {
-                let addr = self.get_address(mode);
-                let mut value = self.mem_read(addr);
+                let addr = self.get_write_address(bus, mode);
+                let mut value = self.read(bus, addr);
+                // Written back unchanged before the result
+                self.write(bus, addr, value);
                 let old_carry = self.status.contains(CpuFlag::CARRY);
 
                 self.status.set(CpuFlag::CARRY, value >> 7 == 1);
@@ -565,13 +671,13 @@ impl Cpu {
                     value |= 1;
                 }
 
-                self.mem_write(addr, value);
+                self.write(bus, addr, value);
                 self.update_zero_and_negative_flags(value);
             }
         }
     }
     // Rotate right
-    fn ror(&mut self, mode: &AddressingMode) {
+    fn ror<B: CpuBus>(&mut self, bus: &mut B, mode: &AddressingMode) {
         match mode {
             AddressingMode::NoneAddressing => {
                 let mut value = self.register_a;
@@ -587,8 +693,10 @@ impl Cpu {
                 self.set_register_a(value);
             }
             _ => {
-                let addr = self.get_address(mode);
-                let mut value = self.mem_read(addr);
+                let addr = self.get_write_address(bus, mode);
+                let mut value = self.read(bus, addr);
+                // Written back unchanged before the result
+                self.write(bus, addr, value);
                 let old_carry = self.status.contains(CpuFlag::CARRY);
 
                 self.status.set(CpuFlag::CARRY, value & 1 == 1);
@@ -598,126 +706,107 @@ impl Cpu {
                     value |= 0b1000_0000;
                 }
 
-                self.mem_write(addr, value);
+                self.write(bus, addr, value);
                 self.update_zero_and_negative_flags(value);
             }
         }
     }
 
     // Push Accumulator
-    fn pha(&mut self) {
-        self.stack_push(self.register_a);
+    fn pha<B: CpuBus>(&mut self, bus: &mut B) {
+        self.stack_push(bus, self.register_a);
     }
     // Pull Accumulator
-    fn pla(&mut self) {
-        let value = self.stack_pop();
+    fn pla<B: CpuBus>(&mut self, bus: &mut B) {
+        self.dummy_read(bus, self.stackptr.addr());
+        let value = self.stack_pop(bus);
         self.set_register_a(value);
     }
 
     // Push Processor Status
-    fn php(&mut self) {
+    fn php<B: CpuBus>(&mut self, bus: &mut B) {
         let mut flags = self.status;
         flags.insert(CpuFlag::BREAK);
         flags.insert(CpuFlag::BREAK2);
-        self.stack_push(flags.bits());
+        self.stack_push(bus, flags.bits());
     }
-    // Pull Processor Status
-    fn plp(&mut self) {
-        self.status.bits = self.stack_pop();
+    // Pull Processor Status. P changes on the last cycle, too late for the
+    // interrupt poll, so a new I takes effect one instruction later
+    fn plp<B: CpuBus>(&mut self, bus: &mut B) {
+        self.dummy_read(bus, self.stackptr.addr());
+        self.status.bits = self.stack_pop(bus);
         self.clear_flag(CpuFlag::BREAK);
         self.set_flag(CpuFlag::BREAK2);
     }
 
     // Logical AND
-    fn and(&mut self, mode: &AddressingMode) {
-        let addr = self.get_address(mode);
-        let value = self.mem_read(addr);
+    fn and<B: CpuBus>(&mut self, bus: &mut B, mode: &AddressingMode) {
+        let value = self.read_value(bus, mode);
         self.set_register_a(self.register_a & value);
     }
     // Logical Inclusive OR
-    fn ora(&mut self, mode: &AddressingMode) {
-        let addr = self.get_address(mode);
-        let value = self.mem_read(addr);
+    fn ora<B: CpuBus>(&mut self, bus: &mut B, mode: &AddressingMode) {
+        let value = self.read_value(bus, mode);
         self.set_register_a(self.register_a | value);
     }
     // Exclusive OR
-    fn eor(&mut self, mode: &AddressingMode) {
-        let addr = self.get_address(mode);
-        let value = self.mem_read(addr);
+    fn eor<B: CpuBus>(&mut self, bus: &mut B, mode: &AddressingMode) {
+        let value = self.read_value(bus, mode);
         self.set_register_a(self.register_a ^ value);
     }
 
     // Branch if Positive
-    fn bpl(&mut self) {
-        if !self.status.contains(CpuFlag::NEGATIVE) {
-            self.branch();
-        }
+    fn bpl<B: CpuBus>(&mut self, bus: &mut B) {
+        self.branch(bus, !self.status.contains(CpuFlag::NEGATIVE));
     }
     // Branch if Minus
-    fn bmi(&mut self) {
-        if self.status.contains(CpuFlag::NEGATIVE) {
-            self.branch();
-        }
+    fn bmi<B: CpuBus>(&mut self, bus: &mut B) {
+        self.branch(bus, self.status.contains(CpuFlag::NEGATIVE));
     }
     // Branch if Overflow Clear
-    fn bvc(&mut self) {
-        if !self.status.contains(CpuFlag::OVERFLOW) {
-            self.branch();
-        }
+    fn bvc<B: CpuBus>(&mut self, bus: &mut B) {
+        self.branch(bus, !self.status.contains(CpuFlag::OVERFLOW));
     }
     // Branch if Overflow Set
-    fn bvs(&mut self) {
-        if self.status.contains(CpuFlag::OVERFLOW) {
-            self.branch();
-        }
+    fn bvs<B: CpuBus>(&mut self, bus: &mut B) {
+        self.branch(bus, self.status.contains(CpuFlag::OVERFLOW));
     }
     // Branch if Carry Clear
-    fn bcc(&mut self) {
-        if !self.status.contains(CpuFlag::CARRY) {
-            self.branch();
-        }
+    fn bcc<B: CpuBus>(&mut self, bus: &mut B) {
+        self.branch(bus, !self.status.contains(CpuFlag::CARRY));
     }
     // Branch if Carry Set
-    fn bcs(&mut self) {
-        if self.status.contains(CpuFlag::CARRY) {
-            self.branch();
-        }
+    fn bcs<B: CpuBus>(&mut self, bus: &mut B) {
+        self.branch(bus, self.status.contains(CpuFlag::CARRY));
     }
     // Branch if Not Equal
-    fn bne(&mut self) {
-        if !self.status.contains(CpuFlag::ZERO) {
-            self.branch();
-        }
+    fn bne<B: CpuBus>(&mut self, bus: &mut B) {
+        self.branch(bus, !self.status.contains(CpuFlag::ZERO));
     }
     // Branch if Equal
-    fn beq(&mut self) {
-        if self.status.contains(CpuFlag::ZERO) {
-            self.branch();
-        }
+    fn beq<B: CpuBus>(&mut self, bus: &mut B) {
+        self.branch(bus, self.status.contains(CpuFlag::ZERO));
     }
 
     // Compare
-    fn cmp(&mut self, mode: &AddressingMode) {
-        let addr = self.get_address(mode);
-        let value = self.mem_read(addr);
+    fn cmp<B: CpuBus>(&mut self, bus: &mut B, mode: &AddressingMode) {
+        let value = self.read_value(bus, mode);
 
         self.status.set(CpuFlag::CARRY, self.register_a >= value);
 
         self.update_zero_and_negative_flags(self.register_a.wrapping_sub(value));
     }
     // Compare X Register
-    fn cpx(&mut self, mode: &AddressingMode) {
-        let addr = self.get_address(mode);
-        let value = self.mem_read(addr);
+    fn cpx<B: CpuBus>(&mut self, bus: &mut B, mode: &AddressingMode) {
+        let value = self.read_value(bus, mode);
 
         self.status.set(CpuFlag::CARRY, self.register_x >= value);
 
         self.update_zero_and_negative_flags(self.register_x.wrapping_sub(value));
     }
     // Compare Y Register
-    fn cpy(&mut self, mode: &AddressingMode) {
-        let addr = self.get_address(mode);
-        let value = self.mem_read(addr);
+    fn cpy<B: CpuBus>(&mut self, bus: &mut B, mode: &AddressingMode) {
+        let value = self.read_value(bus, mode);
 
         self.status.set(CpuFlag::CARRY, self.register_y >= value);
 
@@ -725,11 +814,13 @@ impl Cpu {
     }
 
     // Increment Memory
-    fn inc(&mut self, mode: &AddressingMode) {
-        let addr = self.get_address(mode);
-        let value = self.mem_read(addr).wrapping_add(1);
+    fn inc<B: CpuBus>(&mut self, bus: &mut B, mode: &AddressingMode) {
+        let addr = self.get_write_address(bus, mode);
+        let value = self.read(bus, addr);
+        self.write(bus, addr, value);
 
-        self.mem_write(addr, value);
+        let value = value.wrapping_add(1);
+        self.write(bus, addr, value);
         self.update_zero_and_negative_flags(value);
     }
     // Increment X Register
@@ -742,11 +833,13 @@ impl Cpu {
     }
 
     // Decrement Memory
-    fn dec(&mut self, mode: &AddressingMode) {
-        let addr = self.get_address(mode);
-        let value = self.mem_read(addr).wrapping_sub(1);
+    fn dec<B: CpuBus>(&mut self, bus: &mut B, mode: &AddressingMode) {
+        let addr = self.get_write_address(bus, mode);
+        let value = self.read(bus, addr);
+        self.write(bus, addr, value);
 
-        self.mem_write(addr, value);
+        let value = value.wrapping_sub(1);
+        self.write(bus, addr, value);
         self.update_zero_and_negative_flags(value);
     }
     // Decrement X Register
@@ -759,48 +852,61 @@ impl Cpu {
     }
 
     // Jump
-    fn jmp(&mut self, mode: &AddressingMode) {
+    fn jmp<B: CpuBus>(&mut self, bus: &mut B, mode: &AddressingMode) {
         match mode {
             AddressingMode::Absolute => {
-                let addr = self.mem_read_u16(self.pc);
+                let addr = self.read_u16(bus, self.pc, ReadKind::Operand);
                 self.pc = addr;
             }
             AddressingMode::NoneAddressing => {
-                let addr = self.mem_read_u16(self.pc);
+                let addr = self.read_u16(bus, self.pc, ReadKind::Operand);
 
                 // 6502 does not correctly fetch the target address if indirect vector falls on a page boundary
                 // (e.g. $xxFF where xx is any value from $00 to $FF). In this case it fetches the LSB from $xxFF as expected
                 // but takes the MSB from $xx00. Fixed in some later chips.
                 let indirect_ref = if addr & 0x00FF == 0x00FF {
-                    let lo = self.mem_read(addr);
-                    let hi = self.mem_read(addr & 0xFF00);
+                    let lo = self.read(bus, addr);
+                    let hi = self.read(bus, addr & 0xFF00);
                     (hi as u16) << 8 | (lo as u16)
                 } else {
-                    self.mem_read_u16(addr)
+                    self.read_u16(bus, addr, ReadKind::Data)
                 };
 
+                bus.log_indirect_code(indirect_ref);
                 self.pc = indirect_ref;
             }
             _ => unreachable!(),
         }
     }
     // Jump to Subroutine
-    fn jsr(&mut self) {
-        self.stack_push_u16(self.pc + 2 - 1);
-        let addr = self.mem_read_u16(self.pc);
-        self.pc = addr;
+    // The high byte of the target is read after the return address is pushed
+    fn jsr<B: CpuBus>(&mut self, bus: &mut B) {
+        let lo = self.fetch(bus, self.pc);
+        self.dummy_read(bus, self.stackptr.addr());
+        self.stack_push_u16(bus, self.pc.wrapping_add(1));
+        let hi = self.fetch(bus, self.pc.wrapping_add(1));
+        self.pc = u16::from_le_bytes([lo, hi]);
+    }
+    // Force Interrupt, the byte after the opcode is skipped
+    fn brk<B: CpuBus>(&mut self, bus: &mut B) {
+        self.pc = self.pc.wrapping_add(1);
+        self.interrupt_sequence(bus, true);
     }
     // Return from Interrupt
-    fn rti(&mut self) {
-        self.status.bits = self.stack_pop();
+    fn rti<B: CpuBus>(&mut self, bus: &mut B) {
+        self.dummy_read(bus, self.stackptr.addr());
+        self.status.bits = self.stack_pop(bus);
         self.clear_flag(CpuFlag::BREAK);
         self.set_flag(CpuFlag::BREAK2);
 
-        self.pc = self.stack_pop_u16();
+        self.pc = self.stack_pop_u16(bus);
     }
     // Return from Subroutine
-    fn rts(&mut self) {
-        self.pc = self.stack_pop_u16() + 1;
+    fn rts<B: CpuBus>(&mut self, bus: &mut B) {
+        self.dummy_read(bus, self.stackptr.addr());
+        let addr = self.stack_pop_u16(bus);
+        self.dummy_read(bus, addr);
+        self.pc = addr.wrapping_add(1);
     }
 
     fn add_to_register_a(&mut self, value: u8) {
@@ -828,15 +934,164 @@ impl Cpu {
         self.set_register_a(result);
     }
 
-    fn sbc(&mut self, mode: &AddressingMode) {
-        let addr = self.get_address(mode);
-        let value = self.mem_read(addr);
-        self.add_to_register_a(((value as i8).wrapping_neg().wrapping_sub(1)) as u8);
+    fn decimal_mode(&self) -> bool {
+        self.variant == CpuVariant::Nmos6502 && self.status.contains(CpuFlag::DECIMAL_MODE)
+    }
+
+    // NMOS decimal mode gets the carry right, Z comes from the binary sum and
+    // N and V from the sum after only the low digit is adjusted
+    // http://www.6502.org/tutorials/decimal_mode.html#A
+    fn add_decimal(&mut self, value: u8) {
+        let a = self.register_a;
+        let carry = self.status.contains(CpuFlag::CARRY) as i16;
+
+        let mut lo = (a & 0x0F) as i16 + (value & 0x0F) as i16 + carry;
+        if lo >= 0x0A {
+            lo = ((lo + 0x06) & 0x0F) + 0x10;
+        }
+        let signed = (a & 0xF0) as i8 as i16 + (value & 0xF0) as i8 as i16 + lo;
+        let mut sum = (a & 0xF0) as i16 + (value & 0xF0) as i16 + lo;
+        if sum >= 0xA0 {
+            sum += 0x60;
+        }
+
+        let binary = a.wrapping_add(value).wrapping_add(carry as u8);
+        self.status.set(CpuFlag::ZERO, binary == 0);
+        self.status.set(CpuFlag::NEGATIVE, signed & 0x80 != 0);
+        self.status
+            .set(CpuFlag::OVERFLOW, !(-128..=127).contains(&signed));
+        self.status.set(CpuFlag::CARRY, sum >= 0x100);
+        self.register_a = sum as u8;
+    }
+
+    // Flags are all those of the binary subtraction
+    fn subtract_decimal(&mut self, value: u8) {
+        let a = self.register_a;
+        let borrow = !self.status.contains(CpuFlag::CARRY) as i16;
+        self.add_to_register_a(!value);
+
+        let mut lo = (a & 0x0F) as i16 - (value & 0x0F) as i16 - borrow;
+        if lo < 0 {
+            lo = ((lo - 0x06) & 0x0F) - 0x10;
+        }
+        let mut difference = (a & 0xF0) as i16 - (value & 0xF0) as i16 + lo;
+        if difference < 0 {
+            difference -= 0x60;
+        }
+        self.register_a = difference as u8;
+    }
+
+    fn sbc<B: CpuBus>(&mut self, bus: &mut B, mode: &AddressingMode) {
+        let value = self.read_value(bus, mode);
+        if self.decimal_mode() {
+            self.subtract_decimal(value);
+        } else {
+            self.add_to_register_a(((value as i8).wrapping_neg().wrapping_sub(1)) as u8);
+        }
+    }
+
+    fn adc<B: CpuBus>(&mut self, bus: &mut B, mode: &AddressingMode) {
+        let value = self.read_value(bus, mode);
+        if self.decimal_mode() {
+            self.add_decimal(value);
+        } else {
+            self.add_to_register_a(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LDA #$01, then $02 which jams a 6502
+    fn jammed() -> (Cpu, FlatRam) {
+        let mut bus = FlatRam::new();
+        bus.load(0x8000, &[0xA9, 0x01, 0x02, 0xEA]);
+        bus.load(0xFFFC, &[0x00, 0x80]);
+        let mut cpu = Cpu::new();
+        cpu.reset(&mut bus);
+        (cpu, bus)
+    }
+
+    #[test]
+    fn unknown_opcode_halts() {
+        let (mut cpu, mut bus) = jammed();
+        assert!(cpu.step(&mut bus));
+        assert!(!cpu.step(&mut bus));
+        let halt = Halt {
+            opcode: 0x02,
+            pc: 0x8002,
+        };
+        assert_eq!(cpu.halted(), Some(halt));
+        assert_eq!(halt.describe(), "CPU halted on opcode $02 at $8002");
+
+        // Time goes on, the CPU stays put until reset
+        let cycles = bus.cycles();
+        assert!(!cpu.step(&mut bus));
+        assert_eq!(bus.cycles(), cycles + 1);
+        assert_eq!(cpu.pc(), 0x8002);
+        cpu.reset(&mut bus);
+        assert_eq!(cpu.halted(), None);
+        assert!(cpu.step(&mut bus));
+    }
+
+    // Every opcode the CPU knows has a handler, the others halt in `step`
+    #[test]
+    fn known_opcodes_have_handlers() {
+        for code in 0..=255u8 {
+            assert_eq!(
+                opcode::lookup(code).is_some(),
+                Dispatch::<FlatRam>::TABLE[code as usize].is_some(),
+                "opcode {:02X}",
+                code
+            );
+        }
     }
 
-    fn adc(&mut self, mode: &AddressingMode) {
-        let addr = self.get_address(mode);
-        let value = self.mem_read(addr);
-        self.add_to_register_a(value);
+    // Taking an NMI is a step of its own, the handler starts on the next one
+    #[test]
+    fn interrupt_entry_is_a_step() {
+        let mut bus = FlatRam::new();
+        bus.load(0x8000, &[0xEA, 0xEA]);
+        bus.load(0x9000, &[0xA2, 0x05]);
+        bus.load(0xFFFA, &[0x00, 0x90]);
+        bus.load(0xFFFC, &[0x00, 0x80]);
+        let mut cpu = Cpu::new();
+        cpu.reset(&mut bus);
+
+        bus.nmi = true;
+        assert!(!cpu.interrupt_pending());
+        cpu.step(&mut bus);
+        assert!(cpu.interrupt_pending());
+
+        let cycles = bus.cycles();
+        assert!(cpu.step(&mut bus));
+        assert_eq!(cpu.pc(), 0x9000);
+        assert_eq!(bus.cycles(), cycles + 7);
+        assert!(!cpu.interrupt_pending());
+
+        cpu.step(&mut bus);
+        assert_eq!(cpu.pc(), 0x9002);
+        assert_eq!(cpu.registers().x, 0x05);
+    }
+
+    #[test]
+    fn halt_is_saved() {
+        let (mut cpu, mut bus) = jammed();
+        cpu.step(&mut bus);
+        cpu.step(&mut bus);
+        let mut section = Section::new();
+        cpu.save(&mut section);
+
+        let mut loaded = Cpu::new();
+        Snapshot::load(&mut loaded, &section).unwrap();
+        assert_eq!(loaded.halted(), cpu.halted());
+
+        cpu.reset(&mut bus);
+        let mut section = Section::new();
+        cpu.save(&mut section);
+        Snapshot::load(&mut loaded, &section).unwrap();
+        assert_eq!(loaded.halted(), None);
     }
 }
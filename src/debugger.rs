@@ -0,0 +1,790 @@
+use std::{
+    io::{self, BufRead, Write},
+    ops::RangeInclusive,
+    path::Path,
+};
+
+use crate::{
+    asm,
+    cpu::{opcode, CpuBus, CpuFlag},
+    disasm::{self, Symbols},
+    ramsearch::{Filter, RamSearch, ValueType},
+    watch::{WatchAction, WatchKind, Watchpoint},
+    Nes,
+};
+
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+const RTI: u8 = 0x40;
+
+// There is no way to interrupt a run, so every run gives up after a minute
+const RUN_LIMIT_FRAMES: usize = 3600;
+
+const HELP: &str = "\
+s, step [n]            execute n instructions
+n, next                step over subroutine calls
+fin, finish            run until the current subroutine returns
+c, continue [frames]   run until a breakpoint, or for some frames (3600)
+sl, scanline N         run until the PPU starts scanline N (decimal)
+b, break ADDR          break when ADDR is executed
+bx RANGE               break on execute of ADDR or START-END
+br|bw|ba RANGE [VALUE] break on read/write/either, of VALUE only if given
+tr|tw|ta RANGE [VALUE] trace accesses like br/bw/ba, listed when a run stops
+bo OPCODE              break before executing OPCODE
+bl                     list breakpoints
+d [N]                  delete breakpoint N, or all of them
+r, regs                show registers
+set REG VALUE          set a, x, y, sp, p, pc, or flag n v d i z c to 0/1
+x ADDR [LEN]           dump memory
+w ADDR VALUE...        write RAM or PRG RAM, without advancing the clock
+a ADDR INSTRUCTION     assemble one instruction and write it like w
+l, dis [ADDR] [N]      disassemble N instructions around ADDR (PC)
+sym FILE               load labels from .nl, .dbg or `label = $addr` file
+cdl [start [FILE]|save FILE|stop]
+                       code/data logger, start adds to FILE if given
+ram new [TYPE]         search RAM and PRG RAM for u8 (default), s8, u16 or s16
+ram =|!=|<|> N         keep candidates compared to N, may be negative
+ram changed|same       keep candidates compared to the last search step
+ram inc|dec [N]        keep candidates that went up or down, by N if given
+ram                    list candidates
+reset                  press the reset button
+h, help                this text
+q, quit                exit
+Numbers are hex unless noted, with optional $ or 0x prefix. Addresses may
+also be labels";
+
+enum Breakpoint {
+    Execute(RangeInclusive<u16>),
+    Opcode(u8),
+    // Handed to the bus for the duration of a run
+    Watch(Watchpoint),
+}
+
+impl Breakpoint {
+    fn describe(&self) -> String {
+        let range = |kind: &str, range: &RangeInclusive<u16>| {
+            if range.start() == range.end() {
+                format!("{} ${:04X}", kind, range.start())
+            } else {
+                format!("{} ${:04X}-${:04X}", kind, range.start(), range.end())
+            }
+        };
+
+        match self {
+            Breakpoint::Execute(addrs) => range("execute", addrs),
+            Breakpoint::Opcode(opcode) => format!("opcode ${:02X}", opcode),
+            Breakpoint::Watch(watchpoint) => {
+                let kind = match watchpoint.kind {
+                    WatchKind::Read => "read",
+                    WatchKind::Write => "write",
+                    WatchKind::Access => "access",
+                };
+                let mut text = range(kind, &watchpoint.range);
+                if let Some(value) = watchpoint.value {
+                    text += &format!(" = ${:02X}", value);
+                }
+                match watchpoint.action {
+                    WatchAction::Log => format!("trace {}", text),
+                    WatchAction::Break => text,
+                }
+            }
+        }
+    }
+}
+
+// Interactive debugger over stdin/stdout
+pub struct Debugger {
+    nes: Nes,
+    breakpoints: Vec<Breakpoint>,
+    symbols: Symbols,
+    search: Option<RamSearch>,
+}
+
+impl Debugger {
+    pub fn new(nes: Nes) -> Self {
+        Debugger {
+            nes,
+            breakpoints: Vec::new(),
+            symbols: Symbols::new(),
+            search: None,
+        }
+    }
+
+    pub fn into_nes(self) -> Nes {
+        self.nes
+    }
+
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        writeln!(output, "{}", self.status())?;
+
+        let mut lines = input.lines();
+        let mut last_line = String::new();
+        loop {
+            write!(output, "> ")?;
+            output.flush()?;
+
+            let Some(line) = lines.next() else {
+                return Ok(());
+            };
+            let mut line = line?;
+            // Empty line repeats the previous command
+            if line.trim().is_empty() {
+                line = last_line.clone();
+            }
+
+            let args: Vec<&str> = line.split_whitespace().collect();
+            match args.first() {
+                None => continue,
+                Some(&"q") | Some(&"quit") => return Ok(()),
+                Some(_) => match self.execute(&args) {
+                    Ok(text) => writeln!(output, "{}", text)?,
+                    Err(err) => writeln!(output, "Error: {}", err)?,
+                },
+            }
+            last_line = line;
+        }
+    }
+
+    fn execute(&mut self, args: &[&str]) -> Result<String, String> {
+        match args[0] {
+            "s" | "step" => {
+                let count = parse_count(args.get(1))?;
+                let mut executed = 0;
+                Ok(self.run_until(RUN_LIMIT_FRAMES, |_, _| {
+                    executed += 1;
+                    executed >= count
+                }))
+            }
+            "n" | "next" => {
+                // An interrupt taken first returns to the same PC and stack,
+                // the instruction there runs after it
+                let start = self.nes.cpu().registers();
+                let over_jsr = self.nes.read_memory(start.pc) == JSR;
+                let mut before = start;
+                let mut started = false;
+                Ok(self.run_until(RUN_LIMIT_FRAMES, |nes, opcode| {
+                    let now = nes.cpu().registers();
+                    if opcode.is_some() && before.pc == start.pc && before.sp == start.sp {
+                        started = true;
+                    }
+                    before = now;
+                    // Recursion comes back to the same address with a deeper stack
+                    started
+                        && (!over_jsr || now.pc == start.pc.wrapping_add(3) && now.sp == start.sp)
+                }))
+            }
+            "fin" | "finish" => {
+                let start_sp = self.nes.cpu().registers().sp;
+                Ok(self.run_until(RUN_LIMIT_FRAMES, |nes, opcode| {
+                    matches!(opcode, Some(RTS | RTI)) && nes.cpu().registers().sp > start_sp
+                }))
+            }
+            "c" | "continue" => {
+                let frames = match args.get(1) {
+                    Some(arg) => parse_decimal(arg)?,
+                    None => RUN_LIMIT_FRAMES,
+                };
+                Ok(self.run_until(frames, |_, _| false))
+            }
+            "sl" | "scanline" => {
+                let target = parse_decimal(args.get(1).ok_or("Scanline is required")?)? as u16;
+                let mut last = self.nes.bus().ppu().scanline();
+                Ok(self.run_until(RUN_LIMIT_FRAMES, |nes, _| {
+                    let scanline = nes.bus().ppu().scanline();
+                    let reached = scanline == target && last != target;
+                    last = scanline;
+                    reached
+                }))
+            }
+
+            "b" | "break" | "bx" => {
+                let range = parse_range(&self.symbols, args.get(1))?;
+                Ok(self.add_breakpoint(Breakpoint::Execute(range)))
+            }
+            "br" | "bw" | "ba" | "tr" | "tw" | "ta" => {
+                let range = parse_range(&self.symbols, args.get(1))?;
+                let value = args.get(2).map(|arg| parse_byte(arg)).transpose()?;
+                let kind = match &args[0][1..] {
+                    "r" => WatchKind::Read,
+                    "w" => WatchKind::Write,
+                    _ => WatchKind::Access,
+                };
+                let action = if args[0].starts_with('t') {
+                    WatchAction::Log
+                } else {
+                    WatchAction::Break
+                };
+                Ok(self.add_breakpoint(Breakpoint::Watch(Watchpoint {
+                    range,
+                    kind,
+                    value,
+                    action,
+                })))
+            }
+            "bo" => {
+                let opcode = parse_byte(args.get(1).ok_or("Opcode is required")?)?;
+                Ok(self.add_breakpoint(Breakpoint::Opcode(opcode)))
+            }
+            "bl" => Ok(self
+                .breakpoints
+                .iter()
+                .enumerate()
+                .map(|(i, breakpoint)| format!("{}: {}", i, breakpoint.describe()))
+                .collect::<Vec<_>>()
+                .join("\n")),
+            "d" => match args.get(1) {
+                Some(arg) => {
+                    let index = parse_decimal(arg)?;
+                    if index >= self.breakpoints.len() {
+                        return Err(format!("There is no breakpoint {}", index));
+                    }
+                    self.breakpoints.remove(index);
+                    Ok(format!("Deleted breakpoint {}", index))
+                }
+                None => {
+                    self.breakpoints.clear();
+                    Ok("Deleted all breakpoints".to_string())
+                }
+            },
+
+            "r" | "regs" => Ok(self.status()),
+            "set" => {
+                let name = args.get(1).ok_or("Register is required")?;
+                let value = parse_hex(args.get(2).ok_or("Value is required")?)?;
+                self.set_register(name, value)?;
+                Ok(self.registers_line())
+            }
+            "x" => {
+                let addr = parse_addr(&self.symbols, args.get(1).ok_or("Address is required")?)?;
+                let len = match args.get(2) {
+                    Some(arg) => parse_hex(arg)? as usize,
+                    None => 0x40,
+                };
+                Ok(self.hexdump(addr, len))
+            }
+            "w" => {
+                let addr = parse_addr(&self.symbols, args.get(1).ok_or("Address is required")?)?;
+                if args.len() < 3 {
+                    return Err("Values are required".to_string());
+                }
+                let values = args[2..].iter().map(|arg| {
+                    u8::try_from(parse_hex(arg)?).map_err(|_| "Values are bytes".to_string())
+                });
+                let values = values.collect::<Result<Vec<_>, _>>()?;
+                self.poke(addr, &values)?;
+                Ok(self.hexdump(addr, args.len() - 2))
+            }
+            "a" => {
+                let addr = parse_addr(&self.symbols, args.get(1).ok_or("Address is required")?)?;
+                let assembly = asm::assemble(&args[2..].join(" "), addr)?;
+                if assembly.bytes.is_empty() {
+                    return Err("Instruction is required".to_string());
+                }
+                self.poke(addr, &assembly.bytes)?;
+                Ok(self.disassemble(addr).line(Some(&self.symbols)))
+            }
+            "l" | "dis" => {
+                let addr = match args.get(1) {
+                    Some(arg) => parse_addr(&self.symbols, arg)?,
+                    None => self.nes.cpu().pc(),
+                };
+                let count = match args.get(2) {
+                    Some(arg) => parse_decimal(arg)?,
+                    None => 10,
+                };
+                Ok(self.disassemble_around(addr, count))
+            }
+            "sym" => {
+                let path = args.get(1).ok_or("File is required")?;
+                let symbols = Symbols::load(Path::new(path))?;
+                let count = symbols.len();
+                self.symbols = symbols;
+                Ok(format!("Loaded {} labels", count))
+            }
+            "cdl" => self.code_data_log(&args[1..]),
+            "ram" => self.ram_search(&args[1..]),
+            "reset" => {
+                self.nes.reset();
+                Ok(self.status())
+            }
+            "h" | "help" => Ok(HELP.to_string()),
+            command => Err(format!("Unknown command \"{}\", try help", command)),
+        }
+    }
+
+    fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> String {
+        let text = format!(
+            "Breakpoint {}: {}",
+            self.breakpoints.len(),
+            breakpoint.describe()
+        );
+        self.breakpoints.push(breakpoint);
+        text
+    }
+
+    fn ram_search(&mut self, args: &[&str]) -> Result<String, String> {
+        if let Some(&"new") = args.first() {
+            let value_type = ValueType::parse(args.get(1).unwrap_or(&"u8"))?;
+            let search = RamSearch::new(&self.nes, value_type);
+            let text = format!("{} candidates", search.candidates().len());
+            self.search = Some(search);
+            return Ok(text);
+        }
+
+        let search = self
+            .search
+            .as_mut()
+            .ok_or("No search yet, start one with ram new")?;
+        let number = |index: usize| -> Result<i32, String> {
+            parse_signed(args.get(index).ok_or("Number is required")?)
+        };
+        let filter = match args {
+            [] => {
+                const SHOWN: usize = 50;
+                let candidates = search.candidates();
+                let mut lines: Vec<String> = candidates
+                    .iter()
+                    .take(SHOWN)
+                    .map(|&addr| {
+                        format!(
+                            "${:04X}: {} (was {})",
+                            addr,
+                            search.value(&self.nes, addr),
+                            search.previous_value(addr)
+                        )
+                    })
+                    .collect();
+                if candidates.len() > SHOWN {
+                    lines.push(format!("... {} more", candidates.len() - SHOWN));
+                }
+                lines.push(format!("{} candidates", candidates.len()));
+                return Ok(lines.join("\n"));
+            }
+            ["=", ..] => Filter::Equal(number(1)?),
+            ["!=", ..] => Filter::NotEqual(number(1)?),
+            ["<", ..] => Filter::Less(number(1)?),
+            [">", ..] => Filter::Greater(number(1)?),
+            ["changed"] => Filter::Changed,
+            ["same"] => Filter::Unchanged,
+            ["inc"] => Filter::Increased,
+            ["dec"] => Filter::Decreased,
+            ["inc", _] => Filter::ChangedBy(number(1)?),
+            ["dec", _] => Filter::ChangedBy(-number(1)?),
+            _ => {
+                return Err(format!(
+                    "Unknown search step \"{}\", try help",
+                    args.join(" ")
+                ))
+            }
+        };
+
+        let left = search.filter(&self.nes, filter);
+        Ok(format!("{} candidates", left))
+    }
+
+    fn code_data_log(&mut self, args: &[&str]) -> Result<String, String> {
+        match args {
+            [] => {
+                let log = self.nes.code_data_log().ok_or("Code/data logger is off")?;
+                let (code, data, chr) = log.coverage();
+                Ok(format!(
+                    "PRG: {} of {} bytes code, {} data; CHR: {} of {} bytes used",
+                    code,
+                    log.prg.len(),
+                    data,
+                    chr,
+                    log.chr.len()
+                ))
+            }
+            ["start"] => {
+                self.nes.start_code_data_log(None)?;
+                Ok("Code/data logger started".to_string())
+            }
+            ["start", path] => {
+                self.nes.start_code_data_log(Some(Path::new(path)))?;
+                Ok(format!("Code/data logger continues {}", path))
+            }
+            ["save", path] => {
+                let log = self.nes.code_data_log().ok_or("Code/data logger is off")?;
+                log.save(Path::new(path))?;
+                Ok(format!("Saved {}", path))
+            }
+            ["stop"] => {
+                self.nes.stop_code_data_log();
+                Ok("Code/data logger stopped".to_string())
+            }
+            _ => Err("Usage: cdl [start [FILE]|save FILE|stop]".to_string()),
+        }
+    }
+
+    // Executes instructions until `done` says so, a breakpoint hits or `frames`
+    // frames complete. `done` sees the opcode just executed, none when the
+    // step entered an interrupt handler. Breakpoints are checked before every
+    // instruction, also the first one of a handler, except at the starting PC
+    // so continuing from one works
+    fn run_until<F>(&mut self, frames: usize, mut done: F) -> String
+    where
+        F: FnMut(&Nes, Option<u8>) -> bool,
+    {
+        let watchpoints = self.nes.parts_mut().1.watchpoints_mut();
+        for breakpoint in &self.breakpoints {
+            if let Breakpoint::Watch(watchpoint) = breakpoint {
+                watchpoints.add(watchpoint.clone());
+            }
+        }
+
+        let mut first = true;
+        let mut completed = 0;
+        let reason = loop {
+            let pc = self.nes.cpu().pc();
+            let opcode = if self.nes.cpu().interrupt_pending() {
+                None
+            } else {
+                Some(self.nes.read_memory(pc))
+            };
+
+            if let (false, Some(opcode)) = (first, opcode) {
+                let hit = self
+                    .breakpoints
+                    .iter()
+                    .position(|breakpoint| match breakpoint {
+                        Breakpoint::Execute(range) => range.contains(&pc),
+                        Breakpoint::Opcode(code) => *code == opcode,
+                        _ => false,
+                    });
+                if let Some(index) = hit {
+                    break format!("Breakpoint {} at ${:04X}", index, pc);
+                }
+            }
+            first = false;
+
+            if let Some(opcode) = opcode.filter(|&opcode| opcode::lookup(opcode).is_none()) {
+                break format!("Illegal opcode ${:02X} at ${:04X}", opcode, pc);
+            }
+
+            self.nes.step_instruction();
+            let (_, bus) = self.nes.parts_mut();
+            if bus.poll_frame_complete() {
+                completed += 1;
+            }
+
+            if let Some(hit) = bus.watchpoints_mut().take_break() {
+                let index = self.breakpoints.iter().position(|breakpoint| {
+                    matches!(breakpoint, Breakpoint::Watch(watchpoint)
+                        if watchpoint.action == WatchAction::Break
+                            && watchpoint.matches(hit.kind, hit.addr, hit.value))
+                });
+                break format!("Breakpoint {}: {}", index.unwrap_or(0), hit.describe());
+            }
+
+            if done(&self.nes, opcode) {
+                break String::new();
+            }
+            if completed >= frames {
+                break format!("Stopped after {} frames", completed);
+            }
+        };
+        let watchpoints = self.nes.parts_mut().1.watchpoints_mut();
+        watchpoints.clear();
+        let (hits, dropped) = watchpoints.take_hits();
+
+        let mut lines: Vec<String> = hits.iter().map(|hit| hit.describe()).collect();
+        if dropped > 0 {
+            lines.push(format!("{} more accesses were not kept", dropped));
+        }
+        if !reason.is_empty() {
+            lines.push(reason);
+        }
+        lines.push(self.status());
+        lines.join("\n")
+    }
+
+    fn registers_line(&self) -> String {
+        let registers = self.nes.cpu().registers();
+        let flags: String = [
+            (CpuFlag::NEGATIVE, 'n'),
+            (CpuFlag::OVERFLOW, 'v'),
+            (CpuFlag::BREAK2, '-'),
+            (CpuFlag::BREAK, 'b'),
+            (CpuFlag::DECIMAL_MODE, 'd'),
+            (CpuFlag::INTERRUPT_DISABLE, 'i'),
+            (CpuFlag::ZERO, 'z'),
+            (CpuFlag::CARRY, 'c'),
+        ]
+        .iter()
+        .map(|(flag, name)| {
+            if registers.p & flag.bits() != 0 {
+                name.to_ascii_uppercase()
+            } else {
+                *name
+            }
+        })
+        .collect();
+
+        let ppu = self.nes.bus().ppu();
+        format!(
+            "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} {} CYC:{} SL:{} DOT:{}",
+            registers.pc,
+            registers.a,
+            registers.x,
+            registers.y,
+            registers.p,
+            registers.sp,
+            flags,
+            self.nes.bus().cycles(),
+            ppu.scanline(),
+            ppu.dot()
+        )
+    }
+
+    // Registers and the instruction about to execute
+    fn status(&self) -> String {
+        let instruction = self.disassemble(self.nes.cpu().pc());
+        format!(
+            "{}\n{}",
+            self.registers_line(),
+            instruction.line(Some(&self.symbols))
+        )
+    }
+
+    fn set_register(&mut self, name: &str, value: u32) -> Result<(), String> {
+        let mut registers = self.nes.cpu().registers();
+        let byte = || u8::try_from(value).map_err(|_| format!("{} is one byte", name));
+        let flag = match name {
+            "n" => Some(CpuFlag::NEGATIVE),
+            "v" => Some(CpuFlag::OVERFLOW),
+            "d" => Some(CpuFlag::DECIMAL_MODE),
+            "i" => Some(CpuFlag::INTERRUPT_DISABLE),
+            "z" => Some(CpuFlag::ZERO),
+            "c" => Some(CpuFlag::CARRY),
+            _ => None,
+        };
+
+        match (name, flag) {
+            (_, Some(flag)) => {
+                let mut status = CpuFlag::from_bits_truncate(registers.p);
+                status.set(flag, value != 0);
+                registers.p = status.bits();
+            }
+            ("a", _) => registers.a = byte()?,
+            ("x", _) => registers.x = byte()?,
+            ("y", _) => registers.y = byte()?,
+            ("sp", _) => registers.sp = byte()?,
+            ("p", _) => registers.p = byte()?,
+            ("pc", _) => {
+                registers.pc = u16::try_from(value).map_err(|_| "pc is two bytes".to_string())?
+            }
+            _ => return Err(format!("Unknown register \"{}\"", name)),
+        }
+
+        self.nes.parts_mut().0.set_registers(registers);
+        Ok(())
+    }
+
+    // Stops at the first byte that can't be written
+    fn poke(&mut self, addr: u16, values: &[u8]) -> Result<(), String> {
+        let (_, bus) = self.nes.parts_mut();
+        for (i, value) in values.iter().enumerate() {
+            let addr = addr.wrapping_add(i as u16);
+            if !bus.poke(addr, *value) {
+                return Err(format!("No writable RAM at ${:04X}", addr));
+            }
+        }
+        Ok(())
+    }
+
+    fn hexdump(&self, addr: u16, len: usize) -> String {
+        let mut lines = Vec::new();
+        for line_start in (0..len).step_by(16) {
+            let line_addr = addr.wrapping_add(line_start as u16);
+            let bytes: Vec<String> = (line_start..len.min(line_start + 16))
+                .map(|i| format!("{:02X}", self.nes.read_memory(addr.wrapping_add(i as u16))))
+                .collect();
+            lines.push(format!("${:04X}: {}", line_addr, bytes.join(" ")));
+        }
+        lines.join("\n")
+    }
+
+    // Code can't be decoded backwards reliably, so a few lines before `addr`
+    // are taken from the earliest start that decodes right into it
+    fn disassemble_around(&self, addr: u16, count: usize) -> String {
+        let before = count / 2;
+        let mut start = addr;
+        for distance in (1..=before as u16 * 3).rev() {
+            let candidate = addr.wrapping_sub(distance);
+            let mut pc = candidate;
+            let mut lines = 0;
+            while pc != addr && lines < before {
+                let len = self.disassemble(pc).len();
+                // `addr` is in the middle of this instruction
+                if addr.wrapping_sub(pc) < len {
+                    break;
+                }
+                pc = pc.wrapping_add(len);
+                lines += 1;
+            }
+            if pc == addr {
+                start = candidate;
+                break;
+            }
+        }
+
+        let current = self.nes.cpu().pc();
+        let mut lines = Vec::new();
+        let instructions = disasm::disassemble(|addr| self.nes.read_memory(addr), start, count);
+        for instruction in instructions {
+            if let Some(label) = self.symbols.label(instruction.addr) {
+                lines.push(format!(" {}:", label));
+            }
+            let marker = if instruction.addr == current {
+                ">"
+            } else {
+                " "
+            };
+            lines.push(format!(
+                "{}{}",
+                marker,
+                instruction.line(Some(&self.symbols))
+            ));
+        }
+        lines.join("\n")
+    }
+
+    fn disassemble(&self, addr: u16) -> disasm::Instruction {
+        disasm::decode(|addr| self.nes.read_memory(addr), addr)
+    }
+}
+
+fn parse_hex(text: &str) -> Result<u32, String> {
+    let digits = text
+        .strip_prefix('$')
+        .or_else(|| text.strip_prefix("0x"))
+        .unwrap_or(text);
+    u32::from_str_radix(digits, 16).map_err(|_| format!("\"{}\" is not a hex number", text))
+}
+
+fn parse_addr(symbols: &Symbols, text: &str) -> Result<u16, String> {
+    if let Some(addr) = symbols.address(text) {
+        return Ok(addr);
+    }
+    u16::try_from(parse_hex(text)?).map_err(|_| format!("${} is not an address", text))
+}
+
+fn parse_range(symbols: &Symbols, text: Option<&&str>) -> Result<RangeInclusive<u16>, String> {
+    let text = text.ok_or("Address is required")?;
+    match text.split_once('-') {
+        Some((start, end)) => {
+            let (start, end) = (parse_addr(symbols, start)?, parse_addr(symbols, end)?);
+            if start > end {
+                return Err(format!("Range {} is empty", text));
+            }
+            Ok(start..=end)
+        }
+        None => {
+            let addr = parse_addr(symbols, text)?;
+            Ok(addr..=addr)
+        }
+    }
+}
+
+fn parse_byte(text: &str) -> Result<u8, String> {
+    u8::try_from(parse_hex(text)?).map_err(|_| format!("${} is not a byte", text))
+}
+
+fn parse_signed(text: &str) -> Result<i32, String> {
+    match text.strip_prefix('-') {
+        Some(digits) => Ok(-(parse_hex(digits)? as i32)),
+        None => Ok(parse_hex(text)? as i32),
+    }
+}
+
+fn parse_decimal(text: &str) -> Result<usize, String> {
+    text.parse()
+        .map_err(|_| format!("\"{}\" is not a decimal number", text))
+}
+
+fn parse_count(text: Option<&&str>) -> Result<usize, String> {
+    match text {
+        Some(text) => parse_decimal(text),
+        None => Ok(1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Main loop calls a subroutine, the NMI handler counts frames in X
+    const ROM: &str = "
+        reset: lda #$80
+               sta $2000
+        loop:  jsr sub
+               jmp loop
+        sub:   rts
+        nmi:   inx
+               rti";
+
+    fn debugger() -> Debugger {
+        let mut debugger = Debugger::new(Nes::new(&asm::test_rom(ROM)).unwrap());
+        debugger.symbols.add(0xC00C, "nmi");
+        debugger
+    }
+
+    fn x(debugger: &Debugger) -> u8 {
+        debugger.nes.cpu().registers().x
+    }
+
+    #[test]
+    fn breaks_on_interrupt_handler() {
+        let mut debugger = debugger();
+        debugger.execute(&["b", "nmi"]).unwrap();
+
+        let output = debugger.execute(&["c", "5"]).unwrap();
+        assert!(output.contains("Breakpoint 0 at $C00C"), "{}", output);
+        assert_eq!(x(&debugger), 0);
+        let output = debugger.execute(&["c", "5"]).unwrap();
+        assert!(output.contains("Breakpoint 0 at $C00C"), "{}", output);
+        assert_eq!(x(&debugger), 1);
+    }
+
+    #[test]
+    fn step_stops_on_handler_entry() {
+        let mut debugger = debugger();
+        while !debugger.nes.cpu().interrupt_pending() {
+            debugger.nes.step_instruction();
+        }
+        debugger.execute(&["s"]).unwrap();
+        assert_eq!(debugger.nes.cpu().pc(), 0xC00C);
+        assert_eq!(x(&debugger), 0);
+        debugger.execute(&["s"]).unwrap();
+        assert_eq!(x(&debugger), 1);
+    }
+
+    #[test]
+    fn next_steps_over_interrupt_then_instruction() {
+        let mut debugger = debugger();
+        while !debugger.nes.cpu().interrupt_pending() {
+            debugger.nes.step_instruction();
+        }
+        let start = debugger.nes.cpu().pc();
+        debugger.execute(&["n"]).unwrap();
+
+        // Stepping over the JSR or the RTS ends on the JMP, the JMP goes back
+        // to the JSR
+        let expected = if start == 0xC008 { 0xC005 } else { 0xC008 };
+        assert_eq!(debugger.nes.cpu().pc(), expected, "from ${:04X}", start);
+        assert_eq!(x(&debugger), 1);
+    }
+
+    #[test]
+    fn finish_leaves_interrupt_handler() {
+        let mut debugger = debugger();
+        debugger.execute(&["b", "nmi"]).unwrap();
+        debugger.execute(&["c"]).unwrap();
+        debugger.execute(&["d"]).unwrap();
+
+        debugger.execute(&["fin"]).unwrap();
+        assert!(debugger.nes.cpu().pc() < 0xC00C);
+        assert_eq!(x(&debugger), 1);
+    }
+}
@@ -0,0 +1,243 @@
+mod symbols;
+
+pub use symbols::Symbols;
+
+use crate::cpu::{
+    opcode::{self, OpCode},
+    AddressingMode,
+};
+
+// One decoded instruction, or a lone byte that is not a known opcode
+pub struct Instruction {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub opcode: Option<&'static OpCode>,
+}
+
+impl Instruction {
+    pub fn len(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    fn byte(&self) -> u8 {
+        self.bytes.get(1).copied().unwrap_or(0)
+    }
+    fn word(&self) -> u16 {
+        u16::from_le_bytes([self.byte(), self.bytes.get(2).copied().unwrap_or(0)])
+    }
+
+    // Address the operand points at, branch targets resolved
+    pub fn target(&self) -> Option<u16> {
+        let opcode = self.opcode?;
+        match (&opcode.addressing_mode, opcode.bytes) {
+            (AddressingMode::Immediate, _) => None,
+            (
+                AddressingMode::ZeroPage
+                | AddressingMode::ZeroPage_X
+                | AddressingMode::ZeroPage_Y
+                | AddressingMode::Indirect_X
+                | AddressingMode::Indirect_Y,
+                _,
+            ) => Some(self.byte() as u16),
+            (
+                AddressingMode::Absolute | AddressingMode::Absolute_X | AddressingMode::Absolute_Y,
+                _,
+            ) => Some(self.word()),
+            (AddressingMode::NoneAddressing, 1) => None,
+            // Branches are relative to the next instruction
+            (AddressingMode::NoneAddressing, 2) => Some(
+                self.addr
+                    .wrapping_add(2)
+                    .wrapping_add(self.byte() as i8 as u16),
+            ),
+            // JMP indirect
+            (AddressingMode::NoneAddressing, _) => Some(self.word()),
+        }
+    }
+
+    // Just the instruction, like "LDA ($10),Y" or "JSR reset"
+    pub fn text(&self, symbols: Option<&Symbols>) -> String {
+        let Some(opcode) = self.opcode else {
+            return format!(".byte ${:02X}", self.bytes[0]);
+        };
+
+        let label = self
+            .target()
+            .and_then(|target| symbols.and_then(|symbols| symbols.label(target)));
+        let zero_page = |label: Option<&str>| match label {
+            Some(label) => label.to_string(),
+            None => format!("${:02X}", self.byte()),
+        };
+        let absolute = |label: Option<&str>| match label {
+            Some(label) => label.to_string(),
+            None => format!("${:04X}", self.word()),
+        };
+
+        let operand = match (&opcode.addressing_mode, opcode.bytes) {
+            (AddressingMode::Immediate, _) => format!("#${:02X}", self.byte()),
+            (AddressingMode::ZeroPage, _) => zero_page(label),
+            (AddressingMode::ZeroPage_X, _) => format!("{},X", zero_page(label)),
+            (AddressingMode::ZeroPage_Y, _) => format!("{},Y", zero_page(label)),
+            (AddressingMode::Absolute, _) => absolute(label),
+            (AddressingMode::Absolute_X, _) => format!("{},X", absolute(label)),
+            (AddressingMode::Absolute_Y, _) => format!("{},Y", absolute(label)),
+            (AddressingMode::Indirect_X, _) => format!("({},X)", zero_page(label)),
+            (AddressingMode::Indirect_Y, _) => format!("({}),Y", zero_page(label)),
+            // Accumulator shifts and implied instructions
+            (AddressingMode::NoneAddressing, 1) => match opcode.code {
+                0x0A | 0x2A | 0x4A | 0x6A => "A".to_string(),
+                _ => String::new(),
+            },
+            (AddressingMode::NoneAddressing, 2) => match label {
+                Some(label) => label.to_string(),
+                None => format!("${:04X}", self.target().unwrap()),
+            },
+            (AddressingMode::NoneAddressing, _) => format!("({})", absolute(label)),
+        };
+
+        format!("{} {}", opcode.mnemonic, operand)
+            .trim_end()
+            .to_string()
+    }
+
+    // Listing line, like "$C000  A9 10     LDA #$10"
+    pub fn line(&self, symbols: Option<&Symbols>) -> String {
+        let hex: Vec<String> = self
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        format!(
+            "${:04X}  {:<9} {}",
+            self.addr,
+            hex.join(" "),
+            self.text(symbols)
+        )
+    }
+}
+
+// Decodes the instruction at `addr`, reading memory through `peek`
+pub fn decode<F: Fn(u16) -> u8>(peek: F, addr: u16) -> Instruction {
+    let code = peek(addr);
+    let opcode = opcode::lookup(code);
+    let len = opcode.map_or(1, |opcode| opcode.bytes as u16);
+
+    Instruction {
+        addr,
+        bytes: (0..len).map(|i| peek(addr.wrapping_add(i))).collect(),
+        opcode,
+    }
+}
+
+// Decodes `count` instructions in a row through `peek`
+pub fn disassemble<F: Fn(u16) -> u8>(peek: F, addr: u16, count: usize) -> Vec<Instruction> {
+    let mut instructions = Vec::with_capacity(count);
+    let mut addr = addr;
+    for _ in 0..count {
+        let instruction = decode(&peek, addr);
+        addr = addr.wrapping_add(instruction.len());
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+// Decodes a whole byte range loaded at `base`. An instruction cut by the end
+// of the range is left as bytes
+pub fn disassemble_bytes(bytes: &[u8], base: u16) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let addr = base.wrapping_add(offset as u16);
+        let mut instruction = decode(
+            |at| {
+                bytes
+                    .get(at.wrapping_sub(base) as usize)
+                    .copied()
+                    .unwrap_or(0)
+            },
+            addr,
+        );
+        if offset + instruction.bytes.len() > bytes.len() {
+            instruction = Instruction {
+                addr,
+                bytes: vec![bytes[offset]],
+                opcode: None,
+            };
+        }
+        offset += instruction.bytes.len();
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+// Listing with label lines in front of labeled instructions
+pub fn listing(instructions: &[Instruction], symbols: Option<&Symbols>) -> String {
+    let mut lines = Vec::new();
+    for instruction in instructions {
+        if let Some(label) = symbols.and_then(|symbols| symbols.label(instruction.addr)) {
+            lines.push(format!("{}:", label));
+        }
+        lines.push(instruction.line(symbols));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(bytes: &[u8], symbols: Option<&Symbols>) -> String {
+        disassemble_bytes(bytes, 0xC000)[0].text(symbols)
+    }
+
+    #[test]
+    fn addressing_modes() {
+        assert_eq!(text(&[0xA9, 0x10], None), "LDA #$10");
+        assert_eq!(text(&[0xA5, 0x10], None), "LDA $10");
+        assert_eq!(text(&[0xB5, 0x10], None), "LDA $10,X");
+        assert_eq!(text(&[0xB6, 0x10], None), "LDX $10,Y");
+        assert_eq!(text(&[0xAD, 0x34, 0x12], None), "LDA $1234");
+        assert_eq!(text(&[0xBD, 0x34, 0x12], None), "LDA $1234,X");
+        assert_eq!(text(&[0xB9, 0x34, 0x12], None), "LDA $1234,Y");
+        assert_eq!(text(&[0xA1, 0x10], None), "LDA ($10,X)");
+        assert_eq!(text(&[0xB1, 0x10], None), "LDA ($10),Y");
+        assert_eq!(text(&[0x6C, 0xFC, 0xFF], None), "JMP ($FFFC)");
+        assert_eq!(text(&[0x0A], None), "ASL A");
+        assert_eq!(text(&[0xEA], None), "NOP");
+        assert_eq!(text(&[0x02], None), ".byte $02");
+    }
+
+    // Branches count from the next instruction, both ways
+    #[test]
+    fn branch_targets() {
+        assert_eq!(text(&[0xD0, 0x04], None), "BNE $C006");
+        assert_eq!(text(&[0xD0, 0xFE], None), "BNE $C000");
+        assert_eq!(disassemble(|_| 0x10, 0xFFFE, 1)[0].target(), Some(0x0010));
+    }
+
+    #[test]
+    fn labels() {
+        let mut symbols = Symbols::new();
+        symbols.add(0xC000, "loop");
+        symbols.add(0x0010, "ptr");
+        symbols.add(0x1234, "table");
+        assert_eq!(text(&[0xB1, 0x10], Some(&symbols)), "LDA (ptr),Y");
+        assert_eq!(text(&[0xBD, 0x34, 0x12], Some(&symbols)), "LDA table,X");
+        assert_eq!(text(&[0xD0, 0xFE], Some(&symbols)), "BNE loop");
+        // Immediates are numbers, never labels
+        assert_eq!(text(&[0xA9, 0x10], Some(&symbols)), "LDA #$10");
+
+        let code = disassemble_bytes(&[0xA9, 0x00, 0xD0, 0xFC, 0x20], 0xC000);
+        assert_eq!(code.len(), 3);
+        assert_eq!(
+            listing(&code, Some(&symbols)),
+            "loop:\n\
+             $C000  A9 00     LDA #$00\n\
+             $C002  D0 FC     BNE loop\n\
+             $C004  20        .byte $20"
+        );
+    }
+}
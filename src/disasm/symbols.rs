@@ -0,0 +1,217 @@
+use std::{collections::HashMap, fs, path::Path};
+
+// Address labels. Banks are not told apart, a label defined later for the
+// same address replaces the earlier one
+pub struct Symbols {
+    labels: HashMap<u16, String>,
+    addresses: HashMap<String, u16>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Symbols {
+            labels: HashMap::new(),
+            addresses: HashMap::new(),
+        }
+    }
+
+    // Format is picked by extension: FCEUX `.nl`, ca65 `.dbg`, otherwise
+    // `label = $addr` lines
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("Can't read {}: {}", path.display(), err))?;
+
+        let mut symbols = Symbols::new();
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("nl") => symbols.parse_fceux_nl(&text)?,
+            Some("dbg") => symbols.parse_ca65_dbg(&text)?,
+            _ => symbols.parse_assignments(&text)?,
+        }
+        Ok(symbols)
+    }
+
+    pub fn add(&mut self, addr: u16, label: &str) {
+        if let Some(old) = self.labels.insert(addr, label.to_string()) {
+            self.addresses.remove(&old);
+        }
+        self.addresses.insert(label.to_string(), addr);
+    }
+
+    pub fn label(&self, addr: u16) -> Option<&str> {
+        self.labels.get(&addr).map(String::as_str)
+    }
+    pub fn address(&self, label: &str) -> Option<u16> {
+        self.addresses.get(label).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    // $C000#Label#Comment, arrays are written as $0300/10#Label#
+    // https://fceux.com/web/help/NLFilesFormat.html
+    pub fn parse_fceux_nl(&mut self, text: &str) -> Result<(), String> {
+        for (number, line) in text.lines().enumerate() {
+            let mut fields = line.split('#');
+            let addr = fields.next().unwrap_or("").trim();
+            // Comment continuations and blank lines
+            if !addr.starts_with('$') {
+                continue;
+            }
+
+            let addr = addr[1..].split('/').next().unwrap_or("");
+            let addr = parse_addr(addr).ok_or(format!("Line {}: bad address", number + 1))?;
+            match fields.next().map(str::trim) {
+                Some(label) if !label.is_empty() => self.add(addr, label),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    // Labels from ld65 --dbgfile output, lines like
+    // sym id=3,name="reset",addrsize=absolute,...,val=0xC000,seg=0,type=lab
+    pub fn parse_ca65_dbg(&mut self, text: &str) -> Result<(), String> {
+        for line in text.lines() {
+            let Some(attributes) = line.strip_prefix("sym\t") else {
+                continue;
+            };
+
+            let mut name = None;
+            let mut value = None;
+            let mut is_label = false;
+            for attribute in attributes.split(',') {
+                match attribute.split_once('=') {
+                    Some(("name", text)) => name = Some(text.trim_matches('"')),
+                    Some(("val", text)) => {
+                        value = text
+                            .strip_prefix("0x")
+                            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    }
+                    Some(("type", kind)) => is_label = kind == "lab",
+                    _ => (),
+                }
+            }
+
+            // Constants are not addresses
+            if let (true, Some(name), Some(value)) = (is_label, name, value) {
+                if let Ok(addr) = u16::try_from(value) {
+                    self.add(addr, name);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // label = $C000, comments start with ;
+    pub fn parse_assignments(&mut self, text: &str) -> Result<(), String> {
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let bad_line = || format!("Line {}: expected `label = $addr`", number + 1);
+            let (label, addr) = line.split_once('=').ok_or_else(bad_line)?;
+            let addr = addr.trim();
+            let addr = addr
+                .strip_prefix('$')
+                .or_else(|| addr.strip_prefix("0x"))
+                .unwrap_or(addr);
+            let addr = parse_addr(addr).ok_or_else(bad_line)?;
+            self.add(addr, label.trim());
+        }
+        Ok(())
+    }
+}
+
+fn parse_addr(hex: &str) -> Option<u16> {
+    u16::from_str_radix(hex, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fceux_nl() {
+        let mut symbols = Symbols::new();
+        symbols
+            .parse_fceux_nl(
+                "$C000#Reset#Power on and reset\n\
+                 \\#continues the comment\n\
+                 \n\
+                 $0300/10#Buffer#\n\
+                 $00FF##No label\n",
+            )
+            .unwrap();
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols.label(0xC000), Some("Reset"));
+        assert_eq!(symbols.address("Buffer"), Some(0x0300));
+        assert_eq!(symbols.label(0x00FF), None);
+
+        assert_eq!(
+            symbols.parse_fceux_nl("$C000#Reset#\n$XYZ#Bad#"),
+            Err("Line 2: bad address".to_string())
+        );
+    }
+
+    #[test]
+    fn ca65_dbg() {
+        let mut symbols = Symbols::new();
+        symbols
+            .parse_ca65_dbg(
+                "version\tmajor=2,minor=0\n\
+                 seg\tid=0,name=\"CODE\",start=0x00C000,size=0x0100\n\
+                 sym\tid=0,name=\"reset\",addrsize=absolute,scope=0,def=1,val=0xC000,seg=0,type=lab\n\
+                 sym\tid=1,name=\"PPUCTRL\",addrsize=absolute,scope=0,def=2,val=0x2000,type=equ\n\
+                 sym\tid=2,name=\"temp\",addrsize=zeropage,scope=0,def=3,val=0x10,seg=1,type=lab\n\
+                 sym\tid=3,name=\"far\",addrsize=far,scope=0,def=4,val=0x18000,seg=2,type=lab\n",
+            )
+            .unwrap();
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols.address("reset"), Some(0xC000));
+        assert_eq!(symbols.label(0x0010), Some("temp"));
+        // Constants and addresses past $FFFF are left out
+        assert_eq!(symbols.address("PPUCTRL"), None);
+        assert_eq!(symbols.address("far"), None);
+    }
+
+    #[test]
+    fn assignments() {
+        let mut symbols = Symbols::new();
+        symbols
+            .parse_assignments(
+                "; game variables\n\
+                 lives = $075A\n\
+                 score=0x07DD  ; six digits\n\
+                 \n\
+                 nmi = FFFA\n",
+            )
+            .unwrap();
+        assert_eq!(symbols.address("lives"), Some(0x075A));
+        assert_eq!(symbols.address("score"), Some(0x07DD));
+        assert_eq!(symbols.label(0xFFFA), Some("nmi"));
+
+        let error = |text| Symbols::new().parse_assignments(text).err().unwrap();
+        assert_eq!(error("lives $075A"), "Line 1: expected `label = $addr`");
+        assert_eq!(
+            error("\nlives = $10000"),
+            "Line 2: expected `label = $addr`"
+        );
+    }
+
+    // A new label for an address takes the place of the old one
+    #[test]
+    fn relabeling() {
+        let mut symbols = Symbols::new();
+        symbols.add(0xC000, "start");
+        symbols.add(0xC000, "reset");
+        assert_eq!(symbols.len(), 1);
+        assert_eq!(symbols.label(0xC000), Some("reset"));
+        assert_eq!(symbols.address("start"), None);
+    }
+}
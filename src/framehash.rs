@@ -0,0 +1,179 @@
+use std::{fs, path::Path};
+
+// Hash of the whole machine after every frame. Runs of the same ROM with the
+// same power-on state and input agree frame for frame, the first hash that
+// doesn't is where they went apart
+pub struct FrameHashes {
+    hashes: Vec<u64>,
+    // Earlier run being checked against
+    expected: Option<Vec<u64>>,
+    first_difference: Option<usize>,
+}
+
+impl FrameHashes {
+    pub fn new() -> Self {
+        FrameHashes {
+            hashes: Vec::new(),
+            expected: None,
+            first_difference: None,
+        }
+    }
+
+    pub fn verifying(expected: Vec<u64>) -> Self {
+        FrameHashes {
+            expected: Some(expected),
+            ..FrameHashes::new()
+        }
+    }
+
+    pub fn push(&mut self, hash: u64) {
+        let frame = self.hashes.len();
+        let expected = self.expected.as_ref().and_then(|hashes| hashes.get(frame));
+        if self.first_difference.is_none() && expected.is_some_and(|&wanted| wanted != hash) {
+            self.first_difference = Some(frame);
+        }
+        self.hashes.push(hash);
+    }
+
+    pub fn hashes(&self) -> &[u64] {
+        &self.hashes
+    }
+
+    // Frame counted from 0 where the runs first differ
+    pub fn first_difference(&self) -> Option<usize> {
+        self.first_difference
+    }
+
+    // Frames run in both
+    pub fn compared(&self) -> usize {
+        self.expected
+            .as_ref()
+            .map_or(0, |hashes| hashes.len().min(self.hashes.len()))
+    }
+
+    // One hash per line in hex, # starts a comment
+    pub fn parse(text: &str) -> Result<Vec<u64>, String> {
+        text.lines()
+            .map(|line| line.split('#').next().unwrap_or("").trim())
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(number, line)| {
+                u64::from_str_radix(line, 16)
+                    .map_err(|_| format!("Line {}: {} is not a hash", number + 1, line))
+            })
+            .collect()
+    }
+
+    pub fn to_text(&self) -> String {
+        self.hashes
+            .iter()
+            .map(|hash| format!("{:016x}\n", hash))
+            .collect()
+    }
+
+    pub fn load(path: &Path) -> Result<Vec<u64>, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("Can't read {}: {}", path.display(), err))?;
+        FrameHashes::parse(&text)
+    }
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, self.to_text())
+            .map_err(|err| format!("Can't write {}: {}", path.display(), err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+    use crate::{
+        asm,
+        joypad::{ControllerPort, JoypadButton},
+        Nes,
+    };
+
+    #[test]
+    fn save_and_load() {
+        let mut hashes = FrameHashes::new();
+        for hash in [0, 0x0123_4567_89AB_CDEF, u64::MAX] {
+            hashes.push(hash);
+        }
+        assert_eq!(
+            hashes.to_text(),
+            "0000000000000000\n0123456789abcdef\nffffffffffffffff\n"
+        );
+
+        let path = env::temp_dir().join(format!("nesmulator-hashes-{}.txt", process::id()));
+        hashes.save(&path).unwrap();
+        let loaded = FrameHashes::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.as_deref(), Ok(hashes.hashes()));
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            FrameHashes::parse("# run 1\n00ff\n\n  1  # second frame\n"),
+            Ok(vec![0xFF, 1])
+        );
+        assert_eq!(
+            FrameHashes::parse("00ff\n\nxyz"),
+            Err("Line 3: xyz is not a hash".to_string())
+        );
+    }
+
+    // Only the first frame that went apart counts, frames past the end of
+    // the earlier run are not compared
+    #[test]
+    fn first_difference() {
+        let mut hashes = FrameHashes::verifying(vec![1, 2, 3, 4]);
+        for hash in [1, 2] {
+            hashes.push(hash);
+        }
+        assert_eq!(hashes.first_difference(), None);
+        for hash in [9, 8, 5, 6] {
+            hashes.push(hash);
+        }
+        assert_eq!(hashes.first_difference(), Some(2));
+        assert_eq!(hashes.compared(), 4);
+
+        let mut longer = FrameHashes::verifying(vec![1]);
+        longer.push(1);
+        longer.push(2);
+        assert_eq!(longer.first_difference(), None);
+        assert_eq!(longer.compared(), 1);
+        assert_eq!(FrameHashes::new().compared(), 0);
+    }
+
+    // Input that differs shows up in the frame it was pressed in
+    #[test]
+    fn desync() {
+        let rom = asm::test_rom(
+            "loop: lda #1
+                   sta $4016
+                   lda #0
+                   sta $4016
+                   lda $4016
+                   sta $10
+                   jmp loop",
+        );
+        let run = |expected: Vec<u64>, press_at: usize| {
+            let mut nes = Nes::new(&rom).unwrap();
+            nes.enable_frame_hashes(FrameHashes::verifying(expected));
+            for frame in 0..6 {
+                if frame == press_at {
+                    nes.set_controller(ControllerPort::One, JoypadButton::A);
+                }
+                nes.run_frame();
+            }
+            nes.take_frame_hashes().unwrap()
+        };
+
+        let recorded = run(Vec::new(), usize::MAX).hashes().to_vec();
+        assert_eq!(run(recorded.clone(), usize::MAX).first_difference(), None);
+        let desynced = run(recorded, 3);
+        assert_eq!(desynced.first_difference(), Some(3));
+        assert_eq!(desynced.compared(), 6);
+    }
+}
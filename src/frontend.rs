@@ -0,0 +1,168 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+use nesmulator::{
+    apu::SAMPLE_RATE,
+    render::frame::{HEIGHT, WIDTH},
+    ControllerPort, JoypadButton, Nes,
+};
+use sdl2::{audio::AudioSpecDesired, event::Event, keyboard::Keycode, pixels::PixelFormatEnum};
+
+const SCALE: u32 = 3;
+// NTSC frame rate is 60.0988 Hz
+const FRAME_DURATION: Duration = Duration::from_nanos(16_639_267);
+// Ten seconds of rewind
+const REWIND_FRAMES: usize = 600;
+const REWIND_MEMORY: usize = 64 * 1024 * 1024;
+
+fn key_map() -> HashMap<Keycode, JoypadButton> {
+    let mut key_map = HashMap::new();
+    key_map.insert(Keycode::Down, JoypadButton::DOWN);
+    key_map.insert(Keycode::Up, JoypadButton::UP);
+    key_map.insert(Keycode::Right, JoypadButton::RIGHT);
+    key_map.insert(Keycode::Left, JoypadButton::LEFT);
+    key_map.insert(Keycode::Space, JoypadButton::SELECT);
+    key_map.insert(Keycode::Return, JoypadButton::START);
+    key_map.insert(Keycode::A, JoypadButton::A);
+    key_map.insert(Keycode::S, JoypadButton::B);
+    key_map
+}
+
+pub fn run(nes: &mut Nes, state_path: &Path) {
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+    let audio_subsystem = sdl_context.audio().unwrap();
+
+    let window = video_subsystem
+        .window("NESmulator", WIDTH as u32 * SCALE, HEIGHT as u32 * SCALE)
+        .position_centered()
+        .build()
+        .unwrap();
+    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    canvas.set_scale(SCALE as f32, SCALE as f32).unwrap();
+
+    let creator = canvas.texture_creator();
+    let mut texture = creator
+        .create_texture_target(PixelFormatEnum::RGB24, WIDTH as u32, HEIGHT as u32)
+        .unwrap();
+
+    let audio_spec = AudioSpecDesired {
+        freq: Some(SAMPLE_RATE as i32),
+        channels: Some(1),
+        samples: None,
+    };
+    let audio_queue = audio_subsystem
+        .open_queue::<f32, _>(None, &audio_spec)
+        .unwrap();
+    audio_queue.resume();
+
+    let key_map = key_map();
+    let mut buttons = JoypadButton::empty();
+    let mut rewinding = false;
+    nes.enable_rewind(REWIND_FRAMES, REWIND_MEMORY);
+    let mut title = String::new();
+
+    loop {
+        let frame_start = Instant::now();
+
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => return,
+                // Quick save and quick load
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    ..
+                } => {
+                    if let Err(err) = fs::write(state_path, nes.save_state()) {
+                        eprintln!("Can't save {}: {}", state_path.display(), err);
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F7),
+                    ..
+                } => {
+                    let result = fs::read(state_path)
+                        .map_err(|err| err.to_string())
+                        .and_then(|data| nes.load_state(&data));
+                    if let Err(err) = result {
+                        eprintln!("Can't load {}: {}", state_path.display(), err);
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F8),
+                    ..
+                } => {
+                    if let Some(movie) = nes.movie_mut() {
+                        movie.read_only = !movie.read_only;
+                    }
+                }
+                // Rewind while held
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = true,
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = false,
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    if let Some(button) = key_map.get(&keycode) {
+                        buttons.insert(*button);
+                    }
+                }
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    if let Some(button) = key_map.get(&keycode) {
+                        buttons.remove(*button);
+                    }
+                }
+                _ => (),
+            }
+        }
+        nes.set_controller(ControllerPort::One, buttons);
+
+        if rewinding {
+            nes.rewind(1);
+        } else {
+            nes.run_frame();
+        }
+
+        // Movie frame counter, and why the game froze if the CPU halted
+        let mut new_title = match nes.movie() {
+            Some(movie) => format!("NESmulator - {}", movie.status()),
+            None => "NESmulator".to_string(),
+        };
+        if let Some(halt) = nes.cpu().halted() {
+            new_title += &format!(" - {}", halt.describe());
+        }
+        if new_title != title {
+            canvas.window_mut().set_title(&new_title).unwrap();
+            title = new_title;
+        }
+
+        texture.update(None, nes.framebuffer(), WIDTH * 3).unwrap();
+        canvas.copy(&texture, None, None).unwrap();
+        canvas.present();
+
+        audio_queue.queue_audio(&nes.drain_audio()).unwrap();
+
+        if let Some(remaining) = FRAME_DURATION.checked_sub(frame_start.elapsed()) {
+            thread::sleep(remaining);
+        }
+    }
+}
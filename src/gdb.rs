@@ -0,0 +1,557 @@
+use std::{
+    collections::HashSet,
+    io::{self, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    cpu::{opcode, Registers},
+    watch::{WatchAction, WatchKind, Watchpoint},
+    Nes,
+};
+
+// GDB remote serial protocol stub
+// https://sourceware.org/gdb/current/onlinedocs/gdb.html/Remote-Protocol.html
+//
+// Registers are numbered a, x, y, p, sp (8 bits each) and pc (16 bits,
+// little endian), which target.xml describes to the client.
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+// Instructions run between checks for a client interrupt (Ctrl-C)
+const INTERRUPT_CHECK_INTERVAL: u32 = 0x4000;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.nesmulator.6502">
+    <reg name="a" bitsize="8" type="uint8"/>
+    <reg name="x" bitsize="8" type="uint8"/>
+    <reg name="y" bitsize="8" type="uint8"/>
+    <reg name="p" bitsize="8" type="uint8"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>"#;
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    no_ack: bool,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        Ok(Connection {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            no_ack: false,
+        })
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        match self.reader.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    // Next packet payload, None when the client hung up
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // Skip acks and anything else between packets
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'$') => break,
+                    Some(_) => (),
+                }
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                }
+            }
+            let mut checksum = [0; 2];
+            self.reader.read_exact(&mut checksum)?;
+
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            let actual = data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+            if self.no_ack {
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+            if expected == Some(actual) {
+                self.writer.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+            self.writer.write_all(b"-")?;
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.writer, "${}#{:02x}", data, checksum)?;
+        self.writer.flush()
+    }
+
+    // Checks without blocking whether the client sent Ctrl-C
+    fn interrupted(&mut self) -> io::Result<bool> {
+        if !self.reader.buffer().is_empty() {
+            return Ok(self.reader.buffer().contains(&0x03));
+        }
+
+        let stream = self.reader.get_ref();
+        stream.set_nonblocking(true)?;
+        let mut byte = [0];
+        let result = stream.peek(&mut byte);
+        stream.set_nonblocking(false)?;
+
+        match result {
+            Ok(1) if byte[0] == 0x03 => {
+                self.read_byte()?;
+                Ok(true)
+            }
+            Ok(_) => Ok(false),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+pub struct GdbServer {
+    nes: Nes,
+    breakpoints: HashSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+}
+
+impl GdbServer {
+    pub fn new(nes: Nes) -> Self {
+        GdbServer {
+            nes,
+            breakpoints: HashSet::new(),
+            watchpoints: Vec::new(),
+        }
+    }
+
+    pub fn into_nes(self) -> Nes {
+        self.nes
+    }
+
+    // Waits for one client on `addr` like "127.0.0.1:1234" and serves it
+    pub fn listen(&mut self, addr: &str) -> io::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, _) = listener.accept()?;
+        self.serve(stream)
+    }
+
+    // Serves one client until it detaches, kills or disconnects
+    pub fn serve(&mut self, stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        let mut connection = Connection::new(stream)?;
+
+        while let Some(packet) = connection.read_packet()? {
+            match packet.chars().next() {
+                Some('D') => return connection.send("OK"),
+                Some('k') => return Ok(()),
+                _ => {
+                    let reply = self.handle(&packet, &mut connection)?;
+                    connection.send(&reply)?;
+                    if packet == "QStartNoAckMode" {
+                        connection.no_ack = true;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, packet: &str, connection: &mut Connection) -> io::Result<String> {
+        let command_len = packet.chars().next().map_or(0, char::len_utf8);
+        let (command, args) = packet.split_at(command_len);
+        let reply = match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => {
+                let registers = self.nes.cpu().registers();
+                hex_bytes(&register_bytes(&registers))
+            }
+            "G" => match parse_hex_bytes(args) {
+                Some(bytes) if bytes.len() >= 7 => {
+                    let registers = Registers {
+                        a: bytes[0],
+                        x: bytes[1],
+                        y: bytes[2],
+                        p: bytes[3],
+                        sp: bytes[4],
+                        pc: u16::from_le_bytes([bytes[5], bytes[6]]),
+                    };
+                    self.nes.parts_mut().0.set_registers(registers);
+                    "OK".to_string()
+                }
+                _ => "E01".to_string(),
+            },
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(number) if number < 6 => {
+                    let bytes = register_bytes(&self.nes.cpu().registers());
+                    match number {
+                        5 => hex_bytes(&bytes[5..7]),
+                        _ => hex_bytes(&bytes[number..number + 1]),
+                    }
+                }
+                _ => "E01".to_string(),
+            },
+            "P" => self
+                .write_register(args)
+                .unwrap_or_else(|| "E01".to_string()),
+            "m" => self.read_memory(args).unwrap_or_else(|| "E01".to_string()),
+            "M" => self.write_memory(args).unwrap_or_else(|| "E01".to_string()),
+            "c" | "s" => {
+                if !args.is_empty() {
+                    let Ok(pc) = u16::from_str_radix(args, 16) else {
+                        return Ok("E01".to_string());
+                    };
+                    let mut registers = self.nes.cpu().registers();
+                    registers.pc = pc;
+                    self.nes.parts_mut().0.set_registers(registers);
+                }
+                self.resume(connection, command == "s")?
+            }
+            "Z" | "z" => self
+                .set_breakpoint(command == "Z", args)
+                .unwrap_or_else(|| "E01".to_string()),
+            "H" => "OK".to_string(),
+            "T" => "OK".to_string(),
+            "q" | "Q" => self.query(packet),
+            // Binary writes and vCont are optional, the client falls back
+            _ => String::new(),
+        };
+        Ok(reply)
+    }
+
+    fn query(&self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+".to_string();
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let Some((offset, len)) = range.split_once(',') else {
+                return "E01".to_string();
+            };
+            let (Ok(offset), Ok(len)) = (
+                usize::from_str_radix(offset, 16),
+                usize::from_str_radix(len, 16),
+            ) else {
+                return "E01".to_string();
+            };
+
+            let start = offset.min(TARGET_XML.len());
+            let end = start.saturating_add(len).min(TARGET_XML.len());
+            let more = if end < TARGET_XML.len() { 'm' } else { 'l' };
+            return format!("{}{}", more, &TARGET_XML[start..end]);
+        }
+
+        match packet {
+            "QStartNoAckMode" => "OK".to_string(),
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    // P n=value, value in target byte order
+    fn write_register(&mut self, args: &str) -> Option<String> {
+        let (number, value) = args.split_once('=')?;
+        let number = usize::from_str_radix(number, 16).ok()?;
+        let value = parse_hex_bytes(value)?;
+
+        let mut registers = self.nes.cpu().registers();
+        let byte = *value.first()?;
+        match number {
+            0 => registers.a = byte,
+            1 => registers.x = byte,
+            2 => registers.y = byte,
+            3 => registers.p = byte,
+            4 => registers.sp = byte,
+            5 => registers.pc = u16::from_le_bytes([byte, *value.get(1)?]),
+            _ => return None,
+        }
+        self.nes.parts_mut().0.set_registers(registers);
+        Some("OK".to_string())
+    }
+
+    // m addr,length
+    fn read_memory(&self, args: &str) -> Option<String> {
+        let (addr, len) = parse_addr_len(args)?;
+        let bytes: Vec<u8> = (0..len)
+            .map(|i| self.nes.read_memory(addr.wrapping_add(i)))
+            .collect();
+        Some(hex_bytes(&bytes))
+    }
+
+    // M addr,length:XX...
+    fn write_memory(&mut self, args: &str) -> Option<String> {
+        let (range, data) = args.split_once(':')?;
+        let (addr, len) = parse_addr_len(range)?;
+        let bytes = parse_hex_bytes(data)?;
+        if bytes.len() != len as usize {
+            return None;
+        }
+
+        // Only RAM can be written, registers would act on it
+        let (_, bus) = self.nes.parts_mut();
+        for (i, value) in bytes.iter().enumerate() {
+            if !bus.poke(addr.wrapping_add(i as u16), *value) {
+                return None;
+            }
+        }
+        Some("OK".to_string())
+    }
+
+    // Z type,addr,kind. Software and hardware breakpoints are the same here
+    fn set_breakpoint(&mut self, insert: bool, args: &str) -> Option<String> {
+        let mut fields = args.split(',');
+        let kind = fields.next()?;
+        let addr = u16::from_str_radix(fields.next()?, 16).ok()?;
+        let len = u16::from_str_radix(fields.next()?, 16).ok()?;
+
+        let watch = match kind {
+            "0" | "1" => {
+                if insert {
+                    self.breakpoints.insert(addr);
+                } else {
+                    self.breakpoints.remove(&addr);
+                }
+                return Some("OK".to_string());
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return Some(String::new()),
+        };
+
+        if insert {
+            let end = addr.saturating_add(len.max(1) - 1);
+            self.watchpoints.push(Watchpoint {
+                range: addr..=end,
+                kind: watch,
+                value: None,
+                action: WatchAction::Break,
+            });
+        } else {
+            self.watchpoints.retain(|watchpoint| {
+                !(watchpoint.kind == watch && *watchpoint.range.start() == addr)
+            });
+        }
+        Some("OK".to_string())
+    }
+
+    // Runs until a breakpoint, watchpoint or interrupt and returns the stop
+    // reply. Watchpoints are the bus ones the debugger uses too. Entering an
+    // IRQ or NMI handler is a step of its own, so a breakpoint on the first
+    // instruction of a handler stops there
+    fn resume(&mut self, connection: &mut Connection, single_step: bool) -> io::Result<String> {
+        let watchpoints = self.nes.parts_mut().1.watchpoints_mut();
+        for watchpoint in &self.watchpoints {
+            watchpoints.add(watchpoint.clone());
+        }
+
+        let mut executed = 0u32;
+        let reply = loop {
+            let cpu = self.nes.cpu();
+            if !cpu.interrupt_pending() && opcode::lookup(self.nes.read_memory(cpu.pc())).is_none()
+            {
+                break format!("S{:02x}", SIGILL);
+            }
+            self.nes.step_instruction();
+
+            let (_, bus) = self.nes.parts_mut();
+            if let Some(hit) = bus.watchpoints_mut().take_break() {
+                let watchpoint = self
+                    .watchpoints
+                    .iter()
+                    .find(|watchpoint| watchpoint.matches(hit.kind, hit.addr, hit.value));
+                let name = match watchpoint.map(|watchpoint| watchpoint.kind) {
+                    Some(WatchKind::Write) => "watch",
+                    Some(WatchKind::Read) => "rwatch",
+                    _ => "awatch",
+                };
+                break format!("T{:02x}{}:{:04x};", SIGTRAP, name, hit.addr);
+            }
+
+            if single_step || self.breakpoints.contains(&self.nes.cpu().pc()) {
+                break format!("S{:02x}", SIGTRAP);
+            }
+
+            executed += 1;
+            if executed.is_multiple_of(INTERRUPT_CHECK_INTERVAL) && connection.interrupted()? {
+                break format!("S{:02x}", SIGINT);
+            }
+        };
+
+        self.nes.parts_mut().1.watchpoints_mut().clear();
+        Ok(reply)
+    }
+}
+
+fn register_bytes(registers: &Registers) -> [u8; 7] {
+    let [pc_lo, pc_hi] = registers.pc.to_le_bytes();
+    [
+        registers.a,
+        registers.x,
+        registers.y,
+        registers.p,
+        registers.sp,
+        pc_lo,
+        pc_hi,
+    ]
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn parse_hex_bytes(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_addr_len(text: &str) -> Option<(u16, u16)> {
+    let (addr, len) = text.split_once(',')?;
+    let addr = u32::from_str_radix(addr, 16).ok()?;
+    let len = u32::from_str_radix(len, 16).ok()?;
+    if addr > 0xFFFF || len > 0x1000 || addr + len > 0x10000 {
+        return None;
+    }
+    Some((addr as u16, len as u16))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use std::thread;
+
+    // Main loop and an NMI handler at $C008 counting frames in X
+    const ROM: &str = "
+        reset: lda #$80
+               sta $2000
+        loop:  jmp loop
+        nmi:   inx
+               rti";
+
+    struct Client {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+    }
+
+    impl Client {
+        // Sends a packet, acks the reply and returns its payload
+        fn request(&mut self, data: &str) -> String {
+            let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+            write!(self.writer, "${}#{:02x}", data, checksum).unwrap();
+
+            let mut byte = [0];
+            self.reader.read_exact(&mut byte).unwrap();
+            assert_eq!(byte[0], b'+');
+            self.reader.read_exact(&mut byte).unwrap();
+            assert_eq!(byte[0], b'$');
+            let mut reply = Vec::new();
+            loop {
+                self.reader.read_exact(&mut byte).unwrap();
+                if byte[0] == b'#' {
+                    break;
+                }
+                reply.push(byte[0]);
+            }
+            let mut checksum = [0; 2];
+            self.reader.read_exact(&mut checksum).unwrap();
+            self.writer.write_all(b"+").unwrap();
+            String::from_utf8(reply).unwrap()
+        }
+    }
+
+    // Runs `session` as the client of a server for `server`, returns what
+    // the session returned and the server back
+    fn talk<T: Send + 'static>(
+        mut server: GdbServer,
+        session: impl FnOnce(&mut Client) -> T + Send + 'static,
+    ) -> (T, GdbServer) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let stream = TcpStream::connect(addr).unwrap();
+            let mut client = Client {
+                reader: BufReader::new(stream.try_clone().unwrap()),
+                writer: stream,
+            };
+            let result = session(&mut client);
+            assert_eq!(client.request("D"), "OK");
+            result
+        });
+        let (stream, _) = listener.accept().unwrap();
+        server.serve(stream).unwrap();
+        (client.join().unwrap(), server)
+    }
+
+    fn server() -> GdbServer {
+        GdbServer::new(Nes::new(&asm::test_rom(ROM)).unwrap())
+    }
+
+    #[test]
+    fn breaks_on_interrupt_handler() {
+        let (replies, server) = talk(server(), |client| {
+            [
+                client.request("?"),
+                client.request("mc008,2"),
+                client.request("Z0,c008,1"),
+                client.request("c"),
+                client.request("g"),
+                client.request("c"),
+                client.request("g"),
+                client.request("s"),
+                client.request("p5"),
+            ]
+        });
+        assert_eq!(replies[0], "S05");
+        assert_eq!(replies[1], "e840");
+        assert_eq!(replies[2], "OK");
+        assert_eq!(replies[3], "S05");
+        // a, x, y, p, sp, pc
+        assert_eq!(&replies[4][2..4], "00");
+        assert_eq!(&replies[4][10..], "08c0");
+        assert_eq!(replies[5], "S05");
+        assert_eq!(&replies[6][2..4], "01");
+        assert_eq!(&replies[6][10..], "08c0");
+        assert_eq!(replies[7], "S05");
+        assert_eq!(replies[8], "09c0");
+        assert_eq!(server.nes.cpu().registers().x, 2);
+    }
+
+    #[test]
+    fn step_enters_interrupt_handler() {
+        let mut server = server();
+        while !server.nes.cpu().interrupt_pending() {
+            server.nes.step_instruction();
+        }
+        let (pc, server) = talk(server, |client| {
+            assert_eq!(client.request("s"), "S05");
+            client.request("p5")
+        });
+        assert_eq!(pc, "08c0");
+        assert_eq!(server.nes.cpu().registers().x, 0);
+    }
+}
@@ -0,0 +1,96 @@
+use crate::savestate::{Section, Snapshot};
+
+use bitflags::bitflags;
+
+/* RLDU TSBA
+   |||| ||||
+   |||| |||+- A
+   |||| ||+-- B
+   |||| |+--- Select
+   |||| +---- Start
+   |||+------ Up
+   ||+------- Down
+   |+-------- Left
+   +--------- Right
+*/
+bitflags! {
+    pub struct JoypadButton: u8 {
+        const A      = 0b0000_0001;
+        const B      = 0b0000_0010;
+        const SELECT = 0b0000_0100;
+        const START  = 0b0000_1000;
+        const UP     = 0b0001_0000;
+        const DOWN   = 0b0010_0000;
+        const LEFT   = 0b0100_0000;
+        const RIGHT  = 0b1000_0000;
+    }
+}
+
+// The two controller ports on the front of the console
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ControllerPort {
+    One,
+    Two,
+}
+
+// Standard controller
+// https://www.nesdev.org/wiki/Standard_controller
+pub struct Joypad {
+    strobe: bool,
+    button_index: u8,
+    buttons: JoypadButton,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad {
+            strobe: false,
+            button_index: 0,
+            buttons: JoypadButton::empty(),
+        }
+    }
+
+    pub fn write(&mut self, value: u8) {
+        self.strobe = value & 1 == 1;
+        if self.strobe {
+            self.button_index = 0;
+        }
+    }
+
+    // Only bit 0 is driven, the caller is responsible for open bus bits
+    pub fn read(&mut self) -> u8 {
+        // After all 8 buttons were reported official controllers return 1
+        if self.button_index > 7 {
+            return 1;
+        }
+
+        let value = (self.buttons.bits >> self.button_index) & 1;
+        if !self.strobe {
+            self.button_index += 1;
+        }
+
+        value
+    }
+
+    pub fn set_buttons(&mut self, buttons: JoypadButton) {
+        self.buttons = buttons;
+    }
+    pub fn buttons(&self) -> JoypadButton {
+        self.buttons
+    }
+}
+
+impl Snapshot for Joypad {
+    fn save(&self, section: &mut Section) {
+        section.put_bool("strobe", self.strobe);
+        section.put_u8("button_index", self.button_index);
+        section.put_u8("buttons", self.buttons.bits);
+    }
+
+    fn load(&mut self, section: &Section) -> Result<(), String> {
+        self.strobe = section.get_bool("strobe")?;
+        self.button_index = section.get_u8("button_index")?;
+        self.buttons = JoypadButton::from_bits_truncate(section.get_u8("buttons")?);
+        Ok(())
+    }
+}
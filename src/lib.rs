@@ -0,0 +1,33 @@
+// Until stable version
+#![allow(dead_code)]
+#![allow(clippy::new_without_default)]
+
+pub mod apu;
+pub mod asm;
+pub mod bus;
+pub mod cdl;
+pub mod cheat;
+pub mod checksum;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod framehash;
+pub mod gdb;
+pub mod joypad;
+pub mod json;
+pub mod mapper;
+pub mod movie;
+pub mod nes;
+pub mod poweron;
+pub mod ppu;
+pub mod ramsearch;
+pub mod render;
+pub mod rewind;
+pub mod rom;
+pub mod savestate;
+pub mod singlestep;
+pub mod testrom;
+pub mod watch;
+
+pub use joypad::{ControllerPort, JoypadButton};
+pub use nes::Nes;
@@ -1,12 +1,292 @@
-#![allow(non_snake_case)]
-// Until stable version
-#![allow(dead_code)]
+#[cfg(feature = "sdl2")]
+mod frontend;
 
-mod bus;
-mod cpu;
-mod ppu;
-mod rom;
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    process,
+};
+
+use nesmulator::{
+    cpu::CpuVariant,
+    debugger::Debugger,
+    framehash::FrameHashes,
+    gdb::GdbServer,
+    movie::Movie,
+    poweron::PowerOnState,
+    singlestep::{self, OpcodeStatus},
+    testrom, Nes,
+};
+
+const USAGE: &str =
+    "Usage: nesmulator [--debug | --gdb PORT | --headless] [--cdl FILE] [--cheats FILE]
+                  [--record FILE | --play FILE] [--ram-init zeros|ones|pattern|random:SEED]
+                  [--hash-log FILE] [--verify-hashes FILE] [--ppu-catch-up]
+                  [--run-ahead FRAMES [--second-instance]] <rom.nes>
+       nesmulator --test-roms DIR | --cpu-tests DIR [--cpu 2a03|6502]";
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(1);
+}
 
 fn main() {
-    println!("Hello, world!");
+    let mut debug = false;
+    let mut headless = false;
+    let mut gdb_port = None;
+    let mut cdl_path = None;
+    let mut cheats_path = None;
+    let mut record_path = None;
+    let mut play_path = None;
+    let mut power_on_state = PowerOnState::Zeros;
+    let mut hash_log_path = None;
+    let mut verify_path = None;
+    let mut ppu_catch_up = false;
+    let mut run_ahead = None;
+    let mut second_instance = false;
+    let mut test_dir = None;
+    let mut cpu_test_dir = None;
+    let mut cpu_variant = CpuVariant::Ricoh2A03;
+    let mut path = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--debug" => debug = true,
+            "--headless" => headless = true,
+            "--gdb" => gdb_port = Some(args.next().unwrap_or_else(|| usage())),
+            "--cdl" => cdl_path = Some(PathBuf::from(args.next().unwrap_or_else(|| usage()))),
+            "--cheats" => cheats_path = Some(PathBuf::from(args.next().unwrap_or_else(|| usage()))),
+            "--record" => record_path = Some(PathBuf::from(args.next().unwrap_or_else(|| usage()))),
+            "--play" => play_path = Some(PathBuf::from(args.next().unwrap_or_else(|| usage()))),
+            "--ram-init" => {
+                let mode = args.next().unwrap_or_else(|| usage());
+                power_on_state = PowerOnState::parse(&mode).unwrap_or_else(|err| {
+                    eprintln!("{}", err);
+                    process::exit(1);
+                });
+            }
+            "--hash-log" => {
+                hash_log_path = Some(PathBuf::from(args.next().unwrap_or_else(|| usage())))
+            }
+            "--verify-hashes" => {
+                verify_path = Some(PathBuf::from(args.next().unwrap_or_else(|| usage())))
+            }
+            "--ppu-catch-up" => ppu_catch_up = true,
+            "--run-ahead" => {
+                let frames = args.next().and_then(|frames| frames.parse().ok());
+                run_ahead = Some(frames.unwrap_or_else(|| usage()));
+            }
+            "--second-instance" => second_instance = true,
+            "--test-roms" => test_dir = Some(PathBuf::from(args.next().unwrap_or_else(|| usage()))),
+            "--cpu-tests" => {
+                cpu_test_dir = Some(PathBuf::from(args.next().unwrap_or_else(|| usage())))
+            }
+            "--cpu" => {
+                let variant = args.next().unwrap_or_else(|| usage());
+                cpu_variant = CpuVariant::parse(&variant).unwrap_or_else(|err| {
+                    eprintln!("{}", err);
+                    process::exit(1);
+                });
+            }
+            _ if arg.starts_with("--") => usage(),
+            _ => path = Some(arg),
+        }
+    }
+    if let Some(test_dir) = test_dir {
+        run_test_roms(&test_dir);
+    }
+    if let Some(cpu_test_dir) = cpu_test_dir {
+        run_cpu_tests(&cpu_test_dir, cpu_variant);
+    }
+    let Some(path) = path else { usage() };
+    // Only run-ahead has a second instance to use
+    if second_instance && run_ahead.is_none() {
+        usage();
+    }
+    if headless && play_path.is_none() {
+        eprintln!("--headless plays a movie, give one with --play");
+        process::exit(1);
+    }
+
+    let rom_data = fs::read(&path).unwrap_or_else(|err| {
+        eprintln!("Can't read {}: {}", path, err);
+        process::exit(1);
+    });
+    let mut nes = Nes::with_power_on_state(&rom_data, power_on_state).unwrap_or_else(|err| {
+        eprintln!("Can't load {}: {}", path, err);
+        process::exit(1);
+    });
+    nes.set_ppu_catch_up(ppu_catch_up);
+    if let Some(frames) = run_ahead {
+        nes.enable_run_ahead(frames, second_instance);
+    }
+
+    // Cheat list of the ROM is picked up by itself
+    let rom_cheats = Path::new(&path).with_extension("cht");
+    let cheat_files = rom_cheats.exists().then_some(rom_cheats.as_path());
+    for cheats_path in cheat_files.into_iter().chain(cheats_path.as_deref()) {
+        match nes.cheats_mut().load(cheats_path) {
+            Ok((_, skipped)) => {
+                for line in skipped {
+                    eprintln!("{}: {}", cheats_path.display(), line);
+                }
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+    }
+
+    // Code/data log keeps growing over sessions
+    if let Some(cdl_path) = &cdl_path {
+        let previous = Some(cdl_path.as_path()).filter(|path| path.exists());
+        if let Err(err) = nes.start_code_data_log(previous) {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+
+    if let Some(record_path) = &record_path {
+        let rom_filename = Path::new(&path).file_stem().unwrap_or_default();
+        nes.record_movie(&rom_filename.to_string_lossy());
+        println!("Recording {}", record_path.display());
+    } else if let Some(play_path) = &play_path {
+        if let Err(err) = Movie::load(play_path).and_then(|movie| nes.play_movie(movie)) {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+
+    // Hashes start with the movie, which starts from power-on
+    if let Some(verify_path) = &verify_path {
+        let expected = FrameHashes::load(verify_path).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        });
+        nes.enable_frame_hashes(FrameHashes::verifying(expected));
+    } else if hash_log_path.is_some() {
+        nes.enable_frame_hashes(FrameHashes::new());
+    }
+
+    let mut nes = if debug {
+        let mut debugger = Debugger::new(nes);
+        if let Err(err) = debugger.run(io::stdin().lock(), io::stdout()) {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+        debugger.into_nes()
+    } else if let Some(port) = gdb_port {
+        let addr = format!("127.0.0.1:{}", port);
+        println!("Waiting for GDB on {}", addr);
+        let mut server = GdbServer::new(nes);
+        if let Err(err) = server.listen(&addr) {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+        server.into_nes()
+    } else if headless {
+        // As fast as it goes, for checking hashes without a window
+        while nes
+            .movie()
+            .is_some_and(|movie| movie.position() < movie.len())
+        {
+            nes.run_frame();
+        }
+        nes
+    } else {
+        // Quick save slot lives next to the ROM
+        let state_path = Path::new(&path).with_extension("state");
+        run(&mut nes, &state_path);
+        nes
+    };
+    if let Some(halt) = nes.cpu().halted() {
+        eprintln!("{}", halt.describe());
+    }
+
+    // Played movies are only written back when recording took over
+    let movie_path = record_path.or(play_path);
+    if let (Some(movie_path), Some(movie)) = (movie_path, nes.movie()) {
+        if movie.is_modified() {
+            if let Err(err) = movie.save(&movie_path) {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+    }
+
+    // A desync fails the run, after everything is written
+    let mut desync = false;
+    if let Some(hashes) = nes.take_frame_hashes() {
+        desync = hashes.first_difference().is_some();
+        if verify_path.is_some() {
+            match hashes.first_difference() {
+                Some(frame) => println!("Desync at frame {}", frame),
+                None => println!("{} frames match", hashes.compared()),
+            }
+        }
+        if let Some(hash_log_path) = &hash_log_path {
+            if let Err(err) = hashes.save(hash_log_path) {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let (Some(cdl_path), Some(log)) = (cdl_path, nes.code_data_log()) {
+        if let Err(err) = log.save(&cdl_path) {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+    if desync {
+        process::exit(1);
+    }
+}
+
+// Prints the result of every ROM and fails if one did
+fn run_test_roms(dir: &Path) -> ! {
+    match testrom::run_directory(dir, testrom::DEFAULT_FRAME_LIMIT) {
+        Ok(results) => {
+            print!("{}", testrom::format_table(dir, &results));
+            process::exit(if results.iter().all(|result| result.passed()) {
+                0
+            } else {
+                1
+            });
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
+
+// Same for the single step tests of every opcode
+fn run_cpu_tests(dir: &Path, variant: CpuVariant) -> ! {
+    match singlestep::run_directory(dir, variant) {
+        Ok(results) => {
+            print!("{}", singlestep::format_table(&results));
+            let failed = results
+                .iter()
+                .any(|result| result.status() == OpcodeStatus::Failed);
+            process::exit(if failed { 1 } else { 0 });
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(feature = "sdl2")]
+fn run(nes: &mut Nes, state_path: &Path) {
+    frontend::run(nes, state_path);
+}
+
+#[cfg(not(feature = "sdl2"))]
+fn run(_nes: &mut Nes, _state_path: &Path) {
+    eprintln!("Built without a frontend, rebuild with `--features sdl2`");
+    process::exit(1);
 }
@@ -0,0 +1,97 @@
+use super::{ChrMemory, Mapper};
+use crate::{
+    poweron::RamFill,
+    rom::{Mirroring, Rom},
+    savestate::{Section, Snapshot},
+};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+
+// Mapper 7
+// https://www.nesdev.org/wiki/AxROM
+pub struct Axrom {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    prg_bank: usize,
+    mirroring: Mirroring,
+}
+
+impl Axrom {
+    pub fn new(rom: Rom) -> Self {
+        Axrom {
+            prg_rom: rom.prg_rom,
+            chr: ChrMemory::new(rom.chr_rom),
+            prg_bank: 0,
+            mirroring: Mirroring::SingleScreenLower,
+        }
+    }
+}
+
+impl Mapper for Axrom {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x8000..=0xFFFF => {
+                let offset = self.prg_bank * PRG_BANK_SIZE + (addr - 0x8000) as usize;
+                Some(self.prg_rom[offset % self.prg_rom.len()])
+            }
+            _ => None,
+        }
+    }
+
+    // ---M -PPP
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        if addr >= 0x8000 {
+            self.prg_bank = (value & 0b0000_0111) as usize;
+            self.mirroring = if value & 0b0001_0000 == 0 {
+                Mirroring::SingleScreenLower
+            } else {
+                Mirroring::SingleScreenUpper
+            };
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        self.chr.write(addr as usize, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        (addr >= 0x8000).then(|| {
+            (self.prg_bank * PRG_BANK_SIZE + (addr - 0x8000) as usize) % self.prg_rom.len()
+        })
+    }
+    fn chr_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.chr.rom_offset(addr as usize)
+    }
+
+    fn fill_ram(&mut self, fill: &mut RamFill) {
+        self.chr.fill_ram(fill);
+    }
+}
+
+impl Snapshot for Axrom {
+    fn save(&self, section: &mut Section) {
+        section.put_u8("prg_bank", self.prg_bank as u8);
+        section.put_bool(
+            "upper_screen",
+            self.mirroring == Mirroring::SingleScreenUpper,
+        );
+        section.put_snapshot("chr", &self.chr);
+    }
+
+    fn load(&mut self, section: &Section) -> Result<(), String> {
+        self.prg_bank = section.get_u8("prg_bank")? as usize;
+        self.mirroring = if section.get_bool("upper_screen")? {
+            Mirroring::SingleScreenUpper
+        } else {
+            Mirroring::SingleScreenLower
+        };
+        section.get_snapshot("chr", &mut self.chr)
+    }
+}
@@ -0,0 +1,79 @@
+use super::{ChrMemory, Mapper};
+use crate::{
+    poweron::RamFill,
+    rom::{Mirroring, Rom},
+    savestate::{Section, Snapshot},
+};
+
+const CHR_BANK_SIZE: usize = 0x2000;
+
+// Mapper 3
+// https://www.nesdev.org/wiki/CNROM
+pub struct Cnrom {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    mirroring: Mirroring,
+    chr_bank: usize,
+}
+
+impl Cnrom {
+    pub fn new(rom: Rom) -> Self {
+        Cnrom {
+            prg_rom: rom.prg_rom,
+            chr: ChrMemory::new(rom.chr_rom),
+            mirroring: rom.screen_mirroring,
+            chr_bank: 0,
+        }
+    }
+}
+
+impl Mapper for Cnrom {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x8000..=0xFFFF => Some(self.prg_rom[(addr - 0x8000) as usize % self.prg_rom.len()]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        if addr >= 0x8000 {
+            self.chr_bank = value as usize;
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_bank * CHR_BANK_SIZE + addr as usize)
+    }
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        self.chr
+            .write(self.chr_bank * CHR_BANK_SIZE + addr as usize, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        (addr >= 0x8000).then(|| (addr - 0x8000) as usize % self.prg_rom.len())
+    }
+    fn chr_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.chr
+            .rom_offset(self.chr_bank * CHR_BANK_SIZE + addr as usize)
+    }
+
+    fn fill_ram(&mut self, fill: &mut RamFill) {
+        self.chr.fill_ram(fill);
+    }
+}
+
+impl Snapshot for Cnrom {
+    fn save(&self, section: &mut Section) {
+        section.put_u8("chr_bank", self.chr_bank as u8);
+        section.put_snapshot("chr", &self.chr);
+    }
+
+    fn load(&mut self, section: &Section) -> Result<(), String> {
+        self.chr_bank = section.get_u8("chr_bank")? as usize;
+        section.get_snapshot("chr", &mut self.chr)
+    }
+}
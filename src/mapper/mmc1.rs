@@ -0,0 +1,201 @@
+use super::{ChrMemory, Mapper, PRG_RAM_SIZE};
+use crate::{
+    poweron::RamFill,
+    rom::{Mirroring, Rom},
+    savestate::{Section, Snapshot},
+};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x1000;
+const SHIFT_RESET: u8 = 0b1_0000;
+
+// Mapper 1
+// https://www.nesdev.org/wiki/MMC1
+pub struct Mmc1 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: ChrMemory,
+    // Bit 4 marks that 5 bits were shifted in
+    shift_register: u8,
+    /* CPPMM
+       |||||
+       |||++- Mirroring
+       |++--- PRG ROM bank mode
+       +----- CHR ROM bank mode
+    */
+    control: u8,
+    chr_bank0: u8,
+    chr_bank1: u8,
+    prg_bank: u8,
+    cycle: u64,
+    last_write_cycle: u64,
+}
+
+impl Mmc1 {
+    pub fn new(rom: Rom) -> Self {
+        Mmc1 {
+            prg_rom: rom.prg_rom,
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            chr: ChrMemory::new(rom.chr_rom),
+            shift_register: SHIFT_RESET,
+            // Last bank is fixed at $C000 on power up
+            control: 0b0_1100,
+            chr_bank0: 0,
+            chr_bank1: 0,
+            prg_bank: 0,
+            cycle: 0,
+            last_write_cycle: u64::MAX,
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.prg_bank & 0b1_0000 == 0
+    }
+
+    fn prg_offset(&self, addr: u16) -> usize {
+        let bank_count = self.prg_rom.len() / PRG_BANK_SIZE;
+        let bank = (self.prg_bank & 0b1111) as usize;
+        let offset = (addr as usize) & (PRG_BANK_SIZE - 1);
+
+        let bank = match ((self.control >> 2) & 0b11, addr) {
+            // 32Kb mode ignores low bit of bank number
+            (0 | 1, 0x8000..=0xBFFF) => bank & !1,
+            (0 | 1, _) => bank | 1,
+            // First bank is fixed at $8000
+            (2, 0x8000..=0xBFFF) => 0,
+            (2, _) => bank,
+            // Last bank is fixed at $C000
+            (3, 0x8000..=0xBFFF) => bank,
+            (3, _) => bank_count - 1,
+            _ => unreachable!(),
+        };
+
+        (bank % bank_count) * PRG_BANK_SIZE + offset
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let offset = (addr as usize) & (CHR_BANK_SIZE - 1);
+        let bank = if self.control & 0b1_0000 == 0 {
+            // 8Kb mode ignores low bit of bank number
+            (self.chr_bank0 & !1) as usize + (addr as usize / CHR_BANK_SIZE)
+        } else if addr < 0x1000 {
+            self.chr_bank0 as usize
+        } else {
+            self.chr_bank1 as usize
+        };
+
+        bank * CHR_BANK_SIZE + offset
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x8000..=0x9FFF => self.control = value,
+            0xA000..=0xBFFF => self.chr_bank0 = value,
+            0xC000..=0xDFFF => self.chr_bank1 = value,
+            0xE000..=0xFFFF => self.prg_bank = value,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                Some(self.prg_ram[(addr - 0x6000) as usize])
+            }
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_offset(addr)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                self.prg_ram[(addr - 0x6000) as usize] = value;
+            }
+            0x8000..=0xFFFF => {
+                // Serial port ignores writes on consecutive cycles, e.g. the second write of RMW
+                let consecutive = self.last_write_cycle.wrapping_add(1) == self.cycle;
+                self.last_write_cycle = self.cycle;
+                if consecutive {
+                    return;
+                }
+
+                if value & 0b1000_0000 != 0 {
+                    self.shift_register = SHIFT_RESET;
+                    self.control |= 0b0_1100;
+                    return;
+                }
+
+                let complete = self.shift_register & 1 == 1;
+                self.shift_register = (self.shift_register >> 1) | ((value & 1) << 4);
+                if complete {
+                    self.write_register(addr, self.shift_register);
+                    self.shift_register = SHIFT_RESET;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        self.chr.write(self.chr_offset(addr), value);
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        (addr >= 0x8000).then(|| self.prg_offset(addr))
+    }
+    fn chr_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.chr.rom_offset(self.chr_offset(addr))
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0b11 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            3 => Mirroring::Horizontal,
+            _ => unreachable!(),
+        }
+    }
+
+    fn tick(&mut self) {
+        self.cycle += 1;
+    }
+
+    fn fill_ram(&mut self, fill: &mut RamFill) {
+        fill.fill(&mut self.prg_ram);
+        self.chr.fill_ram(fill);
+    }
+}
+
+impl Snapshot for Mmc1 {
+    fn save(&self, section: &mut Section) {
+        section.put_bytes("prg_ram", &self.prg_ram);
+        section.put_snapshot("chr", &self.chr);
+        section.put_u8("shift_register", self.shift_register);
+        section.put_u8("control", self.control);
+        section.put_u8("chr_bank0", self.chr_bank0);
+        section.put_u8("chr_bank1", self.chr_bank1);
+        section.put_u8("prg_bank", self.prg_bank);
+        section.put_u64("cycle", self.cycle);
+        section.put_u64("last_write_cycle", self.last_write_cycle);
+    }
+
+    fn load(&mut self, section: &Section) -> Result<(), String> {
+        section.get_bytes_into("prg_ram", &mut self.prg_ram)?;
+        section.get_snapshot("chr", &mut self.chr)?;
+        self.shift_register = section.get_u8("shift_register")?;
+        self.control = section.get_u8("control")?;
+        self.chr_bank0 = section.get_u8("chr_bank0")?;
+        self.chr_bank1 = section.get_u8("chr_bank1")?;
+        self.prg_bank = section.get_u8("prg_bank")?;
+        self.cycle = section.get_u64("cycle")?;
+        self.last_write_cycle = section.get_u64("last_write_cycle")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+use super::{ChrMemory, Mapper, PRG_RAM_SIZE};
+use crate::{
+    poweron::RamFill,
+    rom::{Mirroring, Rom},
+    savestate::{Section, Snapshot},
+};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+// Mapper 4
+// https://www.nesdev.org/wiki/MMC3
+pub struct Mmc3 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: ChrMemory,
+    four_screen: bool,
+    /* CPMx xRRR
+       |||   |||
+       |||   +++- Register to update on the next bank data write
+       ||+------- Nothing on the MMC3
+       |+-------- PRG ROM bank mode
+       +--------- CHR A12 inversion
+    */
+    bank_select: u8,
+    registers: [u8; 8],
+    horizontal_mirroring: bool,
+    prg_ram_enabled: bool,
+    prg_ram_write_protect: bool,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    last_a12: bool,
+}
+
+impl Mmc3 {
+    pub fn new(rom: Rom) -> Self {
+        Mmc3 {
+            prg_rom: rom.prg_rom,
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            chr: ChrMemory::new(rom.chr_rom),
+            four_screen: rom.screen_mirroring == Mirroring::FourScreen,
+            bank_select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            horizontal_mirroring: false,
+            prg_ram_enabled: true,
+            prg_ram_write_protect: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            last_a12: false,
+        }
+    }
+
+    fn prg_offset(&self, addr: u16) -> usize {
+        let bank_count = self.prg_rom.len() / PRG_BANK_SIZE;
+        let second_last = bank_count - 2;
+        let swapped = self.bank_select & 0b0100_0000 != 0;
+
+        let bank = match (addr, swapped) {
+            (0x8000..=0x9FFF, false) | (0xC000..=0xDFFF, true) => self.registers[6] as usize,
+            (0x8000..=0x9FFF, true) | (0xC000..=0xDFFF, false) => second_last,
+            (0xA000..=0xBFFF, _) => self.registers[7] as usize,
+            _ => bank_count - 1,
+        };
+
+        (bank % bank_count) * PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1))
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        // Inversion swaps the 2Kb and 1Kb halves
+        let addr = if self.bank_select & 0b1000_0000 != 0 {
+            addr ^ 0x1000
+        } else {
+            addr
+        };
+
+        let bank = match addr {
+            0x0000..=0x07FF => (self.registers[0] & !1) as usize + (addr as usize >> 10 & 1),
+            0x0800..=0x0FFF => (self.registers[1] & !1) as usize + (addr as usize >> 10 & 1),
+            0x1000..=0x13FF => self.registers[2] as usize,
+            0x1400..=0x17FF => self.registers[3] as usize,
+            0x1800..=0x1BFF => self.registers[4] as usize,
+            _ => self.registers[5] as usize,
+        };
+
+        bank * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1))
+    }
+
+    // Counter is clocked by rising edges of PPU A12
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    fn watch_a12(&mut self, addr: u16) {
+        let a12 = addr & 0x1000 != 0;
+        if a12 && !self.last_a12 {
+            self.clock_irq_counter();
+        }
+        self.last_a12 = a12;
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled => Some(self.prg_ram[(addr - 0x6000) as usize]),
+            0x8000..=0xFFFF => Some(self.prg_rom[self.prg_offset(addr)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        let even = addr & 1 == 0;
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled && !self.prg_ram_write_protect => {
+                self.prg_ram[(addr - 0x6000) as usize] = value;
+            }
+            0x8000..=0x9FFF if even => self.bank_select = value,
+            0x8000..=0x9FFF => self.registers[(self.bank_select & 0b111) as usize] = value,
+            0xA000..=0xBFFF if even => self.horizontal_mirroring = value & 1 == 1,
+            0xA000..=0xBFFF => {
+                self.prg_ram_enabled = value & 0b1000_0000 != 0;
+                self.prg_ram_write_protect = value & 0b0100_0000 != 0;
+            }
+            0xC000..=0xDFFF if even => self.irq_latch = value,
+            0xC000..=0xDFFF => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            0xE000..=0xFFFF if even => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            0xE000..=0xFFFF => self.irq_enabled = true,
+            _ => (),
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.watch_a12(addr);
+        self.ppu_peek(addr)
+    }
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        self.watch_a12(addr);
+        self.chr.write(self.chr_offset(addr), value);
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        (addr >= 0x8000).then(|| self.prg_offset(addr))
+    }
+    fn chr_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.chr.rom_offset(self.chr_offset(addr))
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.four_screen {
+            Mirroring::FourScreen
+        } else if self.horizontal_mirroring {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+    fn irq_follows_ppu(&self) -> bool {
+        self.irq_enabled
+    }
+
+    fn fill_ram(&mut self, fill: &mut RamFill) {
+        fill.fill(&mut self.prg_ram);
+        self.chr.fill_ram(fill);
+    }
+}
+
+impl Snapshot for Mmc3 {
+    fn save(&self, section: &mut Section) {
+        section.put_bytes("prg_ram", &self.prg_ram);
+        section.put_snapshot("chr", &self.chr);
+        section.put_u8("bank_select", self.bank_select);
+        section.put_bytes("registers", &self.registers);
+        section.put_bool("horizontal_mirroring", self.horizontal_mirroring);
+        section.put_bool("prg_ram_enabled", self.prg_ram_enabled);
+        section.put_bool("prg_ram_write_protect", self.prg_ram_write_protect);
+        section.put_u8("irq_latch", self.irq_latch);
+        section.put_u8("irq_counter", self.irq_counter);
+        section.put_bool("irq_reload", self.irq_reload);
+        section.put_bool("irq_enabled", self.irq_enabled);
+        section.put_bool("irq_pending", self.irq_pending);
+        section.put_bool("last_a12", self.last_a12);
+    }
+
+    fn load(&mut self, section: &Section) -> Result<(), String> {
+        section.get_bytes_into("prg_ram", &mut self.prg_ram)?;
+        section.get_snapshot("chr", &mut self.chr)?;
+        self.bank_select = section.get_u8("bank_select")?;
+        section.get_bytes_into("registers", &mut self.registers)?;
+        self.horizontal_mirroring = section.get_bool("horizontal_mirroring")?;
+        self.prg_ram_enabled = section.get_bool("prg_ram_enabled")?;
+        self.prg_ram_write_protect = section.get_bool("prg_ram_write_protect")?;
+        self.irq_latch = section.get_u8("irq_latch")?;
+        self.irq_counter = section.get_u8("irq_counter")?;
+        self.irq_reload = section.get_bool("irq_reload")?;
+        self.irq_enabled = section.get_bool("irq_enabled")?;
+        self.irq_pending = section.get_bool("irq_pending")?;
+        self.last_a12 = section.get_bool("last_a12")?;
+        Ok(())
+    }
+}
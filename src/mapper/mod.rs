@@ -0,0 +1,141 @@
+mod axrom;
+mod cnrom;
+mod mmc1;
+mod mmc3;
+mod nrom;
+mod uxrom;
+
+use crate::{
+    poweron::RamFill,
+    rom::{Mirroring, Rom},
+    savestate::{Section, Snapshot},
+};
+use axrom::Axrom;
+use cnrom::Cnrom;
+use mmc1::Mmc1;
+use mmc3::Mmc3;
+use nrom::Nrom;
+use uxrom::Uxrom;
+
+pub const PRG_RAM_SIZE: usize = 0x2000;
+const CHR_RAM_SIZE: usize = 0x2000;
+
+// Cartridge hardware, sees CPU $4020-$FFFF and PPU $0000-$1FFF
+// https://www.nesdev.org/wiki/Mapper
+// Snapshot covers everything but ROM, which the save state header identifies
+pub trait Mapper: Snapshot {
+    // None means nothing drives the bus and open bus is read
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        self.cpu_peek(addr)
+    }
+    fn cpu_peek(&self, addr: u16) -> Option<u8>;
+    fn cpu_write(&mut self, addr: u16, value: u8);
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.ppu_peek(addr)
+    }
+    fn ppu_peek(&self, addr: u16) -> u8;
+    fn ppu_write(&mut self, addr: u16, value: u8);
+
+    fn mirroring(&self) -> Mirroring;
+
+    // Offsets into PRG and CHR ROM the addresses are currently banked to,
+    // None for RAM and registers. Used by the code/data logger
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize>;
+    fn chr_rom_offset(&self, addr: u16) -> Option<usize>;
+
+    // Cartridge IRQ line
+    fn irq(&self) -> bool {
+        false
+    }
+    // The IRQ line can go up from what the PPU fetches, so a PPU running
+    // behind has to be kept in step
+    fn irq_follows_ppu(&self) -> bool {
+        false
+    }
+
+    // Called every CPU cycle
+    fn tick(&mut self) {}
+
+    // PRG RAM then CHR RAM, whichever the cartridge has
+    fn fill_ram(&mut self, fill: &mut RamFill);
+}
+
+pub fn new_mapper(rom: Rom) -> Result<Box<dyn Mapper>, String> {
+    Ok(match rom.mapper() {
+        0 => Box::new(Nrom::new(rom)),
+        1 => Box::new(Mmc1::new(rom)),
+        2 => Box::new(Uxrom::new(rom)),
+        3 => Box::new(Cnrom::new(rom)),
+        4 => Box::new(Mmc3::new(rom)),
+        7 => Box::new(Axrom::new(rom)),
+        mapper => return Err(format!("Mapper {} is not supported yet", mapper)),
+    })
+}
+
+// CHR ROM or, if cartridge has none, CHR RAM
+pub struct ChrMemory {
+    data: Vec<u8>,
+    writable: bool,
+}
+
+impl ChrMemory {
+    pub fn new(chr_rom: Vec<u8>) -> Self {
+        if chr_rom.is_empty() {
+            ChrMemory {
+                data: vec![0; CHR_RAM_SIZE],
+                writable: true,
+            }
+        } else {
+            ChrMemory {
+                data: chr_rom,
+                writable: false,
+            }
+        }
+    }
+
+    pub fn read(&self, offset: usize) -> u8 {
+        self.data[offset % self.data.len()]
+    }
+    pub fn write(&mut self, offset: usize, value: u8) {
+        if self.writable {
+            let len = self.data.len();
+            self.data[offset % len] = value;
+        }
+    }
+
+    // Nothing happens to CHR ROM
+    pub fn fill_ram(&mut self, fill: &mut RamFill) {
+        if self.writable {
+            fill.fill(&mut self.data);
+        }
+    }
+
+    // None when this is CHR RAM
+    pub fn rom_offset(&self, offset: usize) -> Option<usize> {
+        (!self.writable).then(|| offset % self.data.len())
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+// CHR ROM is never saved
+impl Snapshot for ChrMemory {
+    fn save(&self, section: &mut Section) {
+        if self.writable {
+            section.put_bytes("ram", &self.data);
+        }
+    }
+
+    fn load(&mut self, section: &Section) -> Result<(), String> {
+        if self.writable {
+            section.get_bytes_into("ram", &mut self.data)?;
+        }
+        Ok(())
+    }
+}
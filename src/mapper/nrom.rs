@@ -0,0 +1,78 @@
+use super::{ChrMemory, Mapper, PRG_RAM_SIZE};
+use crate::{
+    poweron::RamFill,
+    rom::{Mirroring, Rom},
+    savestate::{Section, Snapshot},
+};
+
+// Mapper 0
+// https://www.nesdev.org/wiki/NROM
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: ChrMemory,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn new(rom: Rom) -> Self {
+        Nrom {
+            prg_rom: rom.prg_rom,
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            chr: ChrMemory::new(rom.chr_rom),
+            mirroring: rom.screen_mirroring,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF => Some(self.prg_ram[(addr - 0x6000) as usize]),
+            // NROM-128 is mirrored into $C000-$FFFF
+            0x8000..=0xFFFF => Some(self.prg_rom[(addr - 0x8000) as usize % self.prg_rom.len()]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            self.prg_ram[(addr - 0x6000) as usize] = value;
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        self.chr.write(addr as usize, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        (addr >= 0x8000).then(|| (addr - 0x8000) as usize % self.prg_rom.len())
+    }
+    fn chr_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.chr.rom_offset(addr as usize)
+    }
+
+    fn fill_ram(&mut self, fill: &mut RamFill) {
+        fill.fill(&mut self.prg_ram);
+        self.chr.fill_ram(fill);
+    }
+}
+
+impl Snapshot for Nrom {
+    fn save(&self, section: &mut Section) {
+        section.put_bytes("prg_ram", &self.prg_ram);
+        section.put_snapshot("chr", &self.chr);
+    }
+
+    fn load(&mut self, section: &Section) -> Result<(), String> {
+        section.get_bytes_into("prg_ram", &mut self.prg_ram)?;
+        section.get_snapshot("chr", &mut self.chr)
+    }
+}
@@ -0,0 +1,94 @@
+use super::{ChrMemory, Mapper};
+use crate::{
+    poweron::RamFill,
+    rom::{Mirroring, Rom},
+    savestate::{Section, Snapshot},
+};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+
+// Mapper 2
+// https://www.nesdev.org/wiki/UxROM
+pub struct Uxrom {
+    prg_rom: Vec<u8>,
+    chr: ChrMemory,
+    mirroring: Mirroring,
+    prg_bank: usize,
+}
+
+impl Uxrom {
+    pub fn new(rom: Rom) -> Self {
+        Uxrom {
+            prg_rom: rom.prg_rom,
+            chr: ChrMemory::new(rom.chr_rom),
+            mirroring: rom.screen_mirroring,
+            prg_bank: 0,
+        }
+    }
+}
+
+impl Mapper for Uxrom {
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        let bank_count = self.prg_rom.len() / PRG_BANK_SIZE;
+        match addr {
+            // Switchable bank
+            0x8000..=0xBFFF => {
+                let bank = self.prg_bank % bank_count;
+                Some(self.prg_rom[bank * PRG_BANK_SIZE + (addr - 0x8000) as usize])
+            }
+            // Fixed to the last bank
+            0xC000..=0xFFFF => {
+                let bank = bank_count - 1;
+                Some(self.prg_rom[bank * PRG_BANK_SIZE + (addr - 0xC000) as usize])
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, value: u8) {
+        if addr >= 0x8000 {
+            self.prg_bank = value as usize;
+        }
+    }
+
+    fn ppu_peek(&self, addr: u16) -> u8 {
+        self.chr.read(addr as usize)
+    }
+    fn ppu_write(&mut self, addr: u16, value: u8) {
+        self.chr.write(addr as usize, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        let bank_count = self.prg_rom.len() / PRG_BANK_SIZE;
+        match addr {
+            0x8000..=0xBFFF => {
+                Some(self.prg_bank % bank_count * PRG_BANK_SIZE + (addr - 0x8000) as usize)
+            }
+            0xC000..=0xFFFF => Some((bank_count - 1) * PRG_BANK_SIZE + (addr - 0xC000) as usize),
+            _ => None,
+        }
+    }
+    fn chr_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.chr.rom_offset(addr as usize)
+    }
+
+    fn fill_ram(&mut self, fill: &mut RamFill) {
+        self.chr.fill_ram(fill);
+    }
+}
+
+impl Snapshot for Uxrom {
+    fn save(&self, section: &mut Section) {
+        section.put_u8("prg_bank", self.prg_bank as u8);
+        section.put_snapshot("chr", &self.chr);
+    }
+
+    fn load(&mut self, section: &Section) -> Result<(), String> {
+        self.prg_bank = section.get_u8("prg_bank")? as usize;
+        section.get_snapshot("chr", &mut self.chr)
+    }
+}
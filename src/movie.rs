@@ -0,0 +1,486 @@
+use std::{fs, path::Path};
+
+use crate::joypad::JoypadButton;
+
+// FCEUX text movies: `key value` header lines, then one line per frame like
+// |0|R..U...A|........||
+// https://fceux.com/web/help/fm2.html
+const FM2_VERSION: u32 = 3;
+
+// Command field bits, the console buttons pressed at the start of the frame
+pub const SOFT_RESET: u8 = 0b0000_0001;
+pub const POWER: u8 = 0b0000_0010;
+
+// Button of each character of an input field, left to right
+const BUTTON_ORDER: [(char, JoypadButton); 8] = [
+    ('R', JoypadButton::RIGHT),
+    ('L', JoypadButton::LEFT),
+    ('D', JoypadButton::DOWN),
+    ('U', JoypadButton::UP),
+    ('T', JoypadButton::START),
+    ('S', JoypadButton::SELECT),
+    ('B', JoypadButton::B),
+    ('A', JoypadButton::A),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MovieFrame {
+    pub commands: u8,
+    pub controllers: [JoypadButton; 2],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MovieMode {
+    Recording,
+    Playing,
+    // Played to the end, the controllers are free again
+    Finished,
+}
+
+pub struct Movie {
+    pub rom_filename: String,
+    pub rom_md5: [u8; 16],
+    pub guid: String,
+    pub rerecord_count: u32,
+    // Loading a state during playback goes on recording from there
+    // instead of following the movie
+    pub read_only: bool,
+    frames: Vec<MovieFrame>,
+    mode: MovieMode,
+    // Next frame to play or record
+    position: usize,
+    pending_commands: u8,
+    modified: bool,
+}
+
+impl Movie {
+    // Recording, starting from power-on
+    pub fn new(rom_filename: &str, rom_md5: [u8; 16]) -> Self {
+        let id = rand::random::<u128>();
+        Movie {
+            rom_filename: rom_filename.to_string(),
+            rom_md5,
+            guid: format!(
+                "{:08X}-{:04X}-{:04X}-{:04X}-{:012X}",
+                id >> 96,
+                (id >> 80) & 0xFFFF,
+                (id >> 64) & 0xFFFF,
+                (id >> 48) & 0xFFFF,
+                id & 0xFFFF_FFFF_FFFF
+            ),
+            rerecord_count: 0,
+            read_only: false,
+            frames: Vec::new(),
+            mode: MovieMode::Recording,
+            position: 0,
+            pending_commands: 0,
+            modified: false,
+        }
+    }
+
+    // Ready to play read-only
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut movie = Movie::new("", [0; 16]);
+        movie.mode = MovieMode::Playing;
+        movie.read_only = true;
+
+        let mut rom_md5 = None;
+        for (number, line) in text.lines().enumerate() {
+            let bad_line = |err: &str| format!("Line {}: {}", number + 1, err);
+            if line.starts_with('|') {
+                movie
+                    .frames
+                    .push(parse_frame(line).ok_or_else(|| bad_line("bad input line"))?);
+                continue;
+            }
+
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "version" if value != FM2_VERSION.to_string() => {
+                    return Err(format!("FM2 version {} is not supported", value))
+                }
+                "binary" if value != "0" => {
+                    return Err("Binary FM2 movies are not supported".to_string())
+                }
+                "romFilename" => movie.rom_filename = value.to_string(),
+                "guid" => movie.guid = value.to_string(),
+                "rerecordCount" => {
+                    movie.rerecord_count =
+                        value.parse().map_err(|_| bad_line("bad rerecord count"))?
+                }
+                "romChecksum" => {
+                    let md5 = value
+                        .strip_prefix("base64:")
+                        .and_then(base64_decode)
+                        .and_then(|bytes| <[u8; 16]>::try_from(bytes).ok())
+                        .ok_or_else(|| bad_line("bad ROM checksum"))?;
+                    rom_md5 = Some(md5);
+                }
+                "port0" | "port1" if value != "0" && value != "1" => {
+                    return Err(bad_line("only gamepads are supported"))
+                }
+                _ => (),
+            }
+        }
+
+        movie.rom_md5 = rom_md5.ok_or("Movie has no ROM checksum")?;
+        Ok(movie)
+    }
+
+    pub fn to_fm2(&self) -> String {
+        let mut text = format!(
+            "version {}\nemuVersion 22020\nrerecordCount {}\npalFlag 0\nromFilename {}\n\
+             romChecksum base64:{}\nguid {}\nfourscore 0\nmicrophone 0\n\
+             port0 1\nport1 1\nport2 0\nFDS 0\nNewPPU 0\n",
+            FM2_VERSION,
+            self.rerecord_count,
+            self.rom_filename,
+            base64_encode(&self.rom_md5),
+            self.guid
+        );
+        for frame in &self.frames {
+            text += &format!("|{}|", frame.commands);
+            for buttons in frame.controllers {
+                for (letter, button) in BUTTON_ORDER {
+                    text.push(if buttons.contains(button) {
+                        letter
+                    } else {
+                        '.'
+                    });
+                }
+                text.push('|');
+            }
+            text += "|\n";
+        }
+        text
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("Can't read {}: {}", path.display(), err))?;
+        Movie::parse(&text)
+    }
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, self.to_fm2())
+            .map_err(|err| format!("Can't write {}: {}", path.display(), err))
+    }
+
+    // Input for the frame about to run. While recording the controllers
+    // as they are now go in, while playing they are replaced
+    pub fn next_frame(&mut self, controllers: [JoypadButton; 2]) -> Option<MovieFrame> {
+        match self.mode {
+            MovieMode::Recording => {
+                let frame = MovieFrame {
+                    commands: std::mem::take(&mut self.pending_commands),
+                    controllers,
+                };
+                self.frames.push(frame);
+                self.position += 1;
+                self.modified = true;
+                Some(frame)
+            }
+            MovieMode::Playing if self.position < self.frames.len() => {
+                self.position += 1;
+                Some(self.frames[self.position - 1])
+            }
+            _ => {
+                self.mode = MovieMode::Finished;
+                None
+            }
+        }
+    }
+
+    // Console button pressed by the user, false if the movie doesn't take it
+    // and it should act at once. Recorded ones act on the next frame
+    pub fn command(&mut self, commands: u8) -> bool {
+        match self.mode {
+            MovieMode::Recording => {
+                self.pending_commands |= commands;
+                true
+            }
+            // The movie presses the buttons itself
+            MovieMode::Playing => true,
+            MovieMode::Finished => false,
+        }
+    }
+
+    // A save state from `position` frames in was loaded
+    pub fn check_seek(&self, position: usize) -> Result<(), String> {
+        if position > self.frames.len() {
+            return Err(format!(
+                "Save state is from frame {}, after the end of the movie at {}",
+                position,
+                self.frames.len()
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn seek(&mut self, position: usize) -> Result<(), String> {
+        self.check_seek(position)?;
+
+        self.position = position;
+        self.pending_commands = 0;
+        if self.read_only && self.mode != MovieMode::Recording {
+            self.mode = if position < self.frames.len() {
+                MovieMode::Playing
+            } else {
+                MovieMode::Finished
+            };
+        } else {
+            self.frames.truncate(position);
+            self.mode = MovieMode::Recording;
+            self.rerecord_count += 1;
+            self.modified = true;
+        }
+        Ok(())
+    }
+
+    pub fn mode(&self) -> MovieMode {
+        self.mode
+    }
+    pub fn position(&self) -> usize {
+        self.position
+    }
+    pub fn frames(&self) -> &[MovieFrame] {
+        &self.frames
+    }
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+    // Has frames recorded since it was made or loaded
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    // Frame counter for display
+    pub fn status(&self) -> String {
+        let text = match self.mode {
+            MovieMode::Recording => format!("Recording frame {}", self.position),
+            MovieMode::Playing => format!("Playing frame {}/{}", self.position, self.len()),
+            MovieMode::Finished => format!("Movie finished {}/{}", self.position, self.len()),
+        };
+        if self.read_only && self.mode != MovieMode::Recording {
+            text + " (read-only)"
+        } else {
+            text
+        }
+    }
+}
+
+fn parse_frame(line: &str) -> Option<MovieFrame> {
+    let fields: Vec<&str> = line.split('|').collect();
+    if fields.len() < 5 {
+        return None;
+    }
+
+    let mut controllers = [JoypadButton::empty(); 2];
+    for (buttons, field) in controllers.iter_mut().zip(&fields[2..4]) {
+        if field.is_empty() {
+            continue;
+        }
+        if field.chars().count() != BUTTON_ORDER.len() {
+            return None;
+        }
+        for (letter, (_, button)) in field.chars().zip(BUTTON_ORDER) {
+            // Anything but a dot or space is a pressed button
+            buttons.set(button, letter != '.' && letter != ' ');
+        }
+    }
+
+    Some(MovieFrame {
+        commands: fields[1].trim().parse().ok()?,
+        controllers,
+    })
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut text = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(BASE64_ALPHABET[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    for letter in text.trim_end_matches('=').bytes() {
+        let value = BASE64_ALPHABET.iter().position(|&l| l == letter)? as u32;
+        bits = bits << 6 | value;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            data.push((bits >> bit_count) as u8);
+        }
+    }
+    Some(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "version 3\nemuVersion 22020\nrerecordCount 7\npalFlag 0\n\
+        romFilename Game\nromChecksum base64:AAECAwQFBgcICQoLDA0ODw==\n\
+        guid 01234567-89AB-CDEF-0123-456789ABCDEF\nfourscore 0\nmicrophone 0\n\
+        port0 1\nport1 1\nport2 0\nFDS 0\nNewPPU 0\n";
+    const FRAMES: &str = "|2|........|........||\n|0|R..U...A|........||\n|1|........|.L..T.B.||\n";
+
+    fn movie() -> Movie {
+        Movie::parse(&format!("{}{}", HEADER, FRAMES)).unwrap()
+    }
+
+    #[test]
+    fn fm2_round_trip() {
+        let movie = movie();
+        assert_eq!(movie.rom_filename, "Game");
+        assert_eq!(movie.rom_md5, std::array::from_fn(|i| i as u8));
+        assert_eq!(movie.guid, "01234567-89AB-CDEF-0123-456789ABCDEF");
+        assert_eq!(movie.rerecord_count, 7);
+        assert_eq!(
+            movie.frames()[1],
+            MovieFrame {
+                commands: 0,
+                controllers: [
+                    JoypadButton::RIGHT | JoypadButton::UP | JoypadButton::A,
+                    JoypadButton::empty()
+                ],
+            }
+        );
+        assert_eq!(movie.frames()[0].commands, POWER);
+        assert_eq!(
+            movie.frames()[2].controllers[1],
+            JoypadButton::LEFT | JoypadButton::START | JoypadButton::B
+        );
+        assert_eq!(movie.to_fm2(), format!("{}{}", HEADER, FRAMES));
+    }
+
+    // FCEUX marks pressed buttons with any letter and may leave a port empty
+    #[test]
+    fn loose_input_lines() {
+        let movie = Movie::parse(&format!("{}|0|rl   x  ||\n", HEADER)).unwrap();
+        assert_eq!(
+            movie.frames()[0].controllers,
+            [
+                JoypadButton::RIGHT | JoypadButton::LEFT | JoypadButton::SELECT,
+                JoypadButton::empty()
+            ]
+        );
+    }
+
+    #[test]
+    fn header_checks() {
+        let error = |text: &str| Movie::parse(text).err().unwrap();
+        assert_eq!(
+            error(&HEADER.replace("version 3", "version 2")),
+            "FM2 version 2 is not supported"
+        );
+        assert_eq!(
+            error(&format!("binary 1\n{}", HEADER)),
+            "Binary FM2 movies are not supported"
+        );
+        assert_eq!(
+            error(&HEADER.replace("port1 1", "port1 2")),
+            "Line 11: only gamepads are supported"
+        );
+        assert_eq!(
+            error(&HEADER.replace("AAECAwQFBgcICQoLDA0ODw==", "AAEC")),
+            "Line 6: bad ROM checksum"
+        );
+        assert_eq!(
+            error(&HEADER.replace("rerecordCount 7", "rerecordCount many")),
+            "Line 3: bad rerecord count"
+        );
+        assert_eq!(
+            error("version 3\n|0|........|........||\n"),
+            "Movie has no ROM checksum"
+        );
+        assert_eq!(
+            error(&format!("{}|0|RL|........||\n", HEADER)),
+            "Line 15: bad input line"
+        );
+    }
+
+    #[test]
+    fn base64() {
+        for (data, text) in [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(base64_encode(data.as_bytes()), text);
+            assert_eq!(base64_decode(text).unwrap(), data.as_bytes());
+        }
+        let bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(base64_decode(&base64_encode(&bytes)).unwrap(), bytes);
+        assert_eq!(base64_decode("Zm9v!"), None);
+    }
+
+    // Loading a state while read-only moves within the movie, read-write
+    // throws away what came after and records from there
+    #[test]
+    fn read_only_and_taking_over() {
+        let mut movie = movie();
+        let pressed = [JoypadButton::B, JoypadButton::empty()];
+        assert_eq!(movie.mode(), MovieMode::Playing);
+        assert!(movie.read_only);
+        assert_eq!(movie.next_frame(pressed), Some(movie.frames()[0]));
+        assert_eq!(movie.next_frame(pressed), Some(movie.frames()[1]));
+        assert!(movie.command(SOFT_RESET));
+        assert_eq!(movie.status(), "Playing frame 2/3 (read-only)");
+
+        movie.seek(1).unwrap();
+        assert_eq!((movie.position(), movie.len()), (1, 3));
+        assert_eq!(movie.next_frame(pressed), Some(movie.frames()[1]));
+        assert_eq!(movie.rerecord_count, 7);
+        assert!(!movie.is_modified());
+
+        movie.next_frame(pressed);
+        assert_eq!(movie.next_frame(pressed), None);
+        assert_eq!(movie.mode(), MovieMode::Finished);
+        assert!(!movie.command(SOFT_RESET));
+        assert_eq!(
+            movie.seek(4),
+            Err("Save state is from frame 4, after the end of the movie at 3".to_string())
+        );
+
+        movie.read_only = false;
+        movie.seek(1).unwrap();
+        assert_eq!(movie.mode(), MovieMode::Recording);
+        assert_eq!((movie.position(), movie.len()), (1, 1));
+        assert_eq!(movie.rerecord_count, 8);
+        assert!(movie.is_modified());
+
+        // Console buttons go in with the next frame recorded
+        assert!(movie.command(SOFT_RESET));
+        let recorded = movie.next_frame(pressed).unwrap();
+        assert_eq!(recorded.commands, SOFT_RESET);
+        assert_eq!(recorded.controllers, pressed);
+        assert_eq!(movie.next_frame(pressed).unwrap().commands, 0);
+        assert_eq!(movie.len(), 3);
+        assert_eq!(movie.status(), "Recording frame 3");
+    }
+}
@@ -0,0 +1,446 @@
+use std::path::Path;
+
+use crate::{
+    bus::Bus,
+    cdl::CodeDataLog,
+    cheat::Cheats,
+    checksum,
+    cpu::{Cpu, CpuBus},
+    framehash::FrameHashes,
+    joypad::{ControllerPort, JoypadButton},
+    movie::{self, Movie},
+    poweron::PowerOnState,
+    rewind::Rewind,
+    rom::Rom,
+    savestate::{SaveState, Section},
+};
+
+// Whole console: the CPU and everything it sees through its bus
+pub struct Nes {
+    cpu: Cpu,
+    bus: Bus,
+    rom_data: Vec<u8>,
+    rom_checksum: u32,
+    rewind: Option<Rewind>,
+    movie: Option<Movie>,
+    power_on_state: PowerOnState,
+    frame_hashes: Option<FrameHashes>,
+    run_ahead: Option<RunAhead>,
+}
+
+// Frames emulated past the real one only for their video, which shows the
+// effect of input sooner than the console itself would
+struct RunAhead {
+    frames: usize,
+    // A second console running ahead leaves the audio of this one alone
+    second: Option<Box<Nes>>,
+    frame: Vec<u8>,
+}
+
+impl Nes {
+    pub fn new(rom_data: &[u8]) -> Result<Self, String> {
+        Nes::with_power_on_state(rom_data, PowerOnState::Zeros)
+    }
+
+    // Emulation only depends on the ROM, this and the input, so two runs
+    // given the same ones are identical down to the last bit
+    pub fn with_power_on_state(
+        rom_data: &[u8],
+        power_on_state: PowerOnState,
+    ) -> Result<Self, String> {
+        let rom = Rom::new(rom_data)?;
+        let rom_checksum = rom.checksum();
+        let mut nes = Nes {
+            cpu: Cpu::new(),
+            bus: Bus::new(rom)?,
+            rom_data: rom_data.to_vec(),
+            rom_checksum,
+            rewind: None,
+            movie: None,
+            power_on_state,
+            frame_hashes: None,
+            run_ahead: None,
+        };
+        let mut fill = power_on_state.fill();
+        nes.bus.fill_ram(&mut fill);
+        nes.cpu.reset(&mut nes.bus);
+        let mut registers = nes.cpu.registers();
+        registers.a = fill.byte();
+        registers.x = fill.byte();
+        registers.y = fill.byte();
+        nes.cpu.set_registers(registers);
+
+        Ok(nes)
+    }
+
+    // Inserts another cartridge and powers the console on
+    pub fn load_rom(&mut self, rom_data: &[u8]) -> Result<(), String> {
+        let catch_up = self.bus.ppu_catch_up();
+        let run_ahead = self.run_ahead.as_ref();
+        let run_ahead = run_ahead.map(|run_ahead| (run_ahead.frames, run_ahead.second.is_some()));
+        *self = Nes::with_power_on_state(rom_data, self.power_on_state)?;
+        self.bus.set_ppu_catch_up(catch_up);
+        if let Some((frames, second_instance)) = run_ahead {
+            self.enable_run_ahead(frames, second_instance);
+        }
+        Ok(())
+    }
+
+    // Reset button. A movie being recorded or played decides when it acts
+    pub fn reset(&mut self) {
+        if let Some(movie) = &mut self.movie {
+            if movie.command(movie::SOFT_RESET) {
+                return;
+            }
+        }
+        self.cpu.reset(&mut self.bus);
+        self.bus.sync_ppu();
+    }
+
+    // Power button off and on, same as reset for movies
+    pub fn power_cycle(&mut self) {
+        if let Some(movie) = &mut self.movie {
+            if movie.command(movie::POWER) {
+                return;
+            }
+        }
+        self.power_on();
+    }
+
+    // Nothing in the console survives, the tools around it and the cheat
+    // device plugged between console and cartridge do
+    fn power_on(&mut self) {
+        let fresh = Nes::with_power_on_state(&self.rom_data, self.power_on_state)
+            .expect("ROM was already validated");
+        let mut old = std::mem::replace(self, fresh);
+        self.rewind = old.rewind;
+        self.movie = old.movie;
+        self.frame_hashes = old.frame_hashes;
+        self.run_ahead = old.run_ahead;
+        self.bus.set_ppu_catch_up(old.bus.ppu_catch_up());
+        *self.bus.cheats_mut() = std::mem::replace(old.bus.cheats_mut(), Cheats::new());
+        self.bus.set_code_data_log(old.bus.take_code_data_log());
+    }
+
+    pub fn run_frame(&mut self) {
+        if let Some(mut movie) = self.movie.take() {
+            let controllers = [
+                self.bus.joypad1_mut().buttons(),
+                self.bus.joypad2_mut().buttons(),
+            ];
+            if let Some(frame) = movie.next_frame(controllers) {
+                if frame.commands & movie::POWER != 0 {
+                    self.power_on();
+                } else if frame.commands & movie::SOFT_RESET != 0 {
+                    self.cpu.reset(&mut self.bus);
+                }
+                self.bus.joypad1_mut().set_buttons(frame.controllers[0]);
+                self.bus.joypad2_mut().set_buttons(frame.controllers[1]);
+            }
+            self.movie = Some(movie);
+        }
+
+        self.emulate_frame();
+
+        if let Some(mut rewind) = self.rewind.take() {
+            rewind.push(&self.save_state());
+            self.rewind = Some(rewind);
+        }
+        if self.frame_hashes.is_some() {
+            let hash = self.state_hash();
+            if let Some(hashes) = &mut self.frame_hashes {
+                hashes.push(hash);
+            }
+        }
+
+        if let Some(mut run_ahead) = self.run_ahead.take() {
+            self.run_ahead(&mut run_ahead);
+            self.run_ahead = Some(run_ahead);
+        }
+    }
+
+    fn emulate_frame(&mut self) {
+        while !self.bus.poll_frame_complete() {
+            self.cpu.step(&mut self.bus);
+        }
+        self.bus.sync_ppu();
+    }
+
+    // Runs ahead with the input held as it is and keeps the last frame. The
+    // console is left as the real frame left it, movies, rewind and hashes never
+    // see the frames ahead
+    fn run_ahead(&mut self, run_ahead: &mut RunAhead) {
+        let state = self.machine_state();
+        match &mut run_ahead.second {
+            Some(second) => {
+                second
+                    .restore_machine_state(&state)
+                    .expect("State comes from the same ROM");
+                second.bus.set_ppu_catch_up(self.bus.ppu_catch_up());
+                second.bus.cheats_mut().clone_from(self.bus.cheats());
+                for _ in 0..run_ahead.frames {
+                    second.emulate_frame();
+                }
+                run_ahead.frame.clone_from(&second.bus.ppu().frame().data);
+            }
+            None => {
+                let log = self.bus.take_code_data_log();
+                self.bus.apu_mut().set_muted(true);
+                for _ in 0..run_ahead.frames {
+                    self.emulate_frame();
+                }
+                self.bus.apu_mut().set_muted(false);
+                self.bus.set_code_data_log(log);
+                run_ahead.frame.clone_from(&self.bus.ppu().frame().data);
+                self.restore_machine_state(&state)
+                    .expect("State comes from the same ROM");
+            }
+        }
+    }
+
+    // Runs one instruction, or the entry into an interrupt handler, and
+    // returns the CPU cycles it took
+    pub fn step_instruction(&mut self) -> u64 {
+        let cycles_before = self.bus.cycles();
+        self.cpu.step(&mut self.bus);
+        self.bus.sync_ppu();
+        self.bus.cycles() - cycles_before
+    }
+
+    // RGB24, 256x240. Comes from the frames ahead when running ahead
+    pub fn framebuffer(&self) -> &[u8] {
+        match &self.run_ahead {
+            Some(run_ahead) if !run_ahead.frame.is_empty() => &run_ahead.frame,
+            _ => &self.bus.ppu().frame().data,
+        }
+    }
+
+    // Mono samples at `apu::SAMPLE_RATE` produced since the last call
+    pub fn drain_audio(&mut self) -> Vec<f32> {
+        self.bus.apu_mut().drain_samples()
+    }
+
+    pub fn set_controller(&mut self, port: ControllerPort, buttons: JoypadButton) {
+        match port {
+            ControllerPort::One => self.bus.joypad1_mut().set_buttons(buttons),
+            ControllerPort::Two => self.bus.joypad2_mut().set_buttons(buttons),
+        }
+    }
+
+    // Reads CPU address space without side effects
+    pub fn read_memory(&self, addr: u16) -> u8 {
+        self.bus.peek(addr)
+    }
+    // Writes like the CPU would, taking one cycle
+    pub fn write_memory(&mut self, addr: u16, value: u8) {
+        self.bus.mem_write(addr, value);
+        self.bus.sync_ppu();
+    }
+
+    fn machine_state(&self) -> Section {
+        let mut root = Section::new();
+        root.put_snapshot("cpu", &self.cpu);
+        root.put_snapshot("bus", &self.bus);
+        root
+    }
+    fn restore_machine_state(&mut self, root: &Section) -> Result<(), String> {
+        root.get_snapshot("cpu", &mut self.cpu)?;
+        root.get_snapshot("bus", &mut self.bus)
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut root = self.machine_state();
+        if let Some(movie) = &self.movie {
+            root.put_u32("movie_frame", movie.position() as u32);
+        }
+
+        SaveState::new(self.rom_checksum, root).to_bytes()
+    }
+
+    // Refuses states made with another ROM. Nothing changes unless the whole
+    // state loads
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let state = SaveState::from_bytes(data)?;
+        if state.rom_checksum != self.rom_checksum {
+            return Err(format!(
+                "Save state was made with another ROM (CRC-32 {:08X}, loaded {:08X})",
+                state.rom_checksum, self.rom_checksum
+            ));
+        }
+
+        let movie_frame = match &self.movie {
+            Some(movie) => {
+                let frame = state
+                    .root
+                    .get_u32("movie_frame")
+                    .map_err(|_| "Save state was not made during a movie")?;
+                movie.check_seek(frame as usize)?;
+                Some(frame as usize)
+            }
+            None => None,
+        };
+
+        // A component failing halfway puts everything back as it was
+        let old = self.machine_state();
+        if let Err(err) = self.restore_machine_state(&state.root) {
+            self.restore_machine_state(&old)
+                .expect("State was just saved");
+            return Err(err);
+        }
+
+        if let (Some(movie), Some(frame)) = (&mut self.movie, movie_frame) {
+            movie.seek(frame)?;
+        }
+        // Frames ahead of the old state are shown no more
+        if let Some(run_ahead) = &mut self.run_ahead {
+            run_ahead.frame.clear();
+        }
+        Ok(())
+    }
+
+    // Everything a save state holds, which is all of the machine
+    pub fn state_hash(&self) -> u64 {
+        checksum::fnv1a64(&self.save_state())
+    }
+
+    // Hashes the state after every frame from now on
+    pub fn enable_frame_hashes(&mut self, hashes: FrameHashes) {
+        self.frame_hashes = Some(hashes);
+    }
+    pub fn take_frame_hashes(&mut self) -> Option<FrameHashes> {
+        self.frame_hashes.take()
+    }
+    pub fn frame_hashes(&self) -> Option<&FrameHashes> {
+        self.frame_hashes.as_ref()
+    }
+
+    // Lazy PPU, see `Bus`. Off by default, the per-dot PPU is the reference
+    pub fn set_ppu_catch_up(&mut self, enabled: bool) {
+        self.bus.set_ppu_catch_up(enabled);
+    }
+    pub fn ppu_catch_up(&self) -> bool {
+        self.bus.ppu_catch_up()
+    }
+
+    // Shows every frame as it will be `frames` frames later if the input
+    // stays the same, which takes away that many frames of input lag. With
+    // a second console doing the running ahead this one never goes back, so
+    // its audio plays on undisturbed
+    pub fn enable_run_ahead(&mut self, frames: usize, second_instance: bool) {
+        let second = second_instance.then(|| {
+            let mut second = Nes::with_power_on_state(&self.rom_data, self.power_on_state)
+                .expect("ROM was already validated");
+            second.bus.apu_mut().set_muted(true);
+            Box::new(second)
+        });
+        self.run_ahead = Some(RunAhead {
+            frames,
+            second,
+            frame: Vec::new(),
+        });
+    }
+    pub fn disable_run_ahead(&mut self) {
+        self.run_ahead = None;
+    }
+
+    pub fn power_on_state(&self) -> PowerOnState {
+        self.power_on_state
+    }
+
+    // Records a state after every frame, up to whichever limit is hit first
+    pub fn enable_rewind(&mut self, max_frames: usize, memory_budget: usize) {
+        self.rewind = Some(Rewind::new(max_frames, memory_budget));
+    }
+    pub fn disable_rewind(&mut self) {
+        self.rewind = None;
+    }
+
+    // Steps back up to `frames` frames, false if there is nothing to go back to
+    pub fn rewind(&mut self, frames: usize) -> bool {
+        match self
+            .rewind
+            .as_mut()
+            .and_then(|rewind| rewind.rewind(frames))
+        {
+            Some(state) => self.load_state(&state).is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn cheats(&self) -> &Cheats {
+        self.bus.cheats()
+    }
+    pub fn cheats_mut(&mut self) -> &mut Cheats {
+        self.bus.cheats_mut()
+    }
+
+    // Starts the code/data logger, adding to an earlier .cdl file if given
+    pub fn start_code_data_log(&mut self, previous: Option<&Path>) -> Result<(), String> {
+        let rom = Rom::new(&self.rom_data)?;
+        let (prg_size, chr_size) = (rom.prg_rom.len(), rom.chr_rom.len());
+        let log = match previous {
+            Some(path) => CodeDataLog::load(path, prg_size, chr_size)?,
+            None => CodeDataLog::new(prg_size, chr_size),
+        };
+        self.bus.set_code_data_log(Some(log));
+        Ok(())
+    }
+    pub fn stop_code_data_log(&mut self) {
+        self.bus.set_code_data_log(None);
+    }
+    pub fn code_data_log(&self) -> Option<&CodeDataLog> {
+        self.bus.code_data_log()
+    }
+
+    // Powers on and records the controllers from the first frame
+    pub fn record_movie(&mut self, rom_filename: &str) {
+        self.movie = None;
+        self.power_on();
+        self.movie = Some(Movie::new(rom_filename, self.rom_md5()));
+    }
+
+    // Powers on and plays `movie` back, from then on it owns the controllers
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), String> {
+        if movie.rom_md5 != self.rom_md5() {
+            return Err(format!(
+                "Movie was recorded with another ROM ({})",
+                movie.rom_filename
+            ));
+        }
+        self.movie = None;
+        self.power_on();
+        self.movie = Some(movie);
+        Ok(())
+    }
+
+    pub fn stop_movie(&mut self) -> Option<Movie> {
+        self.movie.take()
+    }
+    pub fn movie(&self) -> Option<&Movie> {
+        self.movie.as_ref()
+    }
+    pub fn movie_mut(&mut self) -> Option<&mut Movie> {
+        self.movie.as_mut()
+    }
+
+    // What FCEUX identifies ROMs by, PRG and CHR ROM without the header
+    pub fn rom_md5(&self) -> [u8; 16] {
+        let rom = Rom::new(&self.rom_data).expect("ROM was already validated");
+        checksum::md5(&[rom.prg_rom, rom.chr_rom].concat())
+    }
+
+    pub fn rom_checksum(&self) -> u32 {
+        self.rom_checksum
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+    pub fn bus(&self) -> &Bus {
+        &self.bus
+    }
+    // Both halves at once, for tools driving the CPU directly
+    pub fn parts_mut(&mut self) -> (&mut Cpu, &mut Bus) {
+        (&mut self.cpu, &mut self.bus)
+    }
+}
@@ -0,0 +1,134 @@
+// What RAM and the CPU registers hold when the console is switched on. Real
+// hardware comes up somewhere between a pattern and noise, the few games that
+// care can be checked against all of them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PowerOnState {
+    Zeros,
+    Ones,
+    // Four $00 then four $FF, what FCEUX fills RAM with
+    Pattern,
+    // Same seed, same bytes, on every machine and every run
+    Random(u64),
+}
+
+impl PowerOnState {
+    // zeros, ones, pattern or random:SEED
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text.split_once(':') {
+            None if text == "zeros" => Ok(PowerOnState::Zeros),
+            None if text == "ones" => Ok(PowerOnState::Ones),
+            None if text == "pattern" => Ok(PowerOnState::Pattern),
+            Some(("random", seed)) => seed
+                .parse()
+                .map(PowerOnState::Random)
+                .map_err(|_| format!("\"{}\" is not a seed", seed)),
+            _ => Err(format!(
+                "\"{}\" is not zeros, ones, pattern or random:SEED",
+                text
+            )),
+        }
+    }
+
+    pub fn fill(self) -> RamFill {
+        let seed = match self {
+            PowerOnState::Random(seed) => seed,
+            _ => 0,
+        };
+        RamFill {
+            state: self,
+            rng: seed,
+        }
+    }
+}
+
+// Hands out power-on contents. Memories must be filled in the same order
+// every time for random ones to come out the same
+pub struct RamFill {
+    state: PowerOnState,
+    rng: u64,
+}
+
+impl RamFill {
+    pub fn fill(&mut self, data: &mut [u8]) {
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = match self.state {
+                PowerOnState::Zeros => 0x00,
+                PowerOnState::Ones => 0xFF,
+                PowerOnState::Pattern if i & 4 == 0 => 0x00,
+                PowerOnState::Pattern => 0xFF,
+                PowerOnState::Random(_) => self.next_random(),
+            };
+        }
+    }
+
+    pub fn byte(&mut self) -> u8 {
+        let mut byte = [0];
+        self.fill(&mut byte);
+        byte[0]
+    }
+
+    // SplitMix64, small and fixed forever unlike the generators of `rand`
+    // https://prng.di.unimi.it/splitmix64.c
+    fn next_random(&mut self) -> u8 {
+        self.rng = self.rng.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        (z ^ (z >> 31)) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(PowerOnState::parse("zeros"), Ok(PowerOnState::Zeros));
+        assert_eq!(PowerOnState::parse("ones"), Ok(PowerOnState::Ones));
+        assert_eq!(PowerOnState::parse("pattern"), Ok(PowerOnState::Pattern));
+        assert_eq!(
+            PowerOnState::parse("random:1234567"),
+            Ok(PowerOnState::Random(1234567))
+        );
+        assert_eq!(
+            PowerOnState::parse("random:-1"),
+            Err("\"-1\" is not a seed".to_string())
+        );
+        assert_eq!(
+            PowerOnState::parse("zeros:1"),
+            Err("\"zeros:1\" is not zeros, ones, pattern or random:SEED".to_string())
+        );
+    }
+
+    #[test]
+    fn fills() {
+        let filled = |state: PowerOnState| {
+            let mut data = [0x55; 10];
+            state.fill().fill(&mut data);
+            data
+        };
+        assert_eq!(filled(PowerOnState::Zeros), [0x00; 10]);
+        assert_eq!(filled(PowerOnState::Ones), [0xFF; 10]);
+        assert_eq!(
+            filled(PowerOnState::Pattern),
+            [0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00]
+        );
+    }
+
+    // Low bytes of the SplitMix64 reference output for seed 1234567, which
+    // go on from one memory to the next
+    #[test]
+    fn random_fill_is_fixed() {
+        let mut fill = PowerOnState::parse("random:1234567").unwrap().fill();
+        let mut ram = [0; 3];
+        fill.fill(&mut ram);
+        assert_eq!(ram, [0x85, 0xA5, 0x77]);
+        assert_eq!(fill.byte(), 0x3F);
+        assert_eq!(fill.byte(), 0xCD);
+
+        let mut other = [0; 5];
+        PowerOnState::Random(1234568).fill().fill(&mut other);
+        assert_ne!(other[..3], ram);
+    }
+}
@@ -1,10 +1,25 @@
 mod reg;
 
-use crate::rom::Mirroring;
-use reg::{AddressRegister, ControlRegister, MaskRegister, ScrollRegister};
+use crate::{
+    cdl,
+    mapper::Mapper,
+    poweron::RamFill,
+    render::{frame::Frame, palette::SYSTEM_PALETTE},
+    rom::Mirroring,
+    savestate::{Section, Snapshot},
+};
+use reg::{AddressRegister, ControlRegister, MaskRegister};
 
 use bitflags::bitflags;
 
+// Dots per scanline and scanlines per frame (NTSC)
+pub const DOTS_PER_SCANLINE: usize = 341;
+pub const SCANLINES_PER_FRAME: u16 = 262;
+const VISIBLE_SCANLINES: u16 = 240;
+const VBLANK_SCANLINE: u16 = 241;
+const PRE_RENDER_SCANLINE: u16 = 261;
+const SPRITES_PER_LINE: usize = 8;
+
 /* VSO. ....
    |||| ||||
    |||+-++++- PPU open bus
@@ -20,46 +35,132 @@ bitflags! {
   }
 }
 
+// Sprite fetched for the scanline being drawn
+#[derive(Clone, Copy)]
+struct Sprite {
+    x: u8,
+    attributes: u8,
+    pattern_lo: u8,
+    pattern_hi: u8,
+}
+
+impl Sprite {
+    fn empty() -> Self {
+        Sprite {
+            x: 0xFF,
+            attributes: 0,
+            pattern_lo: 0,
+            pattern_hi: 0,
+        }
+    }
+}
+
 pub struct Ppu {
+    // Also known as w, shared by $2005 and $2006
     address_latch: bool,
+    // Current VRAM address (v)
     reg_address: AddressRegister,
+    // Temporary VRAM address (t), top left onscreen tile
+    reg_temp: AddressRegister,
+    fine_x: u8,
     reg_control: ControlRegister,
     reg_mask: MaskRegister,
-    reg_scroll: ScrollRegister,
     status: PpuFlags,
-    chr_rom: Vec<u8>,
-    vram: [u8; 2048],
+    // 2Kb of console VRAM plus 2Kb for four-screen cartridges
+    vram: [u8; 4096],
+    palette_table: [u8; 32],
     oam_address: u8,
     oam_data: [u8; 256],
     data_buffer: u8,
-    mirroring: Mirroring,
+    dot: usize,
+    scanline: u16,
+    odd_frame: bool,
+    nmi_output: bool,
+
+    // Background pipeline
+    next_tile_id: u8,
+    next_tile_attribute: u8,
+    next_tile_lo: u8,
+    next_tile_hi: u8,
+    pattern_shifter_lo: u16,
+    pattern_shifter_hi: u16,
+    attribute_shifter_lo: u16,
+    attribute_shifter_hi: u16,
+
+    // Sprites found by evaluation for the next scanline (OAM indexes)
+    secondary_oam: [u8; SPRITES_PER_LINE],
+    secondary_oam_count: usize,
+    next_sprite_zero: bool,
+    sprites: [Sprite; SPRITES_PER_LINE],
+    sprite_count: usize,
+    sprite_zero_on_line: bool,
+
+    frame: Frame,
+
+    // Pattern table accesses for the code/data logger, None when it's off
+    chr_fetches: Option<Vec<(u16, u8)>>,
 }
 
 impl Ppu {
-    pub fn new(chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {
+    pub fn new() -> Self {
         Ppu {
-            chr_rom,
             address_latch: false,
             reg_address: AddressRegister::new(),
+            reg_temp: AddressRegister::new(),
+            fine_x: 0,
             reg_control: ControlRegister::new(),
             reg_mask: MaskRegister::new(),
-            reg_scroll: ScrollRegister::new(),
             status: PpuFlags::from_bits_truncate(0b1010_0000),
-            vram: [0; 2048],
+            vram: [0; 4096],
+            palette_table: [0; 32],
             oam_address: 0,
             oam_data: [0; 64 * 4],
             data_buffer: 0,
-            mirroring,
+            dot: 0,
+            scanline: 0,
+            odd_frame: false,
+            nmi_output: false,
+            next_tile_id: 0,
+            next_tile_attribute: 0,
+            next_tile_lo: 0,
+            next_tile_hi: 0,
+            pattern_shifter_lo: 0,
+            pattern_shifter_hi: 0,
+            attribute_shifter_lo: 0,
+            attribute_shifter_hi: 0,
+            secondary_oam: [0; SPRITES_PER_LINE],
+            secondary_oam_count: 0,
+            next_sprite_zero: false,
+            sprites: [Sprite::empty(); SPRITES_PER_LINE],
+            sprite_count: 0,
+            sprite_zero_on_line: false,
+            frame: Frame::new(),
+            chr_fetches: None,
         }
     }
 
+    // NMI line is "vblank and NMI enabled", the CPU acts on its rising edge
+    fn update_nmi(&mut self) {
+        self.nmi_output = self.status.contains(PpuFlags::VBLANK_STARTED)
+            && self.reg_control.generate_vblank_nmi();
+    }
+
+    fn is_rendering_line(&self) -> bool {
+        self.scanline < VISIBLE_SCANLINES || self.scanline == PRE_RENDER_SCANLINE
+    }
+
     pub fn write_to_address(&mut self, value: u8) {
-        self.reg_address.update(value, self.address_latch);
-        self.address_latch = true;
+        self.reg_temp.update(value, self.address_latch);
+        if self.address_latch {
+            self.reg_address = self.reg_temp;
+        }
+        self.address_latch = !self.address_latch;
     }
 
     pub fn write_to_control(&mut self, value: u8) {
         self.reg_control.update(value);
+        self.reg_temp.set_nametable(value);
+        self.update_nmi();
     }
 
     pub fn write_to_mask(&mut self, value: u8) {
@@ -67,103 +168,584 @@ impl Ppu {
     }
 
     pub fn write_to_scroll(&mut self, value: u8) {
-        self.reg_scroll.update(value, self.address_latch);
-        self.address_latch = true;
+        if !self.address_latch {
+            self.reg_temp.set_coarse_x(value >> 3);
+            self.fine_x = value & 0b111;
+        } else {
+            self.reg_temp.set_coarse_y(value >> 3);
+            self.reg_temp.set_fine_y(value & 0b111);
+        }
+        self.address_latch = !self.address_latch;
     }
 
     pub fn write_to_oam_addr(&mut self, value: u8) {
         self.oam_address = value;
     }
 
+    // Palette RAM only has six bits
+    pub fn fill_ram(&mut self, fill: &mut RamFill) {
+        fill.fill(&mut self.vram);
+        fill.fill(&mut self.palette_table);
+        for color in &mut self.palette_table {
+            *color &= 0x3F;
+        }
+        fill.fill(&mut self.oam_data);
+    }
+
     pub fn write_to_oam_data(&mut self, value: u8) {
         self.oam_data[self.oam_address as usize] = value;
         self.oam_address = self.oam_address.wrapping_add(1);
     }
-    pub fn read_from_oam_data(&mut self, addr: u16) -> u8 {
-        self.oam_data[addr as usize]
-    }
-
-    pub fn write_oam_dma(&mut self, page: &[u8]) {
-        // Page size is 256 bytes
-        for value in page.iter() {
-            self.oam_data[self.oam_address as usize] = *value;
-            self.oam_address = self.oam_address.wrapping_add(1);
-        }
+    pub fn read_from_oam_data(&self) -> u8 {
+        self.oam_data[self.oam_address as usize]
     }
 
     pub fn get_status(&mut self) -> u8 {
+        let status = self.status.bits;
+        self.status.remove(PpuFlags::VBLANK_STARTED);
         self.address_latch = false;
+        self.update_nmi();
+        status
+    }
+    // Status without reading side effects
+    pub fn peek_status(&self) -> u8 {
         self.status.bits
     }
 
     fn increment_vram_addr(&mut self) {
-        self.reg_address
-            .increment(self.reg_control.vram_addr_increment());
+        // During rendering both scroll increments happen at once
+        if self.reg_mask.is_rendering() && self.is_rendering_line() {
+            self.reg_address.increment_coarse_x();
+            self.reg_address.increment_y();
+        } else {
+            self.reg_address
+                .increment(self.reg_control.vram_addr_increment());
+        }
     }
-    fn mirror_vram_addr(&self, addr: u16) -> u16 {
-        let mirrored_vram = addr & 0b1011_1111_1111_1111;
-        let vram_index = mirrored_vram - 0x2000;
-        let name_table = vram_index / 0x0400;
-        match (&self.mirroring, name_table) {
-            (Mirroring::Vertical, 2) | (Mirroring::Vertical, 3) => vram_index - 0x0800,
-            (Mirroring::Horizontal, 1) => vram_index - 0x0400,
-            (Mirroring::Horizontal, 2) => vram_index - 0x0400,
-            (Mirroring::Horizontal, 3) => vram_index - 0x0800,
-            _ => vram_index,
+    fn mirror_vram_addr(addr: u16, mirroring: Mirroring) -> usize {
+        let index = (addr & 0x0FFF) as usize;
+        let name_table = index / 0x0400;
+        let physical_table = match mirroring {
+            Mirroring::Vertical => name_table % 2,
+            Mirroring::Horizontal => name_table / 2,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+            Mirroring::FourScreen => name_table,
+        };
+        physical_table * 0x0400 + index % 0x0400
+    }
+    fn mirror_palette_addr(addr: u16) -> usize {
+        let index = (addr - 0x3F00) % 0x0020;
+        // Backdrop entries of sprite palettes are mirrors of background ones
+        match index {
+            0x10 | 0x14 | 0x18 | 0x1C => (index - 0x10) as usize,
+            _ => index as usize,
         }
     }
 
-    pub fn read(&mut self) -> u8 {
+    fn mem_read(&mut self, mapper: &mut dyn Mapper, addr: u16) -> u8 {
+        let addr = addr & 0x3FFF;
+        match addr {
+            // CHR ROM
+            0x0000..=0x1FFF => mapper.ppu_read(addr),
+            // VRAM and mirrors
+            0x2000..=0x3EFF => self.vram[Self::mirror_vram_addr(addr, mapper.mirroring())],
+            // Palette table and mirrors
+            _ => self.palette_table[Self::mirror_palette_addr(addr)],
+        }
+    }
+
+    // Pattern fetch that ends up on screen
+    fn fetch_pattern(&mut self, mapper: &mut dyn Mapper, addr: u16) -> u8 {
+        if let Some(fetches) = &mut self.chr_fetches {
+            fetches.push((addr, cdl::RENDERED));
+        }
+        self.mem_read(mapper, addr)
+    }
+
+    pub fn read(&mut self, mapper: &mut dyn Mapper) -> u8 {
         let addr = self.reg_address.get_addr();
         self.increment_vram_addr();
 
+        if let (Some(fetches), 0x0000..=0x1FFF) = (&mut self.chr_fetches, addr) {
+            fetches.push((addr, cdl::CHR_READ));
+        }
+
         match addr {
-            // CHR ROM
-            0x0000..=0x1FFF => {
-                let result = self.data_buffer;
-                self.data_buffer = self.chr_rom[addr as usize];
-                result
-            }
-            // Registers
-            0x2000..=0x2007 => todo!("Log attempt to read from register"),
-            // VRAM
-            0x2008..=0x2FFF => {
-                let value = self.data_buffer;
-                self.data_buffer = self.vram[self.mirror_vram_addr(addr) as usize];
-                value
+            // Palette table and mirrors
+            0x3F00..=0x3FFF => {
+                // Not buffered, but the buffer gets the nametable byte "under" the palette
+                self.data_buffer = self.mem_read(mapper, addr - 0x1000);
+                self.palette_table[Self::mirror_palette_addr(addr)]
             }
-            // Mirrors of VRAM
-            0x3000..=0x3EFF => {
+            _ => {
                 let value = self.data_buffer;
-                self.data_buffer = self.vram[self.mirror_vram_addr(addr - 0x1000) as usize];
+                self.data_buffer = self.mem_read(mapper, addr);
                 value
             }
-            // Palette table and mirrors
-            0x3F00..=0x3FFF => {
-                // XXX Danger place. Maybe data buffer update value is in another castle
-                // Not mirrored to palette
-                self.data_buffer = self.vram[self.mirror_vram_addr(addr - 0x1000) as usize];
-                self.vram[((addr - 0x3F00) % 0x0020 + 0x3F00) as usize]
-            }
-            _ => panic!("No such address in PPU: {:x}", addr),
         }
     }
-    pub fn write(&mut self, value: u8) {
+    pub fn write(&mut self, mapper: &mut dyn Mapper, value: u8) {
         let addr = self.reg_address.get_addr();
 
         match addr {
-            // CHR ROM
-            0x0000..=0x1FFF => todo!("Log attempt to write to CHR ROM space ({:x})", addr),
-            // Registers
-            0x2000..=0x2007 => todo!("Log attempt to write to register"),
-            // VRAM
-            0x2008..=0x2FFF => self.vram[self.mirror_vram_addr(addr) as usize] = value,
-            // Mirrors of VRAM
-            0x3000..=0x3EFF => self.vram[self.mirror_vram_addr(addr - 0x1000) as usize] = value,
+            // CHR RAM
+            0x0000..=0x1FFF => mapper.ppu_write(addr, value),
+            // VRAM and mirrors
+            0x2000..=0x3EFF => self.vram[Self::mirror_vram_addr(addr, mapper.mirroring())] = value,
             // Palette table and mirrors
-            0x3F00..=0x3FFF => self.vram[((addr - 0x3F00) % 0x0020 + 0x3F00) as usize] = value,
-            _ => panic!("No such address in PPU: {:x}", addr),
+            _ => self.palette_table[Self::mirror_palette_addr(addr)] = value,
         }
         self.increment_vram_addr();
     }
 }
+
+// Rendering
+// https://www.nesdev.org/wiki/PPU_rendering
+impl Ppu {
+    fn load_background_shifters(&mut self) {
+        self.pattern_shifter_lo = (self.pattern_shifter_lo & 0xFF00) | self.next_tile_lo as u16;
+        self.pattern_shifter_hi = (self.pattern_shifter_hi & 0xFF00) | self.next_tile_hi as u16;
+
+        // Attribute is the same for the whole tile
+        let attribute_lo = if self.next_tile_attribute & 0b01 != 0 {
+            0xFF
+        } else {
+            0x00
+        };
+        let attribute_hi = if self.next_tile_attribute & 0b10 != 0 {
+            0xFF
+        } else {
+            0x00
+        };
+        self.attribute_shifter_lo = (self.attribute_shifter_lo & 0xFF00) | attribute_lo;
+        self.attribute_shifter_hi = (self.attribute_shifter_hi & 0xFF00) | attribute_hi;
+    }
+
+    fn shift_background_shifters(&mut self) {
+        self.pattern_shifter_lo <<= 1;
+        self.pattern_shifter_hi <<= 1;
+        self.attribute_shifter_lo <<= 1;
+        self.attribute_shifter_hi <<= 1;
+    }
+
+    fn fetch_background(&mut self, mapper: &mut dyn Mapper) {
+        match (self.dot - 1) % 8 {
+            0 => {
+                self.load_background_shifters();
+                let addr = 0x2000 | (self.reg_address.get() & 0x0FFF);
+                self.next_tile_id = self.mem_read(mapper, addr);
+            }
+            2 => {
+                let v = self.reg_address.get();
+                let addr = 0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+                let mut attribute = self.mem_read(mapper, addr);
+                // Each attribute byte covers 4x4 tiles, 2 bits per 2x2 quadrant
+                if self.reg_address.coarse_y() & 0b10 != 0 {
+                    attribute >>= 4;
+                }
+                if self.reg_address.coarse_x() & 0b10 != 0 {
+                    attribute >>= 2;
+                }
+                self.next_tile_attribute = attribute & 0b11;
+            }
+            4 => {
+                let addr = self.background_pattern_addr();
+                self.next_tile_lo = self.fetch_pattern(mapper, addr);
+            }
+            6 => {
+                let addr = self.background_pattern_addr() + 8;
+                self.next_tile_hi = self.fetch_pattern(mapper, addr);
+            }
+            7 => self.reg_address.increment_coarse_x(),
+            _ => (),
+        }
+    }
+
+    fn background_pattern_addr(&self) -> u16 {
+        self.reg_control.background_pattern_addr()
+            + (self.next_tile_id as u16) * 16
+            + self.reg_address.fine_y()
+    }
+
+    // Finds sprites of the next scanline
+    fn evaluate_sprites(&mut self) {
+        let height = self.reg_control.sprite_size() as u16;
+        self.secondary_oam_count = 0;
+        self.next_sprite_zero = false;
+
+        for i in 0..64 {
+            let y = self.oam_data[i * 4] as u16;
+            if self.scanline < y || self.scanline - y >= height {
+                continue;
+            }
+
+            if self.secondary_oam_count == SPRITES_PER_LINE {
+                self.status.insert(PpuFlags::OVERFLOW);
+                break;
+            }
+            if i == 0 {
+                self.next_sprite_zero = true;
+            }
+            self.secondary_oam[self.secondary_oam_count] = i as u8;
+            self.secondary_oam_count += 1;
+        }
+    }
+
+    fn sprite_pattern_addr(&self, tile: u8, row: u16) -> u16 {
+        if self.reg_control.sprite_size() == 8 {
+            self.reg_control.sprite_pattern_addr() + tile as u16 * 16 + row
+        } else {
+            // 8x16 sprites take the table from bit 0 of the tile number
+            let table = (tile as u16 & 1) * 0x1000;
+            let tile = (tile & 0xFE) as u16 + if row >= 8 { 1 } else { 0 };
+            table + tile * 16 + (row & 0b111)
+        }
+    }
+
+    // Slot fetches happen during dots 257-320, the mapper may watch them
+    fn fetch_sprite(&mut self, mapper: &mut dyn Mapper, slot: usize) {
+        if slot >= self.secondary_oam_count {
+            // Unused slots fetch tile $FF
+            let addr = self.sprite_pattern_addr(0xFF, 0);
+            self.mem_read(mapper, addr);
+            self.mem_read(mapper, addr + 8);
+            self.sprites[slot] = Sprite::empty();
+            return;
+        }
+
+        let oam_index = self.secondary_oam[slot] as usize * 4;
+        let y = self.oam_data[oam_index] as u16;
+        let tile = self.oam_data[oam_index + 1];
+        let attributes = self.oam_data[oam_index + 2];
+        let x = self.oam_data[oam_index + 3];
+
+        let height = self.reg_control.sprite_size() as u16;
+        let mut row = self.scanline.wrapping_sub(y) % height;
+        if attributes & 0b1000_0000 != 0 {
+            row = height - 1 - row;
+        }
+
+        let addr = self.sprite_pattern_addr(tile, row);
+        let mut pattern_lo = self.fetch_pattern(mapper, addr);
+        let mut pattern_hi = self.fetch_pattern(mapper, addr + 8);
+        if attributes & 0b0100_0000 != 0 {
+            pattern_lo = pattern_lo.reverse_bits();
+            pattern_hi = pattern_hi.reverse_bits();
+        }
+
+        self.sprites[slot] = Sprite {
+            x,
+            attributes,
+            pattern_lo,
+            pattern_hi,
+        };
+    }
+
+    fn background_pixel(&self, x: usize) -> (u8, u8) {
+        if !self.reg_mask.show_background()
+            || (x < 8 && !self.reg_mask.contains(MaskRegister::SHOW_BACKGROUND_LEFT))
+        {
+            return (0, 0);
+        }
+
+        let bit = 15 - self.fine_x as u16;
+        let pixel =
+            ((self.pattern_shifter_hi >> bit) & 1) << 1 | ((self.pattern_shifter_lo >> bit) & 1);
+        let palette = ((self.attribute_shifter_hi >> bit) & 1) << 1
+            | ((self.attribute_shifter_lo >> bit) & 1);
+        (pixel as u8, palette as u8)
+    }
+
+    // Returns pixel, palette, behind background flag and sprite 0 flag
+    fn sprite_pixel(&self, x: usize) -> (u8, u8, bool, bool) {
+        if !self.reg_mask.show_sprites()
+            || (x < 8 && !self.reg_mask.contains(MaskRegister::SHOW_SPRITES_LEFT))
+        {
+            return (0, 0, false, false);
+        }
+
+        for (slot, sprite) in self.sprites[..self.sprite_count].iter().enumerate() {
+            let offset = x.wrapping_sub(sprite.x as usize);
+            if offset >= 8 {
+                continue;
+            }
+
+            let bit = 7 - offset;
+            let pixel = ((sprite.pattern_hi >> bit) & 1) << 1 | ((sprite.pattern_lo >> bit) & 1);
+            if pixel == 0 {
+                continue;
+            }
+
+            let palette = (sprite.attributes & 0b11) + 4;
+            let behind_background = sprite.attributes & 0b0010_0000 != 0;
+            let sprite_zero = slot == 0 && self.sprite_zero_on_line;
+            return (pixel, palette, behind_background, sprite_zero);
+        }
+
+        (0, 0, false, false)
+    }
+
+    fn draw_pixel(&mut self) {
+        let x = self.dot - 1;
+        let y = self.scanline as usize;
+
+        let (bg_pixel, bg_palette) = self.background_pixel(x);
+        let (sprite_pixel, sprite_palette, behind_background, sprite_zero) = self.sprite_pixel(x);
+
+        let (pixel, palette) = match (bg_pixel, sprite_pixel) {
+            (0, 0) => (0, 0),
+            (0, _) => (sprite_pixel, sprite_palette),
+            (_, 0) => (bg_pixel, bg_palette),
+            _ => {
+                if sprite_zero && x != 255 {
+                    self.status.insert(PpuFlags::SPRITE_ZERO_HIT);
+                }
+                if behind_background {
+                    (bg_pixel, bg_palette)
+                } else {
+                    (sprite_pixel, sprite_palette)
+                }
+            }
+        };
+
+        let mut color =
+            self.palette_table[Self::mirror_palette_addr(0x3F00 + (palette * 4 + pixel) as u16)];
+        if self.reg_mask.contains(MaskRegister::GREYSCALE) {
+            color &= 0x30;
+        }
+        self.frame
+            .set_pixel(x, y, SYSTEM_PALETTE[(color & 0x3F) as usize]);
+    }
+
+    fn render_dot(&mut self, mapper: &mut dyn Mapper) {
+        let rendering = self.reg_mask.is_rendering();
+        let pre_render = self.scanline == PRE_RENDER_SCANLINE;
+
+        if rendering {
+            if (2..=257).contains(&self.dot) || (321..=337).contains(&self.dot) {
+                self.shift_background_shifters();
+                self.fetch_background(mapper);
+            }
+            match self.dot {
+                256 => self.reg_address.increment_y(),
+                257 => {
+                    self.reg_address.copy_horizontal(&self.reg_temp);
+                    if !pre_render {
+                        self.evaluate_sprites();
+                    } else {
+                        self.secondary_oam_count = 0;
+                        self.next_sprite_zero = false;
+                    }
+                }
+                280..=304 if pre_render => self.reg_address.copy_vertical(&self.reg_temp),
+                // Unused nametable fetches
+                338 | 340 => {
+                    let addr = 0x2000 | (self.reg_address.get() & 0x0FFF);
+                    self.next_tile_id = self.mem_read(mapper, addr);
+                }
+                _ => (),
+            }
+
+            if (257..=320).contains(&self.dot) && (self.dot - 257) % 8 == 4 {
+                self.fetch_sprite(mapper, (self.dot - 257) / 8);
+            }
+            if self.dot == 320 {
+                self.sprite_count = self.secondary_oam_count;
+                self.sprite_zero_on_line = self.next_sprite_zero;
+            }
+        }
+
+        if !pre_render && (1..=256).contains(&self.dot) {
+            self.draw_pixel();
+        }
+    }
+
+    // Advances by one dot, returns true when a frame was completed
+    pub fn tick(&mut self, mapper: &mut dyn Mapper) -> bool {
+        if self.is_rendering_line() {
+            self.render_dot(mapper);
+        }
+
+        if self.dot == 1 {
+            if self.scanline == VBLANK_SCANLINE {
+                self.status.insert(PpuFlags::VBLANK_STARTED);
+                self.update_nmi();
+            } else if self.scanline == PRE_RENDER_SCANLINE {
+                self.status.remove(PpuFlags::VBLANK_STARTED);
+                self.status.remove(PpuFlags::SPRITE_ZERO_HIT);
+                self.status.remove(PpuFlags::OVERFLOW);
+                self.update_nmi();
+            }
+        }
+
+        // Odd frames are one dot shorter when rendering is enabled
+        if self.scanline == PRE_RENDER_SCANLINE
+            && self.dot == 339
+            && self.odd_frame
+            && self.reg_mask.is_rendering()
+        {
+            self.dot = 340;
+        }
+
+        self.dot += 1;
+        if self.dot < DOTS_PER_SCANLINE {
+            return false;
+        }
+        self.dot = 0;
+        self.scanline += 1;
+
+        if self.scanline >= SCANLINES_PER_FRAME {
+            self.scanline = 0;
+            self.odd_frame = !self.odd_frame;
+            return true;
+        }
+
+        false
+    }
+
+    pub fn nmi_line(&self) -> bool {
+        self.nmi_output
+    }
+
+    // Dots that can run before the PPU next changes something the CPU sees
+    // without asking: the NMI line rising or falling, or the frame ending
+    pub fn dots_until_event(&self) -> u32 {
+        [
+            (VBLANK_SCANLINE, 1),
+            (PRE_RENDER_SCANLINE, 1),
+            (PRE_RENDER_SCANLINE, DOTS_PER_SCANLINE - 1),
+        ]
+        .into_iter()
+        .map(|(scanline, dot)| self.dots_until(scanline, dot))
+        .min()
+        .unwrap_or(0)
+    }
+
+    // Dots that run before the one at `scanline` and `dot` does. Odd frames
+    // skipping a dot can only make it fewer
+    pub fn dots_until(&self, scanline: u16, dot: usize) -> u32 {
+        let frame = SCANLINES_PER_FRAME as u32 * DOTS_PER_SCANLINE as u32;
+        let position =
+            |scanline: u16, dot: usize| scanline as u32 * DOTS_PER_SCANLINE as u32 + dot as u32;
+        (position(scanline, dot) + frame - position(self.scanline, self.dot)) % frame
+    }
+
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+    pub fn dot(&self) -> usize {
+        self.dot
+    }
+
+    pub fn set_chr_fetch_log(&mut self, enabled: bool) {
+        self.chr_fetches = if enabled { Some(Vec::new()) } else { None };
+    }
+    pub fn chr_fetches_mut(&mut self) -> Option<&mut Vec<(u16, u8)>> {
+        self.chr_fetches.as_mut()
+    }
+
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+}
+
+impl Snapshot for Ppu {
+    fn save(&self, section: &mut Section) {
+        section.put_bool("address_latch", self.address_latch);
+        section.put_u16("v", self.reg_address.get());
+        section.put_u16("t", self.reg_temp.get());
+        section.put_u8("fine_x", self.fine_x);
+        section.put_u8("control", self.reg_control.bits());
+        section.put_u8("mask", self.reg_mask.bits());
+        section.put_u8("status", self.status.bits);
+        section.put_bytes("vram", &self.vram);
+        section.put_bytes("palette", &self.palette_table);
+        section.put_u8("oam_address", self.oam_address);
+        section.put_bytes("oam", &self.oam_data);
+        section.put_u8("data_buffer", self.data_buffer);
+        section.put_u16("dot", self.dot as u16);
+        section.put_u16("scanline", self.scanline);
+        section.put_bool("odd_frame", self.odd_frame);
+        section.put_bool("nmi_output", self.nmi_output);
+
+        section.put_u8("next_tile_id", self.next_tile_id);
+        section.put_u8("next_tile_attribute", self.next_tile_attribute);
+        section.put_u8("next_tile_lo", self.next_tile_lo);
+        section.put_u8("next_tile_hi", self.next_tile_hi);
+        section.put_u16("pattern_shifter_lo", self.pattern_shifter_lo);
+        section.put_u16("pattern_shifter_hi", self.pattern_shifter_hi);
+        section.put_u16("attribute_shifter_lo", self.attribute_shifter_lo);
+        section.put_u16("attribute_shifter_hi", self.attribute_shifter_hi);
+
+        section.put_bytes("secondary_oam", &self.secondary_oam);
+        section.put_u8("secondary_oam_count", self.secondary_oam_count as u8);
+        section.put_bool("next_sprite_zero", self.next_sprite_zero);
+        // X, attributes, pattern low and high per sprite
+        let sprites: Vec<u8> = self
+            .sprites
+            .iter()
+            .flat_map(|sprite| {
+                [
+                    sprite.x,
+                    sprite.attributes,
+                    sprite.pattern_lo,
+                    sprite.pattern_hi,
+                ]
+            })
+            .collect();
+        section.put_bytes("sprites", &sprites);
+        section.put_u8("sprite_count", self.sprite_count as u8);
+        section.put_bool("sprite_zero_on_line", self.sprite_zero_on_line);
+
+        section.put_bytes("frame", &self.frame.data);
+    }
+
+    fn load(&mut self, section: &Section) -> Result<(), String> {
+        self.address_latch = section.get_bool("address_latch")?;
+        self.reg_address.set(section.get_u16("v")?);
+        self.reg_temp.set(section.get_u16("t")?);
+        self.fine_x = section.get_u8("fine_x")?;
+        self.reg_control = ControlRegister::from_bits_truncate(section.get_u8("control")?);
+        self.reg_mask = MaskRegister::from_bits_truncate(section.get_u8("mask")?);
+        self.status = PpuFlags::from_bits_truncate(section.get_u8("status")?);
+        section.get_bytes_into("vram", &mut self.vram)?;
+        section.get_bytes_into("palette", &mut self.palette_table)?;
+        self.oam_address = section.get_u8("oam_address")?;
+        section.get_bytes_into("oam", &mut self.oam_data)?;
+        self.data_buffer = section.get_u8("data_buffer")?;
+        self.dot = section.get_u16("dot")? as usize;
+        self.scanline = section.get_u16("scanline")?;
+        self.odd_frame = section.get_bool("odd_frame")?;
+        self.nmi_output = section.get_bool("nmi_output")?;
+
+        self.next_tile_id = section.get_u8("next_tile_id")?;
+        self.next_tile_attribute = section.get_u8("next_tile_attribute")?;
+        self.next_tile_lo = section.get_u8("next_tile_lo")?;
+        self.next_tile_hi = section.get_u8("next_tile_hi")?;
+        self.pattern_shifter_lo = section.get_u16("pattern_shifter_lo")?;
+        self.pattern_shifter_hi = section.get_u16("pattern_shifter_hi")?;
+        self.attribute_shifter_lo = section.get_u16("attribute_shifter_lo")?;
+        self.attribute_shifter_hi = section.get_u16("attribute_shifter_hi")?;
+
+        section.get_bytes_into("secondary_oam", &mut self.secondary_oam)?;
+        self.secondary_oam_count =
+            (section.get_u8("secondary_oam_count")? as usize).min(SPRITES_PER_LINE);
+        self.next_sprite_zero = section.get_bool("next_sprite_zero")?;
+        let mut sprites = [0; SPRITES_PER_LINE * 4];
+        section.get_bytes_into("sprites", &mut sprites)?;
+        for (sprite, bytes) in self.sprites.iter_mut().zip(sprites.chunks_exact(4)) {
+            *sprite = Sprite {
+                x: bytes[0],
+                attributes: bytes[1],
+                pattern_lo: bytes[2],
+                pattern_hi: bytes[3],
+            };
+        }
+        self.sprite_count = (section.get_u8("sprite_count")? as usize).min(SPRITES_PER_LINE);
+        self.sprite_zero_on_line = section.get_bool("sprite_zero_on_line")?;
+
+        section.get_bytes_into("frame", &mut self.frame.data)?;
+        Ok(())
+    }
+}
@@ -1,32 +1,115 @@
+/* Used both as current (v) and temporary (t) VRAM address
+   https://www.nesdev.org/wiki/PPU_scrolling#PPU_internal_registers
+
+   yyy NN YYYYY XXXXX
+   ||| || ||||| +++++- Coarse X scroll
+   ||| || +++++------- Coarse Y scroll
+   ||| ++------------- Nametable select
+   +++---------------- Fine Y scroll
+*/
+#[derive(Clone, Copy)]
 pub struct AddressRegister {
     value: u16,
 }
 
+const COARSE_X: u16 = 0x001F;
+const COARSE_Y: u16 = 0x03E0;
+const NAMETABLE_X: u16 = 0x0400;
+const NAMETABLE_Y: u16 = 0x0800;
+const FINE_Y: u16 = 0x7000;
+
 impl AddressRegister {
     pub fn new() -> Self {
         AddressRegister { value: 0 }
     }
 
     pub fn get_addr(&self) -> u16 {
+        self.value & 0x3FFF
+    }
+    pub fn get(&self) -> u16 {
         self.value
     }
+    pub fn set(&mut self, value: u16) {
+        self.value = value & 0x7FFF;
+    }
+
+    // Writes to $2006
     pub fn update(&mut self, value: u8, latch: bool) {
         // High byte first
         if latch {
-            self.value |= 0b1111_0000;
-            self.value += value as u16;
+            self.value = (self.value & 0xFF00) | value as u16;
         } else {
-            self.value |= 0b0000_1111;
-            self.value += (value as u16) << 8;
+            // Bit 14 is cleared
+            self.value = (self.value & 0x00FF) | (value as u16 & 0b0011_1111) << 8;
         }
     }
 
+    // Writes to $2000
+    pub fn set_nametable(&mut self, value: u8) {
+        self.value = (self.value & !(NAMETABLE_X | NAMETABLE_Y)) | (value as u16 & 0b11) << 10;
+    }
+    // Writes to $2005
+    pub fn set_coarse_x(&mut self, value: u8) {
+        self.value = (self.value & !COARSE_X) | value as u16;
+    }
+    pub fn set_coarse_y(&mut self, value: u8) {
+        self.value = (self.value & !COARSE_Y) | (value as u16) << 5;
+    }
+    pub fn set_fine_y(&mut self, value: u8) {
+        self.value = (self.value & !FINE_Y) | (value as u16 & 0b111) << 12;
+    }
+
+    pub fn coarse_x(&self) -> u16 {
+        self.value & COARSE_X
+    }
+    pub fn coarse_y(&self) -> u16 {
+        (self.value & COARSE_Y) >> 5
+    }
+    pub fn fine_y(&self) -> u16 {
+        (self.value & FINE_Y) >> 12
+    }
+
     pub fn increment(&mut self, inc: u8) {
-        self.value = self.value.wrapping_add(inc as u16);
+        self.value = self.value.wrapping_add(inc as u16) & 0x7FFF;
+    }
+
+    // Moves to the next tile, wrapping into the horizontally adjacent nametable
+    pub fn increment_coarse_x(&mut self) {
+        if self.coarse_x() == 31 {
+            self.value &= !COARSE_X;
+            self.value ^= NAMETABLE_X;
+        } else {
+            self.value += 1;
+        }
+    }
 
-        if self.get_addr() > 0x3FFF {
-            // Mirror down the address
-            self.value &= 0b01111111_11111111;
+    // Moves to the next pixel row, wrapping into the vertically adjacent nametable
+    pub fn increment_y(&mut self) {
+        if self.fine_y() < 7 {
+            self.value += 1 << 12;
+            return;
         }
+
+        self.value &= !FINE_Y;
+        let mut coarse_y = self.coarse_y();
+        if coarse_y == 29 {
+            coarse_y = 0;
+            self.value ^= NAMETABLE_Y;
+        } else if coarse_y == 31 {
+            // Attribute table rows wrap without switching nametable
+            coarse_y = 0;
+        } else {
+            coarse_y += 1;
+        }
+        self.set_coarse_y(coarse_y as u8);
+    }
+
+    pub fn copy_horizontal(&mut self, from: &AddressRegister) {
+        let mask = COARSE_X | NAMETABLE_X;
+        self.value = (self.value & !mask) | (from.value & mask);
+    }
+    pub fn copy_vertical(&mut self, from: &AddressRegister) {
+        let mask = COARSE_Y | NAMETABLE_Y | FINE_Y;
+        self.value = (self.value & !mask) | (from.value & mask);
     }
 }
@@ -29,6 +29,16 @@ impl MaskRegister {
         Self::empty()
     }
 
+    pub fn show_background(&self) -> bool {
+        self.contains(MaskRegister::SHOW_BACKGROUND)
+    }
+    pub fn show_sprites(&self) -> bool {
+        self.contains(MaskRegister::SHOW_SPRITES)
+    }
+    pub fn is_rendering(&self) -> bool {
+        self.show_background() || self.show_sprites()
+    }
+
     pub fn update(&mut self, value: u8) {
         self.bits = value;
     }
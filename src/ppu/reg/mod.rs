@@ -1,10 +1,8 @@
 pub mod address;
 pub mod control;
 pub mod mask;
-pub mod scroll;
 pub mod status;
 
 pub use address::AddressRegister;
 pub use control::ControlRegister;
 pub use mask::MaskRegister;
-pub use scroll::ScrollRegister;
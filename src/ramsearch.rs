@@ -0,0 +1,250 @@
+use crate::Nes;
+
+// How the bytes at a candidate address are read, 16-bit values are little endian
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ValueType {
+    pub bytes: u16,
+    pub signed: bool,
+}
+
+impl ValueType {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "u8" => Ok(ValueType {
+                bytes: 1,
+                signed: false,
+            }),
+            "s8" => Ok(ValueType {
+                bytes: 1,
+                signed: true,
+            }),
+            "u16" => Ok(ValueType {
+                bytes: 2,
+                signed: false,
+            }),
+            "s16" => Ok(ValueType {
+                bytes: 2,
+                signed: true,
+            }),
+            _ => Err(format!("\"{}\" is not u8, s8, u16 or s16", text)),
+        }
+    }
+
+    fn decode(self, lo: u8, hi: u8) -> i32 {
+        match (self.bytes, self.signed) {
+            (1, false) => lo as i32,
+            (1, true) => lo as i8 as i32,
+            (_, false) => u16::from_le_bytes([lo, hi]) as i32,
+            (_, true) => i16::from_le_bytes([lo, hi]) as i32,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    // Against a number
+    Equal(i32),
+    NotEqual(i32),
+    Less(i32),
+    Greater(i32),
+    // Against the value at the previous step
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+    // Previous value plus this, negative for decreased by
+    ChangedBy(i32),
+}
+
+impl Filter {
+    fn keeps(self, previous: i32, current: i32) -> bool {
+        match self {
+            Filter::Equal(value) => current == value,
+            Filter::NotEqual(value) => current != value,
+            Filter::Less(value) => current < value,
+            Filter::Greater(value) => current > value,
+            Filter::Changed => current != previous,
+            Filter::Unchanged => current == previous,
+            Filter::Increased => current > previous,
+            Filter::Decreased => current < previous,
+            Filter::ChangedBy(delta) => current - previous == delta,
+        }
+    }
+}
+
+// Narrows down addresses in console RAM and cartridge PRG RAM holding a game
+// variable by comparing snapshots taken between frames
+pub struct RamSearch {
+    value_type: ValueType,
+    candidates: Vec<u16>,
+    // Whole CPU address space, only candidates mean anything
+    previous: Vec<u8>,
+}
+
+impl RamSearch {
+    pub fn new(nes: &Nes, value_type: ValueType) -> Self {
+        // A 16-bit value may not run past the end of its region
+        let tail = value_type.bytes - 1;
+        let mut candidates: Vec<u16> = (0x0000..0x0800 - tail).collect();
+        if nes.bus().has_prg_ram() {
+            candidates.extend(0x6000..0x8000 - tail);
+        }
+
+        let mut search = RamSearch {
+            value_type,
+            candidates,
+            previous: vec![0; 0x10000],
+        };
+        search.snapshot(nes);
+        search
+    }
+
+    fn snapshot(&mut self, nes: &Nes) {
+        for &addr in &self.candidates {
+            for i in 0..self.value_type.bytes {
+                let addr = addr + i;
+                self.previous[addr as usize] = nes.read_memory(addr);
+            }
+        }
+    }
+
+    pub fn value(&self, nes: &Nes, addr: u16) -> i32 {
+        let hi = if self.value_type.bytes == 2 {
+            nes.read_memory(addr + 1)
+        } else {
+            0
+        };
+        self.value_type.decode(nes.read_memory(addr), hi)
+    }
+    pub fn previous_value(&self, addr: u16) -> i32 {
+        let addr = addr as usize;
+        self.value_type
+            .decode(self.previous[addr], self.previous[addr + 1])
+    }
+
+    // Keeps the candidates passing `filter` and remembers their values for
+    // the next step. Returns how many are left
+    pub fn filter(&mut self, nes: &Nes, filter: Filter) -> usize {
+        let mut candidates = std::mem::take(&mut self.candidates);
+        candidates.retain(|&addr| filter.keeps(self.previous_value(addr), self.value(nes, addr)));
+        self.candidates = candidates;
+        self.snapshot(nes);
+        self.candidates.len()
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+    pub fn value_type(&self) -> ValueType {
+        self.value_type
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+
+    fn nes() -> Nes {
+        Nes::new(&asm::test_rom("loop: jmp loop")).unwrap()
+    }
+
+    fn value_type(text: &str) -> ValueType {
+        ValueType::parse(text).unwrap()
+    }
+
+    #[test]
+    fn decoding() {
+        assert_eq!(value_type("u8").decode(0x80, 0xFF), 0x80);
+        assert_eq!(value_type("s8").decode(0x80, 0xFF), -0x80);
+        assert_eq!(value_type("u16").decode(0xFF, 0xFF), 0xFFFF);
+        assert_eq!(value_type("s16").decode(0xFF, 0xFF), -1);
+        assert_eq!(value_type("u16").decode(0x00, 0x80), 0x8000);
+        assert_eq!(value_type("s16").decode(0x00, 0x80), -0x8000);
+        assert_eq!(value_type("s16").decode(0x34, 0x12), 0x1234);
+        assert_eq!(
+            ValueType::parse("u32"),
+            Err("\"u32\" is not u8, s8, u16 or s16".to_string())
+        );
+
+        let mut nes = nes();
+        nes.write_memory(0x0010, 0xFE);
+        nes.write_memory(0x0011, 0xFF);
+        assert_eq!(
+            RamSearch::new(&nes, value_type("u16")).value(&nes, 0x10),
+            0xFFFE
+        );
+        let search = RamSearch::new(&nes, value_type("s16"));
+        assert_eq!(search.value(&nes, 0x10), -2);
+        assert_eq!(search.previous_value(0x10), -2);
+    }
+
+    // $10 goes up by 2, $11 stays, $12 goes down by 2 and $13 stays
+    fn filtered(filter: Filter) -> Vec<u16> {
+        let mut nes = nes();
+        for (addr, value) in [(0x10, 5), (0x11, 5), (0x12, 5), (0x13, 9)] {
+            nes.write_memory(addr, value);
+        }
+        let mut search = RamSearch::new(&nes, value_type("u8"));
+        nes.write_memory(0x10, 7);
+        nes.write_memory(0x12, 3);
+        search.filter(&nes, filter);
+        let candidates = search.candidates().iter().copied();
+        candidates
+            .filter(|addr| (0x10..0x14).contains(addr))
+            .collect()
+    }
+
+    #[test]
+    fn filters() {
+        assert_eq!(filtered(Filter::Equal(5)), [0x11]);
+        assert_eq!(filtered(Filter::NotEqual(5)), [0x10, 0x12, 0x13]);
+        assert_eq!(filtered(Filter::Less(5)), [0x12]);
+        assert_eq!(filtered(Filter::Greater(5)), [0x10, 0x13]);
+        assert_eq!(filtered(Filter::Changed), [0x10, 0x12]);
+        assert_eq!(filtered(Filter::Unchanged), [0x11, 0x13]);
+        assert_eq!(filtered(Filter::Increased), [0x10]);
+        assert_eq!(filtered(Filter::Decreased), [0x12]);
+        assert_eq!(filtered(Filter::ChangedBy(2)), [0x10]);
+        assert_eq!(filtered(Filter::ChangedBy(-2)), [0x12]);
+    }
+
+    // Each step compares with the values at the one before
+    #[test]
+    fn narrowing_down() {
+        let mut nes = nes();
+        nes.write_memory(0x6100, 0xFF);
+        nes.write_memory(0x6101, 0xFF);
+        let mut search = RamSearch::new(&nes, value_type("s16"));
+        assert!(search.filter(&nes, Filter::Less(0)) > 0);
+        assert!(search.candidates().contains(&0x6100));
+
+        nes.write_memory(0x6100, 0x01);
+        nes.write_memory(0x6101, 0x00);
+        search.filter(&nes, Filter::ChangedBy(2));
+        assert_eq!(search.candidates(), [0x6100]);
+        assert_eq!(search.filter(&nes, Filter::Unchanged), 1);
+        assert_eq!(search.filter(&nes, Filter::Changed), 0);
+    }
+
+    // 16-bit values may not run past the end of RAM or PRG RAM
+    #[test]
+    fn candidate_range() {
+        let nes = nes();
+        let bytes = RamSearch::new(&nes, value_type("u8"));
+        let words = RamSearch::new(&nes, value_type("u16"));
+        assert_eq!(bytes.candidates().len(), 0x800 + 0x2000);
+        assert!(bytes.candidates().contains(&0x07FF) && bytes.candidates().contains(&0x7FFF));
+        assert_eq!(words.candidates().len(), 0x7FF + 0x1FFF);
+        assert!(!words.candidates().contains(&0x07FF) && !words.candidates().contains(&0x7FFF));
+
+        // CNROM has no PRG RAM
+        let mut rom = asm::test_rom("loop: jmp loop");
+        rom[5] = 1;
+        rom[6] |= 0x30;
+        rom.resize(rom.len() + 0x2000, 0);
+        let nes = Nes::new(&rom).unwrap();
+        let words = RamSearch::new(&nes, value_type("u16"));
+        assert_eq!(words.candidates(), (0..0x7FF).collect::<Vec<u16>>());
+    }
+}
@@ -0,0 +1,319 @@
+use std::collections::VecDeque;
+
+// Every this many frames a full save state is kept, frames in between are
+// stored as XOR against it with runs of unchanged bytes squeezed out
+const KEYFRAME_INTERVAL: usize = 60;
+
+struct Group {
+    keyframe: Vec<u8>,
+    deltas: Vec<Vec<u8>>,
+}
+
+impl Group {
+    fn frames(&self) -> usize {
+        1 + self.deltas.len()
+    }
+
+    fn size(&self) -> usize {
+        self.keyframe.len() + self.deltas.iter().map(Vec::len).sum::<usize>()
+    }
+
+    fn last_state(&self) -> Vec<u8> {
+        match self.deltas.last() {
+            Some(delta) => apply_delta(&self.keyframe, delta),
+            None => self.keyframe.clone(),
+        }
+    }
+}
+
+// Ring buffer of per-frame save states, oldest keyframe groups are dropped
+// when either limit is exceeded
+pub struct Rewind {
+    groups: VecDeque<Group>,
+    max_frames: usize,
+    memory_budget: usize,
+    frames: usize,
+    memory_used: usize,
+}
+
+impl Rewind {
+    pub fn new(max_frames: usize, memory_budget: usize) -> Self {
+        Rewind {
+            groups: VecDeque::new(),
+            max_frames,
+            memory_budget,
+            frames: 0,
+            memory_used: 0,
+        }
+    }
+
+    pub fn push(&mut self, state: &[u8]) {
+        let delta = match self.groups.back() {
+            Some(group)
+                if group.frames() < KEYFRAME_INTERVAL && group.keyframe.len() == state.len() =>
+            {
+                Some(encode_delta(&group.keyframe, state))
+            }
+            _ => None,
+        };
+
+        match delta {
+            Some(delta) => {
+                self.memory_used += delta.len();
+                self.groups.back_mut().unwrap().deltas.push(delta);
+            }
+            None => {
+                self.memory_used += state.len();
+                self.groups.push_back(Group {
+                    keyframe: state.to_vec(),
+                    deltas: Vec::new(),
+                });
+            }
+        }
+        self.frames += 1;
+
+        // The group being written is never dropped
+        while self.groups.len() > 1
+            && (self.frames > self.max_frames || self.memory_used > self.memory_budget)
+        {
+            let group = self.groups.pop_front().unwrap();
+            self.frames -= group.frames();
+            self.memory_used -= group.size();
+        }
+    }
+
+    // Drops the newest `frames` states and returns the one now on top,
+    // stops at the oldest state kept
+    pub fn rewind(&mut self, frames: usize) -> Option<Vec<u8>> {
+        for _ in 0..frames.min(self.frames.saturating_sub(1)) {
+            let group = self.groups.back_mut().unwrap();
+            match group.deltas.pop() {
+                Some(delta) => self.memory_used -= delta.len(),
+                None => {
+                    self.memory_used -= group.keyframe.len();
+                    self.groups.pop_back();
+                }
+            }
+            self.frames -= 1;
+        }
+
+        self.groups.back().map(Group::last_state)
+    }
+
+    pub fn clear(&mut self) {
+        self.groups.clear();
+        self.frames = 0;
+        self.memory_used = 0;
+    }
+
+    // Frames that can be stepped back
+    pub fn len(&self) -> usize {
+        self.frames
+    }
+    pub fn is_empty(&self) -> bool {
+        self.frames == 0
+    }
+    pub fn memory_used(&self) -> usize {
+        self.memory_used
+    }
+}
+
+// Pairs of (unchanged run length, changed run length) as LEB128 followed by
+// the changed bytes XORed with the keyframe
+fn encode_delta(base: &[u8], state: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < state.len() {
+        let same_start = i;
+        while i < state.len() && base[i] == state[i] {
+            i += 1;
+        }
+        let changed_start = i;
+        while i < state.len() && base[i] != state[i] {
+            i += 1;
+        }
+
+        write_varint(&mut out, changed_start - same_start);
+        write_varint(&mut out, i - changed_start);
+        out.extend(
+            base[changed_start..i]
+                .iter()
+                .zip(&state[changed_start..i])
+                .map(|(base, state)| base ^ state),
+        );
+    }
+    out
+}
+
+fn apply_delta(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut state = base.to_vec();
+    let mut pos = 0;
+    let mut i = 0;
+    while i < delta.len() {
+        pos += read_varint(delta, &mut i);
+        let changed = read_varint(delta, &mut i);
+        for byte in &mut state[pos..pos + changed] {
+            *byte ^= delta[i];
+            i += 1;
+        }
+        pos += changed;
+    }
+    state
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(base: &[u8], state: &[u8]) -> Vec<u8> {
+        let delta = encode_delta(base, state);
+        assert_eq!(apply_delta(base, &delta), state);
+        delta
+    }
+
+    // A state that differs from frame to frame in a few places
+    fn state(frame: usize) -> Vec<u8> {
+        let mut state = vec![0; 1000];
+        state[frame % 1000] = frame as u8;
+        state[500] = (frame / 7) as u8;
+        state
+    }
+
+    #[test]
+    fn empty_state_has_empty_delta() {
+        assert!(round_trip(&[], &[]).is_empty());
+    }
+
+    #[test]
+    fn unchanged_state_is_one_run() {
+        let base = vec![0x55; 300];
+        assert_eq!(round_trip(&base, &base), [0xAC, 0x02, 0x00]);
+    }
+
+    #[test]
+    fn long_unchanged_runs_stay_small() {
+        let base = vec![0; 100_000];
+        let mut state = base.clone();
+        state[50_000] = 1;
+        state[99_000] = 2;
+        assert_eq!(round_trip(&base, &state).len(), 13);
+    }
+
+    #[test]
+    fn changes_at_both_ends() {
+        let base = vec![0x10; 64];
+        let mut state = base.clone();
+        state[0] = 0x11;
+        state[63] = 0xFF;
+        let delta = round_trip(&base, &state);
+        assert_eq!(&delta[..3], [0x00, 0x01, 0x01]);
+        assert_eq!(&delta[delta.len() - 3..], [0x3E, 0x01, 0xEF]);
+    }
+
+    #[test]
+    fn everything_changed() {
+        let base: Vec<u8> = (0..=255).collect();
+        let state: Vec<u8> = base.iter().map(|byte| !byte).collect();
+        assert_eq!(round_trip(&base, &state).len(), 3 + 256);
+    }
+
+    #[test]
+    fn varints() {
+        for value in [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, u32::MAX as usize] {
+            let mut out = Vec::new();
+            write_varint(&mut out, value);
+            let mut pos = 0;
+            assert_eq!(read_varint(&out, &mut pos), value);
+            assert_eq!(pos, out.len());
+        }
+        let mut out = Vec::new();
+        write_varint(&mut out, 0x80);
+        assert_eq!(out, [0x80, 0x01]);
+    }
+
+    #[test]
+    fn rewinds_through_keyframes() {
+        let mut rewind = Rewind::new(1000, usize::MAX);
+        for frame in 0..150 {
+            rewind.push(&state(frame));
+        }
+        assert_eq!(rewind.len(), 150);
+        for frame in (0..149).rev() {
+            assert_eq!(rewind.rewind(1), Some(state(frame)));
+        }
+        // The oldest state stays
+        assert_eq!(rewind.rewind(5), Some(state(0)));
+        assert_eq!(rewind.len(), 1);
+    }
+
+    #[test]
+    fn states_of_another_size_start_a_group() {
+        let mut rewind = Rewind::new(1000, usize::MAX);
+        rewind.push(&[1, 2, 3]);
+        rewind.push(&[1, 2, 3, 4]);
+        assert_eq!(rewind.memory_used(), 7);
+        assert_eq!(rewind.rewind(1), Some(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn frame_limit_drops_oldest_groups() {
+        let mut rewind = Rewind::new(100, usize::MAX);
+        for frame in 0..250 {
+            rewind.push(&state(frame));
+        }
+        // Whole groups go, the group being written always stays
+        assert_eq!(rewind.len(), 70);
+        assert_eq!(rewind.rewind(1000), Some(state(180)));
+    }
+
+    #[test]
+    fn memory_budget_drops_oldest_groups() {
+        let mut rewind = Rewind::new(1000, 4000);
+        for frame in 0..300 {
+            rewind.push(&state(frame));
+            assert!(rewind.memory_used() <= 4000);
+            let size: usize = rewind.groups.iter().map(Group::size).sum();
+            assert_eq!(rewind.memory_used(), size);
+        }
+        assert_eq!(rewind.groups.len(), 2);
+        assert_eq!(rewind.len(), 120);
+        assert_eq!(rewind.rewind(1000), Some(state(180)));
+
+        // Even a single state over the budget is kept
+        let mut rewind = Rewind::new(1000, 10);
+        rewind.push(&state(0));
+        assert_eq!(rewind.rewind(0), Some(state(0)));
+    }
+
+    #[test]
+    fn clear_forgets_everything() {
+        let mut rewind = Rewind::new(1000, usize::MAX);
+        rewind.push(&state(0));
+        rewind.clear();
+        assert!(rewind.is_empty());
+        assert_eq!(rewind.memory_used(), 0);
+        assert_eq!(rewind.rewind(1), None);
+    }
+}
@@ -1,8 +1,13 @@
-#[derive(Debug, PartialEq)]
+use crate::checksum::Crc32;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Mirroring {
     Vertical,
     Horizontal,
     FourScreen,
+    // Both used by mappers that can switch mirroring
+    SingleScreenLower,
+    SingleScreenUpper,
 }
 
 const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
@@ -14,10 +19,15 @@ pub struct Rom {
     pub chr_rom: Vec<u8>,
     mapper: u8,
     pub screen_mirroring: Mirroring,
+    // Cartridge contains battery-backed PRG RAM
+    pub battery: bool,
 }
 
 impl Rom {
     pub fn new(raw: &[u8]) -> Result<Rom, String> {
+        if raw.len() < 16 {
+            return Err("File is too short to be a ROM".to_string());
+        }
         // First 4 bytes are string "NES^Z"
         if raw[0..4] != NES_TAG {
             return Err("File is not in iNES file format".to_string());
@@ -44,17 +54,34 @@ impl Rom {
         // Size is 8Kb by number of VROM banks
         let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
 
+        let battery = raw[6] & 0b10 != 0;
         let skip_trainer = raw[6] & 0b100 != 0;
 
         // Skip header and if need trainer
         let prg_rom_start = 16 + if skip_trainer { 512 } else { 0 };
         let chr_rom_start = prg_rom_start + prg_rom_size;
+        if raw.len() < chr_rom_start + chr_rom_size {
+            return Err("File is shorter than its header states".to_string());
+        }
 
         Ok(Rom {
             prg_rom: raw[prg_rom_start..(prg_rom_start + prg_rom_size)].to_vec(),
             chr_rom: raw[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec(),
             mapper,
             screen_mirroring,
+            battery,
         })
     }
+
+    pub fn mapper(&self) -> u8 {
+        self.mapper
+    }
+
+    // CRC-32 of PRG and CHR, header excluded, as ROM databases compute it
+    pub fn checksum(&self) -> u32 {
+        let mut crc = Crc32::new();
+        crc.update(&self.prg_rom);
+        crc.update(&self.chr_rom);
+        crc.finish()
+    }
 }
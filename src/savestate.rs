@@ -0,0 +1,413 @@
+// Save states
+//
+// File layout, all numbers little endian:
+//   "NESS" magic, u16 format version, u32 CRC-32 of the ROM, root section
+// Section:
+//   u16 field count, then per field: u8 name length, name, u8 type, value
+// Values are fixed size except bytes (u32 length prefix) and nested sections.
+//
+// Fields are looked up by name, so components may add fields over time.
+// A component adding one gives it a default for states that lack it, any
+// other missing field fails the load.
+pub const FORMAT_VERSION: u16 = 1;
+const MAGIC: [u8; 4] = *b"NESS";
+
+const TYPE_BOOL: u8 = 0;
+const TYPE_U8: u8 = 1;
+const TYPE_U16: u8 = 2;
+const TYPE_U32: u8 = 3;
+const TYPE_U64: u8 = 4;
+const TYPE_F32: u8 = 5;
+const TYPE_F64: u8 = 6;
+const TYPE_BYTES: u8 = 7;
+const TYPE_SECTION: u8 = 8;
+
+// Components nest a few levels deep. A corrupt file could nest sections until
+// the stack overflows
+const MAX_DEPTH: usize = 16;
+
+// Anything that can be written to and restored from a save state
+pub trait Snapshot {
+    fn save(&self, section: &mut Section);
+    fn load(&mut self, section: &Section) -> Result<(), String>;
+}
+
+pub enum Value {
+    Bool(bool),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    F32(f32),
+    F64(f64),
+    Bytes(Vec<u8>),
+    Section(Section),
+}
+
+pub struct Section {
+    // Kept in insertion order so the same state always encodes the same way
+    fields: Vec<(String, Value)>,
+}
+
+macro_rules! scalar_field {
+    ($put:ident, $get:ident, $variant:ident, $ty:ty) => {
+        pub fn $put(&mut self, name: &str, value: $ty) {
+            self.put(name, Value::$variant(value));
+        }
+        pub fn $get(&self, name: &str) -> Result<$ty, String> {
+            match self.get(name)? {
+                Value::$variant(value) => Ok(*value),
+                _ => Err(format!("Field \"{}\" has wrong type", name)),
+            }
+        }
+    };
+}
+
+impl Section {
+    pub fn new() -> Self {
+        Section { fields: Vec::new() }
+    }
+
+    pub fn put(&mut self, name: &str, value: Value) {
+        self.fields.push((name.to_string(), value));
+    }
+    pub fn get(&self, name: &str) -> Result<&Value, String> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value)
+            .ok_or_else(|| format!("Save state has no field \"{}\"", name))
+    }
+    pub fn contains(&self, name: &str) -> bool {
+        self.fields.iter().any(|(field, _)| field == name)
+    }
+
+    scalar_field!(put_bool, get_bool, Bool, bool);
+    scalar_field!(put_u8, get_u8, U8, u8);
+    scalar_field!(put_u16, get_u16, U16, u16);
+    scalar_field!(put_u32, get_u32, U32, u32);
+    scalar_field!(put_u64, get_u64, U64, u64);
+    scalar_field!(put_f32, get_f32, F32, f32);
+    scalar_field!(put_f64, get_f64, F64, f64);
+
+    pub fn put_bytes(&mut self, name: &str, value: &[u8]) {
+        self.put(name, Value::Bytes(value.to_vec()));
+    }
+    pub fn get_bytes(&self, name: &str) -> Result<&[u8], String> {
+        match self.get(name)? {
+            Value::Bytes(value) => Ok(value),
+            _ => Err(format!("Field \"{}\" has wrong type", name)),
+        }
+    }
+    // For fixed size memories, refuses data of another size
+    pub fn get_bytes_into(&self, name: &str, dest: &mut [u8]) -> Result<(), String> {
+        let value = self.get_bytes(name)?;
+        if value.len() != dest.len() {
+            return Err(format!(
+                "Field \"{}\" holds {} bytes, expected {}",
+                name,
+                value.len(),
+                dest.len()
+            ));
+        }
+        dest.copy_from_slice(value);
+        Ok(())
+    }
+
+    pub fn put_snapshot(&mut self, name: &str, component: &dyn Snapshot) {
+        let mut section = Section::new();
+        component.save(&mut section);
+        self.put(name, Value::Section(section));
+    }
+    pub fn get_snapshot(&self, name: &str, component: &mut dyn Snapshot) -> Result<(), String> {
+        match self.get(name)? {
+            Value::Section(section) => component.load(section),
+            _ => Err(format!("Field \"{}\" has wrong type", name)),
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&(self.fields.len() as u16).to_le_bytes());
+        for (name, value) in &self.fields {
+            out.push(name.len() as u8);
+            out.extend_from_slice(name.as_bytes());
+            match value {
+                Value::Bool(value) => {
+                    out.push(TYPE_BOOL);
+                    out.push(*value as u8);
+                }
+                Value::U8(value) => {
+                    out.push(TYPE_U8);
+                    out.push(*value);
+                }
+                Value::U16(value) => {
+                    out.push(TYPE_U16);
+                    out.extend_from_slice(&value.to_le_bytes());
+                }
+                Value::U32(value) => {
+                    out.push(TYPE_U32);
+                    out.extend_from_slice(&value.to_le_bytes());
+                }
+                Value::U64(value) => {
+                    out.push(TYPE_U64);
+                    out.extend_from_slice(&value.to_le_bytes());
+                }
+                Value::F32(value) => {
+                    out.push(TYPE_F32);
+                    out.extend_from_slice(&value.to_le_bytes());
+                }
+                Value::F64(value) => {
+                    out.push(TYPE_F64);
+                    out.extend_from_slice(&value.to_le_bytes());
+                }
+                Value::Bytes(value) => {
+                    out.push(TYPE_BYTES);
+                    out.extend_from_slice(&(value.len() as u32).to_le_bytes());
+                    out.extend_from_slice(value);
+                }
+                Value::Section(section) => {
+                    out.push(TYPE_SECTION);
+                    section.encode(out);
+                }
+            }
+        }
+    }
+
+    fn decode(reader: &mut Reader, depth: usize) -> Result<Self, String> {
+        if depth > MAX_DEPTH {
+            return Err("Save state nests sections too deep".to_string());
+        }
+        let count = u16::from_le_bytes(reader.take_array()?);
+        let mut section = Section::new();
+        for _ in 0..count {
+            let name_len = reader.take(1)?[0] as usize;
+            let name = String::from_utf8(reader.take(name_len)?.to_vec())
+                .map_err(|_| "Save state has a malformed field name".to_string())?;
+
+            let value = match reader.take(1)?[0] {
+                TYPE_BOOL => Value::Bool(reader.take(1)?[0] != 0),
+                TYPE_U8 => Value::U8(reader.take(1)?[0]),
+                TYPE_U16 => Value::U16(u16::from_le_bytes(reader.take_array()?)),
+                TYPE_U32 => Value::U32(u32::from_le_bytes(reader.take_array()?)),
+                TYPE_U64 => Value::U64(u64::from_le_bytes(reader.take_array()?)),
+                TYPE_F32 => Value::F32(f32::from_le_bytes(reader.take_array()?)),
+                TYPE_F64 => Value::F64(f64::from_le_bytes(reader.take_array()?)),
+                TYPE_BYTES => {
+                    let len = u32::from_le_bytes(reader.take_array()?) as usize;
+                    Value::Bytes(reader.take(len)?.to_vec())
+                }
+                TYPE_SECTION => Value::Section(Section::decode(reader, depth + 1)?),
+                kind => return Err(format!("Save state has unknown field type {}", kind)),
+            };
+            section.fields.push((name, value));
+        }
+
+        Ok(section)
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.data.len() - self.pos < len {
+            return Err("Save state is truncated".to_string());
+        }
+        let slice = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+}
+
+pub struct SaveState {
+    pub version: u16,
+    pub rom_checksum: u32,
+    pub root: Section,
+}
+
+impl SaveState {
+    pub fn new(rom_checksum: u32, root: Section) -> Self {
+        SaveState {
+            version: FORMAT_VERSION,
+            rom_checksum,
+            root,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&MAGIC);
+        out.extend_from_slice(&self.version.to_le_bytes());
+        out.extend_from_slice(&self.rom_checksum.to_le_bytes());
+        self.root.encode(&mut out);
+        out
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader { data, pos: 0 };
+        if reader.take(4).ok() != Some(&MAGIC[..]) {
+            return Err("File is not a save state".to_string());
+        }
+
+        let version = u16::from_le_bytes(reader.take_array()?);
+        if version > FORMAT_VERSION {
+            return Err(format!(
+                "Save state format {} is newer than supported {}",
+                version, FORMAT_VERSION
+            ));
+        }
+        let rom_checksum = u32::from_le_bytes(reader.take_array()?);
+        let root = Section::decode(&mut reader, 0)?;
+
+        Ok(SaveState {
+            version,
+            rom_checksum,
+            root,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm, Nes};
+
+    fn every_type() -> Section {
+        let mut inner = Section::new();
+        inner.put_u8("value", 7);
+        let mut root = Section::new();
+        root.put_bool("bool", true);
+        root.put_u8("u8", 0xAB);
+        root.put_u16("u16", 0xBEEF);
+        root.put_u32("u32", 0xDEAD_BEEF);
+        root.put_u64("u64", u64::MAX - 1);
+        root.put_f32("f32", -1.5);
+        root.put_f64("f64", 0.25);
+        root.put_bytes("bytes", &[1, 2, 3]);
+        root.put("inner", Value::Section(inner));
+        root
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = SaveState::new(0x1234_5678, every_type()).to_bytes();
+        let state = SaveState::from_bytes(&bytes).unwrap();
+        assert_eq!(state.version, FORMAT_VERSION);
+        assert_eq!(state.rom_checksum, 0x1234_5678);
+
+        let root = &state.root;
+        assert!(root.get_bool("bool").unwrap());
+        assert_eq!(root.get_u8("u8").unwrap(), 0xAB);
+        assert_eq!(root.get_u16("u16").unwrap(), 0xBEEF);
+        assert_eq!(root.get_u32("u32").unwrap(), 0xDEAD_BEEF);
+        assert_eq!(root.get_u64("u64").unwrap(), u64::MAX - 1);
+        assert_eq!(root.get_f32("f32").unwrap(), -1.5);
+        assert_eq!(root.get_f64("f64").unwrap(), 0.25);
+        assert_eq!(root.get_bytes("bytes").unwrap(), [1, 2, 3]);
+        match root.get("inner").unwrap() {
+            Value::Section(inner) => assert_eq!(inner.get_u8("value").unwrap(), 7),
+            _ => panic!("inner is not a section"),
+        }
+        assert_eq!(state.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn missing_and_mistyped_fields() {
+        let root = every_type();
+        assert_eq!(
+            root.get_u8("nothing").err().unwrap(),
+            "Save state has no field \"nothing\""
+        );
+        assert_eq!(
+            root.get_u16("u8").err().unwrap(),
+            "Field \"u8\" has wrong type"
+        );
+        let mut dest = [0; 4];
+        assert_eq!(
+            root.get_bytes_into("bytes", &mut dest).err().unwrap(),
+            "Field \"bytes\" holds 3 bytes, expected 4"
+        );
+    }
+
+    #[test]
+    fn truncated_input() {
+        let bytes = SaveState::new(0, every_type()).to_bytes();
+        for len in 0..bytes.len() {
+            assert!(
+                SaveState::from_bytes(&bytes[..len]).is_err(),
+                "{} bytes",
+                len
+            );
+        }
+        assert_eq!(
+            SaveState::from_bytes(b"NES\x1A").err().unwrap(),
+            "File is not a save state"
+        );
+    }
+
+    #[test]
+    fn newer_version() {
+        let mut bytes = SaveState::new(0, Section::new()).to_bytes();
+        bytes[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            SaveState::from_bytes(&bytes).err().unwrap(),
+            format!(
+                "Save state format {} is newer than supported {}",
+                FORMAT_VERSION + 1,
+                FORMAT_VERSION
+            )
+        );
+    }
+
+    #[test]
+    fn nesting_is_capped() {
+        let mut bytes = SaveState::new(0, Section::new()).to_bytes();
+        bytes.truncate(10);
+        for _ in 0..100_000 {
+            bytes.extend_from_slice(&[1, 0, 1, b's', TYPE_SECTION]);
+        }
+        bytes.extend_from_slice(&[0, 0]);
+        assert_eq!(
+            SaveState::from_bytes(&bytes).err().unwrap(),
+            "Save state nests sections too deep"
+        );
+    }
+
+    fn running(source: &str) -> Nes {
+        let mut nes = Nes::new(&asm::test_rom(source)).unwrap();
+        nes.run_frame();
+        nes
+    }
+
+    #[test]
+    fn states_of_another_rom_are_refused() {
+        let mut nes = running("loop: inx\njmp loop");
+        let other = running("loop: iny\njmp loop").save_state();
+        let hash = nes.state_hash();
+        let err = nes.load_state(&other).err().unwrap();
+        assert!(
+            err.starts_with("Save state was made with another ROM"),
+            "{}",
+            err
+        );
+        assert_eq!(nes.state_hash(), hash);
+
+        // Same goes for a state whose CRC got corrupted
+        let mut state = nes.save_state();
+        state[6] ^= 0xFF;
+        assert!(nes.load_state(&state).is_err());
+        state[6] ^= 0xFF;
+        nes.run_frame();
+        nes.load_state(&state).unwrap();
+        assert_eq!(nes.state_hash(), hash);
+    }
+}
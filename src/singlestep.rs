@@ -0,0 +1,372 @@
+use std::{
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    bus::{Access, BusAccess},
+    cpu::{opcode, Cpu, CpuBus, CpuVariant, FlatRam, Registers},
+    json::Json,
+};
+
+// Per-opcode single step tests, one file of them per opcode named like
+// `a9.json`: machine state before and after one instruction and what was on
+// the bus every cycle in between. The nes6502 set is for the 2A03, the
+// 6502 one for the NMOS 6502 with decimal mode
+// https://github.com/SingleStepTests/65x02
+pub struct Test {
+    pub name: String,
+    pub initial: State,
+    pub expected: State,
+    pub cycles: Vec<BusAccess>,
+}
+
+pub struct State {
+    pub registers: Registers,
+    pub ram: Vec<(u16, u8)>,
+}
+
+pub fn parse_tests(text: &str) -> Result<Vec<Test>, String> {
+    let json = Json::parse(text)?;
+    let tests = json.as_array().ok_or("Tests are not an array")?;
+    tests
+        .iter()
+        .enumerate()
+        .map(|(i, test)| parse_test(test).map_err(|err| format!("Test {}: {}", i, err)))
+        .collect()
+}
+
+fn parse_test(test: &Json) -> Result<Test, String> {
+    let name = test.get("name").and_then(Json::as_str).unwrap_or("");
+    let cycles = test
+        .get("cycles")
+        .and_then(Json::as_array)
+        .ok_or("no cycles")?
+        .iter()
+        .map(|cycle| {
+            let fields = cycle.as_array().unwrap_or_default();
+            let kind = match fields.get(2).and_then(Json::as_str) {
+                Some("read") => Access::Read,
+                Some("write") => Access::Write,
+                _ => return Err("bad cycle".to_string()),
+            };
+            Ok(BusAccess {
+                kind,
+                addr: number(fields.first(), "cycle address")?,
+                value: number(fields.get(1), "cycle value")?,
+            })
+        })
+        .collect::<Result<_, String>>()?;
+
+    Ok(Test {
+        name: name.to_string(),
+        initial: parse_state(test.get("initial").ok_or("no initial state")?)?,
+        expected: parse_state(test.get("final").ok_or("no final state")?)?,
+        cycles,
+    })
+}
+
+fn parse_state(state: &Json) -> Result<State, String> {
+    let registers = Registers {
+        a: number(state.get("a"), "A")?,
+        x: number(state.get("x"), "X")?,
+        y: number(state.get("y"), "Y")?,
+        sp: number(state.get("s"), "S")?,
+        p: number(state.get("p"), "P")?,
+        pc: number(state.get("pc"), "PC")?,
+    };
+    let ram = state
+        .get("ram")
+        .and_then(Json::as_array)
+        .ok_or("no RAM")?
+        .iter()
+        .map(|cell| {
+            let fields = cell.as_array().unwrap_or_default();
+            Ok((
+                number(fields.first(), "RAM address")?,
+                number(fields.get(1), "RAM value")?,
+            ))
+        })
+        .collect::<Result<_, String>>()?;
+    Ok(State { registers, ram })
+}
+
+fn number<T: TryFrom<u64>>(value: Option<&Json>, what: &str) -> Result<T, String> {
+    value
+        .and_then(Json::as_u64)
+        .and_then(|number| T::try_from(number).ok())
+        .ok_or_else(|| format!("bad {}", what))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OpcodeStatus {
+    Passed,
+    Failed,
+    // Unknown to the CPU, its tests are skipped
+    NotImplemented,
+}
+
+// Failures are counted by what went wrong, a test can fail more than one way
+pub struct OpcodeResult {
+    pub opcode: u8,
+    pub tests: usize,
+    pub register_failures: usize,
+    pub memory_failures: usize,
+    pub cycle_failures: usize,
+    pub panics: usize,
+    pub first_failure: Option<String>,
+}
+
+impl OpcodeResult {
+    pub fn implemented(&self) -> bool {
+        opcode::lookup(self.opcode).is_some()
+    }
+
+    pub fn status(&self) -> OpcodeStatus {
+        let failures =
+            self.register_failures + self.memory_failures + self.cycle_failures + self.panics;
+        if !self.implemented() {
+            OpcodeStatus::NotImplemented
+        } else if failures > 0 {
+            OpcodeStatus::Failed
+        } else {
+            OpcodeStatus::Passed
+        }
+    }
+}
+
+// CPU on a flat RAM bus, kept between tests so only the bytes a test touched
+// have to be cleared
+pub struct TestBench {
+    cpu: Cpu,
+    bus: FlatRam,
+}
+
+impl TestBench {
+    pub fn new(variant: CpuVariant) -> Self {
+        TestBench {
+            cpu: Cpu::with_variant(variant),
+            bus: FlatRam::new(),
+        }
+    }
+
+    // Runs one test and tells what did not match
+    fn run(&mut self, test: &Test) -> Vec<Mismatch> {
+        for &(addr, value) in &test.initial.ram {
+            self.bus.poke(addr, value);
+        }
+        // A test of an opcode the core lacks leaves the CPU halted
+        self.cpu = Cpu::with_variant(self.cpu.variant());
+        self.cpu.set_registers(test.initial.registers);
+
+        self.bus.set_access_log(true);
+        let (cpu, bus) = (&mut self.cpu, &mut self.bus);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| cpu.step(bus)));
+        let cycles = self.bus.take_access_log();
+        self.bus.set_access_log(false);
+
+        let mut mismatches = Vec::new();
+        if let Err(err) = result {
+            let message = err
+                .downcast_ref::<String>()
+                .map(String::as_str)
+                .or_else(|| err.downcast_ref::<&str>().copied())
+                .unwrap_or("panicked");
+            mismatches.push(Mismatch::Panic(message.to_string()));
+        } else {
+            mismatches.extend(self.compare(test, &cycles));
+        }
+
+        let touched = test.initial.ram.iter().chain(&test.expected.ram);
+        let touched = touched.map(|&(addr, _)| addr);
+        for addr in touched.chain(cycles.iter().map(|access| access.addr)) {
+            self.bus.poke(addr, 0);
+        }
+        mismatches
+    }
+
+    fn compare(&self, test: &Test, cycles: &[BusAccess]) -> Vec<Mismatch> {
+        let mut mismatches = Vec::new();
+        let (got, expected) = (self.cpu.registers(), test.expected.registers);
+        if got != expected {
+            mismatches.push(Mismatch::Registers(format!(
+                "got {}, expected {}",
+                describe_registers(got),
+                describe_registers(expected)
+            )));
+        }
+
+        for &(addr, value) in &test.expected.ram {
+            let got = self.bus.peek(addr);
+            if got != value {
+                mismatches.push(Mismatch::Memory(format!(
+                    "${:04X} = ${:02X}, expected ${:02X}",
+                    addr, got, value
+                )));
+                break;
+            }
+        }
+
+        let differing = (0..cycles.len().max(test.cycles.len())).find(|&i| {
+            match (cycles.get(i), test.cycles.get(i)) {
+                (Some(got), Some(wanted)) => {
+                    (got.kind, got.addr, got.value) != (wanted.kind, wanted.addr, wanted.value)
+                }
+                _ => true,
+            }
+        });
+        if let Some(i) = differing {
+            mismatches.push(Mismatch::Cycles(format!(
+                "cycle {}: {}, expected {}",
+                i + 1,
+                describe_access(cycles.get(i)),
+                describe_access(test.cycles.get(i))
+            )));
+        }
+        mismatches
+    }
+}
+
+enum Mismatch {
+    Registers(String),
+    Memory(String),
+    Cycles(String),
+    Panic(String),
+}
+
+fn describe_registers(registers: Registers) -> String {
+    format!(
+        "PC=${:04X} A=${:02X} X=${:02X} Y=${:02X} S=${:02X} P=${:02X}",
+        registers.pc, registers.a, registers.x, registers.y, registers.sp, registers.p
+    )
+}
+
+fn describe_access(access: Option<&BusAccess>) -> String {
+    match access {
+        Some(access) => {
+            let kind = match access.kind {
+                Access::Read => "read",
+                Access::Write => "write",
+            };
+            format!("{} ${:04X} = ${:02X}", kind, access.addr, access.value)
+        }
+        None => "nothing".to_string(),
+    }
+}
+
+// Tests of opcodes the CPU doesn't know are not run
+pub fn run_file(bench: &mut TestBench, opcode: u8, path: &Path) -> Result<OpcodeResult, String> {
+    let mut result = OpcodeResult {
+        opcode,
+        tests: 0,
+        register_failures: 0,
+        memory_failures: 0,
+        cycle_failures: 0,
+        panics: 0,
+        first_failure: None,
+    };
+    if !result.implemented() {
+        return Ok(result);
+    }
+
+    let text = fs::read_to_string(path)
+        .map_err(|err| format!("Can't read {}: {}", path.display(), err))?;
+    let tests = parse_tests(&text).map_err(|err| format!("{}: {}", path.display(), err))?;
+    for test in &tests {
+        result.tests += 1;
+        for mismatch in bench.run(test) {
+            let detail = match mismatch {
+                Mismatch::Registers(detail) => {
+                    result.register_failures += 1;
+                    detail
+                }
+                Mismatch::Memory(detail) => {
+                    result.memory_failures += 1;
+                    detail
+                }
+                Mismatch::Cycles(detail) => {
+                    result.cycle_failures += 1;
+                    detail
+                }
+                Mismatch::Panic(detail) => {
+                    result.panics += 1;
+                    format!("panicked: {}", detail)
+                }
+            };
+            result
+                .first_failure
+                .get_or_insert_with(|| format!("\"{}\" {}", test.name, detail));
+        }
+    }
+    Ok(result)
+}
+
+// Every `xx.json` in `dir`, in opcode order
+pub fn run_directory(dir: &Path, variant: CpuVariant) -> Result<Vec<OpcodeResult>, String> {
+    let entries =
+        fs::read_dir(dir).map_err(|err| format!("Can't read {}: {}", dir.display(), err))?;
+    let mut files: Vec<(u8, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|path| {
+            let stem = path.file_stem()?.to_str()?;
+            let opcode = u8::from_str_radix(stem, 16).ok()?;
+            Some((opcode, path))
+        })
+        .collect();
+    files.sort();
+
+    // Panics are counted, not printed ten thousand times
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let mut bench = TestBench::new(variant);
+    let results = files
+        .iter()
+        .map(|(opcode, path)| run_file(&mut bench, *opcode, path))
+        .collect();
+    panic::set_hook(hook);
+    results
+}
+
+fn count(results: &[OpcodeResult], status: OpcodeStatus) -> usize {
+    results
+        .iter()
+        .filter(|result| result.status() == status)
+        .count()
+}
+
+// One line per opcode with failure counts, then the totals
+pub fn format_table(results: &[OpcodeResult]) -> String {
+    let mut table = "OP  NAME  TESTS   REGS    MEM CYCLES PANICS\n".to_string();
+    for result in results {
+        let name = opcode::lookup(result.opcode).map_or("---", |opcode| opcode.mnemonic);
+        table += &format!("{:02X}  {:4}", result.opcode, name);
+        if !result.implemented() {
+            table += "  not implemented\n";
+            continue;
+        }
+        table += &format!(
+            "  {:5} {:6} {:6} {:6} {:6}",
+            result.tests,
+            result.register_failures,
+            result.memory_failures,
+            result.cycle_failures,
+            result.panics
+        );
+        if let Some(failure) = &result.first_failure {
+            table += &format!("  {}", failure);
+        }
+        table.push('\n');
+    }
+
+    let not_implemented = count(results, OpcodeStatus::NotImplemented);
+    table += &format!(
+        "{}/{} opcodes passed, {} not implemented\n",
+        count(results, OpcodeStatus::Passed),
+        results.len() - not_implemented,
+        not_implemented
+    );
+    table
+}
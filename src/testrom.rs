@@ -0,0 +1,390 @@
+use std::{
+    collections::HashMap,
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+};
+
+use crate::{checksum, Nes};
+
+// Emulated time a ROM gets to report, the slowest of blargg's take under a minute
+pub const DEFAULT_FRAME_LIMIT: usize = 60 * 120;
+
+// Listing of known good screens kept in the test directory
+pub const SCREEN_HASH_FILE: &str = "screen_hashes.txt";
+
+// Reset requests are answered after this, the ROMs ask for at least 100 ms
+const RESET_DELAY_FRAMES: usize = 10;
+
+// Status protocol of blargg's newer tests: $6001-$6003 hold DE B0 61 once
+// $6000 is valid, $6000 is $80 while running, $81 to ask for a reset, then the
+// result code with 0 meaning passed. Text written so far sits at $6004
+// https://github.com/christopherpow/nes-test-roms/blob/master/instr_test-v5/readme.txt
+const STATUS_ADDR: u16 = 0x6000;
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const TEXT_ADDR: u16 = 0x6004;
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_RESET: u8 = 0x81;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    Passed,
+    // Result code the ROM reported
+    Failed(u8),
+    // Screen hash differing from the known good one
+    WrongScreen(u64),
+    // Still running when the frame limit was hit
+    TimedOut,
+    // No status and no known screen to compare with, the hash is there to
+    // be added once someone has checked the screen
+    NoResult(u64),
+    // Could not be read or loaded, the text says why
+    Error,
+    // Emulation panicked or the CPU halted, the text says where
+    Crashed,
+}
+
+pub struct TestResult {
+    pub path: PathBuf,
+    pub outcome: Outcome,
+    // What the ROM printed through the status protocol
+    pub text: String,
+    pub frames: usize,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.outcome == Outcome::Passed
+    }
+}
+
+// Frames to run and the hash the screen must have then
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScreenCheck {
+    pub frames: usize,
+    pub hash: u64,
+}
+
+// `name.nes FRAMES HASH` per line, # starts a comment
+pub fn parse_screen_hashes(text: &str) -> Result<HashMap<String, ScreenCheck>, String> {
+    let mut checks = HashMap::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let bad_line = || format!("Line {}: expected name.nes FRAMES HASH", number + 1);
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [name, frames, hash] = fields[..] else {
+            return Err(bad_line());
+        };
+        let check = ScreenCheck {
+            frames: frames.parse().map_err(|_| bad_line())?,
+            hash: u64::from_str_radix(hash, 16).map_err(|_| bad_line())?,
+        };
+        checks.insert(name.to_string(), check);
+    }
+    Ok(checks)
+}
+
+pub fn screen_hash(nes: &Nes) -> u64 {
+    checksum::fnv1a64(nes.framebuffer())
+}
+
+// Runs one ROM headless until it reports a result, or for the frames of its
+// screen check if it has one
+pub fn run_test_rom(
+    rom_data: &[u8],
+    screen: Option<ScreenCheck>,
+    frame_limit: usize,
+) -> Result<(Outcome, String, usize), String> {
+    let mut nes = Nes::new(rom_data)?;
+    let frame_limit = screen.map_or(frame_limit, |screen| screen.frames);
+    let mut reset_at = None;
+
+    for frame in 1..=frame_limit {
+        nes.run_frame();
+        nes.drain_audio();
+
+        if let Some(halt) = nes.cpu().halted() {
+            return Ok((Outcome::Crashed, halt.describe(), frame));
+        }
+        if !has_signature(&nes) || screen.is_some() {
+            continue;
+        }
+        match nes.read_memory(STATUS_ADDR) {
+            STATUS_RUNNING => (),
+            STATUS_RESET => {
+                let due = *reset_at.get_or_insert(frame + RESET_DELAY_FRAMES);
+                if frame >= due {
+                    nes.reset();
+                    reset_at = None;
+                }
+            }
+            0 => return Ok((Outcome::Passed, status_text(&nes), frame)),
+            code => return Ok((Outcome::Failed(code), status_text(&nes), frame)),
+        }
+    }
+
+    let hash = screen_hash(&nes);
+    let outcome = match screen {
+        Some(screen) if screen.hash == hash => Outcome::Passed,
+        Some(_) => Outcome::WrongScreen(hash),
+        None if has_signature(&nes) => Outcome::TimedOut,
+        None => Outcome::NoResult(hash),
+    };
+    let text = if has_signature(&nes) {
+        status_text(&nes)
+    } else {
+        String::new()
+    };
+    Ok((outcome, text, frame_limit))
+}
+
+fn has_signature(nes: &Nes) -> bool {
+    (0..3).all(|i| nes.read_memory(STATUS_ADDR + 1 + i) == SIGNATURE[i as usize])
+}
+
+fn status_text(nes: &Nes) -> String {
+    let bytes: Vec<u8> = (TEXT_ADDR..0x8000)
+        .map(|addr| nes.read_memory(addr))
+        .take_while(|&byte| byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).trim().to_string()
+}
+
+// Every .nes file under `dir`, in name order. Screen checks come from the
+// listing file of the directory holding the ROM. A ROM that can't be loaded
+// or brings the emulator down fails on its own, the others still run
+pub fn run_directory(dir: &Path, frame_limit: usize) -> Result<Vec<TestResult>, String> {
+    let mut results = Vec::new();
+    for path in find_roms(dir)? {
+        let (outcome, text, frames) = match run_file(&path, frame_limit) {
+            Ok(result) => result,
+            Err(err) => (Outcome::Error, err, 0),
+        };
+        results.push(TestResult {
+            path,
+            outcome,
+            text,
+            frames,
+        });
+    }
+    Ok(results)
+}
+
+fn run_file(path: &Path, frame_limit: usize) -> Result<(Outcome, String, usize), String> {
+    let listing = path.with_file_name(SCREEN_HASH_FILE);
+    let checks = match fs::read_to_string(&listing) {
+        Ok(text) => {
+            parse_screen_hashes(&text).map_err(|err| format!("{}: {}", listing.display(), err))?
+        }
+        Err(_) => HashMap::new(),
+    };
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let screen = checks.get(name.as_ref()).copied();
+
+    let rom_data = fs::read(path).map_err(|err| format!("Can't read it: {}", err))?;
+    let run = panic::catch_unwind(AssertUnwindSafe(|| {
+        run_test_rom(&rom_data, screen, frame_limit)
+    }));
+    match run {
+        Ok(result) => result,
+        Err(err) => {
+            let message = err
+                .downcast_ref::<String>()
+                .map(String::as_str)
+                .or_else(|| err.downcast_ref::<&str>().copied())
+                .unwrap_or("panicked");
+            Ok((Outcome::Crashed, message.to_string(), 0))
+        }
+    }
+}
+
+fn find_roms(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries =
+        fs::read_dir(dir).map_err(|err| format!("Can't read {}: {}", dir.display(), err))?;
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .collect();
+    paths.sort();
+
+    let mut roms = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            roms.extend(find_roms(&path)?);
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("nes"))
+        {
+            roms.push(path);
+        }
+    }
+    Ok(roms)
+}
+
+// One line per ROM with the path relative to `dir`, then the totals
+pub fn format_table(dir: &Path, results: &[TestResult]) -> String {
+    let names: Vec<String> = results
+        .iter()
+        .map(|result| {
+            let path = result.path.strip_prefix(dir).unwrap_or(&result.path);
+            path.display().to_string()
+        })
+        .collect();
+    let width = names.iter().map(|name| name.len()).max().unwrap_or(0);
+
+    let mut table = format!("{:width$}  {:>6}  RESULT\n", "ROM", "FRAMES");
+    for (name, result) in names.iter().zip(results) {
+        let outcome = match result.outcome {
+            Outcome::Passed => "pass".to_string(),
+            Outcome::Failed(code) => format!("FAIL #{}", code),
+            Outcome::WrongScreen(hash) => format!("FAIL screen {:016x}", hash),
+            Outcome::TimedOut => "FAIL timed out".to_string(),
+            Outcome::NoResult(hash) => format!("???? screen {:016x}", hash),
+            Outcome::Error => "FAIL error".to_string(),
+            Outcome::Crashed => "FAIL crashed".to_string(),
+        };
+        table += &format!("{:width$}  {:6}  {}", name, result.frames, outcome);
+        // What failed, on one line
+        if !result.passed() && !result.text.is_empty() {
+            let lines: Vec<&str> = result
+                .text
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .collect();
+            table += &format!("  {}", lines.join(" / "));
+        }
+        table.push('\n');
+    }
+
+    let passed = results.iter().filter(|result| result.passed()).count();
+    table += &format!("{}/{} passed\n", passed, results.len());
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+
+    // Code writing the signature and `text`, then `status`
+    fn report(status: u8, text: &str) -> String {
+        let mut source = String::new();
+        let bytes = SIGNATURE.iter().chain(text.as_bytes()).chain(&[0]);
+        for (addr, byte) in (STATUS_ADDR + 1..).zip(bytes) {
+            source += &format!("lda #{}\nsta ${:04X}\n", byte, addr);
+        }
+        source + &format!("lda #{}\nsta ${:04X}\n", status, STATUS_ADDR)
+    }
+
+    fn run(source: &str, frame_limit: usize) -> (Outcome, String, usize) {
+        run_test_rom(&asm::test_rom(source), None, frame_limit).unwrap()
+    }
+
+    // PRG RAM outlives the reset, the second run finds its mark there
+    #[test]
+    fn passes_after_asking_for_a_reset() {
+        let source = format!(
+            "reset: lda $6010
+                    cmp #$A5
+                    beq again
+                    lda #$A5
+                    sta $6010
+                    {}
+                    {}
+             wait:  jmp wait
+             again: {}
+             done:  jmp done",
+            report(STATUS_RUNNING, ""),
+            report(STATUS_RESET, ""),
+            report(0, "All passed"),
+        );
+        let (outcome, text, frames) = run(&source, 60);
+        assert_eq!(outcome, Outcome::Passed);
+        assert_eq!(text, "All passed");
+        assert!(frames > RESET_DELAY_FRAMES, "reset after {} frames", frames);
+    }
+
+    #[test]
+    fn reports_the_failure() {
+        let source = format!("{}\nloop: jmp loop", report(3, "Bad flags\n"));
+        assert_eq!(
+            run(&source, 60),
+            (Outcome::Failed(3), "Bad flags".to_string(), 1)
+        );
+    }
+
+    #[test]
+    fn times_out_while_running() {
+        let source = format!("{}\nloop: jmp loop", report(STATUS_RUNNING, "Testing"));
+        assert_eq!(
+            run(&source, 5),
+            (Outcome::TimedOut, "Testing".to_string(), 5)
+        );
+    }
+
+    #[test]
+    fn halted_cpu_crashes() {
+        let (outcome, text, _) = run(".byte 2", 60);
+        assert_eq!(outcome, Outcome::Crashed);
+        assert_eq!(text, "CPU halted on opcode $02 at $C000");
+    }
+
+    // Without the signature only the screen tells
+    #[test]
+    fn screen_checks() {
+        let rom = asm::test_rom("loop: jmp loop");
+        let (outcome, text, frames) = run_test_rom(&rom, None, 3).unwrap();
+        let Outcome::NoResult(hash) = outcome else {
+            panic!("{:?}", outcome);
+        };
+        assert_eq!((text.as_str(), frames), ("", 3));
+
+        let check = |hash| run_test_rom(&rom, Some(ScreenCheck { frames: 3, hash }), 100);
+        assert_eq!(check(hash).unwrap().0, Outcome::Passed);
+        assert_eq!(check(hash ^ 1).unwrap().0, Outcome::WrongScreen(hash));
+    }
+
+    #[test]
+    fn screen_hash_listing() {
+        let checks = parse_screen_hashes(
+            "# known good screens
+             sprite_hit.nes 120 0123456789abcdef
+
+             palette.nes  60  ff  # checked by hand",
+        )
+        .unwrap();
+        assert_eq!(checks.len(), 2);
+        assert_eq!(
+            checks["sprite_hit.nes"],
+            ScreenCheck {
+                frames: 120,
+                hash: 0x0123_4567_89AB_CDEF
+            }
+        );
+        assert_eq!(
+            checks["palette.nes"],
+            ScreenCheck {
+                frames: 60,
+                hash: 0xFF
+            }
+        );
+
+        let error = |text| parse_screen_hashes(text).err().unwrap();
+        assert_eq!(
+            error("a.nes 1 2\nb.nes 60"),
+            "Line 2: expected name.nes FRAMES HASH"
+        );
+        assert_eq!(
+            error("a.nes sixty 2"),
+            "Line 1: expected name.nes FRAMES HASH"
+        );
+        assert_eq!(
+            error("a.nes 60 xyz"),
+            "Line 1: expected name.nes FRAMES HASH"
+        );
+    }
+}
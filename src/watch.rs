@@ -0,0 +1,227 @@
+use std::ops::RangeInclusive;
+
+use crate::bus::Access;
+
+// Hits kept until someone takes them, later ones are only counted
+const HIT_LOG_LIMIT: usize = 0x10000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    // Either of them
+    Access,
+}
+
+impl WatchKind {
+    fn matches(self, access: Access) -> bool {
+        match self {
+            WatchKind::Read => access == Access::Read,
+            WatchKind::Write => access == Access::Write,
+            WatchKind::Access => true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchAction {
+    Log,
+    Break,
+}
+
+#[derive(Clone, Debug)]
+pub struct Watchpoint {
+    pub range: RangeInclusive<u16>,
+    pub kind: WatchKind,
+    // Only accesses of this value count
+    pub value: Option<u8>,
+    pub action: WatchAction,
+}
+
+impl Watchpoint {
+    // Mirrors of RAM and the PPU registers count as the address they mirror
+    pub fn matches(&self, access: Access, addr: u16, value: u8) -> bool {
+        self.kind.matches(access)
+            && (self.range.contains(&addr) || self.range.contains(&unmirrored(addr)))
+            && self.value.is_none_or(|wanted| wanted == value)
+    }
+}
+
+fn unmirrored(addr: u16) -> u16 {
+    match addr {
+        0x0000..=0x1FFF => addr & 0x07FF,
+        0x2000..=0x3FFF => addr & 0x2007,
+        _ => addr,
+    }
+}
+
+// CPU access that matched a watchpoint. `pc` is the instruction doing it
+#[derive(Clone, Copy, Debug)]
+pub struct WatchHit {
+    pub kind: Access,
+    pub addr: u16,
+    pub value: u8,
+    pub pc: u16,
+    pub cycle: u64,
+    pub scanline: u16,
+    pub dot: usize,
+}
+
+impl WatchHit {
+    pub fn describe(&self) -> String {
+        let kind = match self.kind {
+            Access::Read => "read",
+            Access::Write => "write",
+        };
+        format!(
+            "{} ${:04X} = ${:02X} by ${:04X}, cycle {} scanline {} dot {}",
+            kind, self.addr, self.value, self.pc, self.cycle, self.scanline, self.dot
+        )
+    }
+}
+
+// Watchpoints of the bus with what they caught
+pub struct Watchpoints {
+    list: Vec<Watchpoint>,
+    hits: Vec<WatchHit>,
+    dropped: usize,
+    break_hit: Option<WatchHit>,
+}
+
+impl Watchpoints {
+    pub fn new() -> Self {
+        Watchpoints {
+            list: Vec::new(),
+            hits: Vec::new(),
+            dropped: 0,
+            break_hit: None,
+        }
+    }
+
+    pub fn add(&mut self, watchpoint: Watchpoint) {
+        self.list.push(watchpoint);
+    }
+    pub fn remove(&mut self, index: usize) -> Option<Watchpoint> {
+        (index < self.list.len()).then(|| self.list.remove(index))
+    }
+    pub fn clear(&mut self) {
+        self.list.clear();
+    }
+    pub fn list(&self) -> &[Watchpoint] {
+        &self.list
+    }
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn check(&mut self, hit: WatchHit) {
+        let mut log = false;
+        for watchpoint in &self.list {
+            if !watchpoint.matches(hit.kind, hit.addr, hit.value) {
+                continue;
+            }
+            match watchpoint.action {
+                WatchAction::Log => log = true,
+                WatchAction::Break => {
+                    self.break_hit.get_or_insert(hit);
+                }
+            }
+        }
+
+        if log {
+            if self.hits.len() < HIT_LOG_LIMIT {
+                self.hits.push(hit);
+            } else {
+                self.dropped += 1;
+            }
+        }
+    }
+
+    // Logged hits and how many did not fit
+    pub fn take_hits(&mut self) -> (Vec<WatchHit>, usize) {
+        (
+            std::mem::take(&mut self.hits),
+            std::mem::take(&mut self.dropped),
+        )
+    }
+
+    // First hit of a breaking watchpoint since the last call. The access has
+    // already happened, the instruction doing it is finished before anyone looks
+    pub fn take_break(&mut self) -> Option<WatchHit> {
+        self.break_hit.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm, Nes};
+
+    fn watch(range: RangeInclusive<u16>, kind: WatchKind, action: WatchAction) -> Watchpoint {
+        Watchpoint {
+            range,
+            kind,
+            value: None,
+            action,
+        }
+    }
+
+    #[test]
+    fn ram_and_register_mirrors() {
+        let mut nes = Nes::new(&asm::test_rom(
+            "reset: lda #$11
+                    sta $0300
+                    sta $0B01   ; $0301
+                    sta $0400
+                    lda $3FFA   ; $2002
+                    lda $2002
+                    lda $2007
+             loop:  jmp loop",
+        ))
+        .unwrap();
+        let watchpoints = nes.parts_mut().1.watchpoints_mut();
+        watchpoints.add(watch(0x0300..=0x0301, WatchKind::Write, WatchAction::Log));
+        watchpoints.add(watch(0x2002..=0x2002, WatchKind::Read, WatchAction::Log));
+        watchpoints.add(Watchpoint {
+            value: Some(0x11),
+            ..watch(0x0300..=0x04FF, WatchKind::Access, WatchAction::Break)
+        });
+        watchpoints.add(Watchpoint {
+            value: Some(0x22),
+            ..watch(0x0000..=0xFFFF, WatchKind::Write, WatchAction::Break)
+        });
+
+        let mut breaks = Vec::new();
+        for _ in 0..8 {
+            nes.step_instruction();
+            let watchpoints = nes.parts_mut().1.watchpoints_mut();
+            breaks.extend(watchpoints.take_break().map(|hit| hit.addr));
+        }
+        assert_eq!(breaks, [0x0300, 0x0B01, 0x0400]);
+
+        let (hits, dropped) = nes.parts_mut().1.watchpoints_mut().take_hits();
+        assert_eq!(dropped, 0);
+        let seen: Vec<(Access, u16, u16)> = hits
+            .iter()
+            .map(|hit| (hit.kind, hit.addr, hit.pc))
+            .collect();
+        assert_eq!(
+            seen,
+            [
+                (Access::Write, 0x0300, 0xC002),
+                (Access::Write, 0x0B01, 0xC005),
+                (Access::Read, 0x3FFA, 0xC00B),
+                (Access::Read, 0x2002, 0xC00E),
+            ]
+        );
+        assert_eq!(hits[0].value, 0x11);
+        assert!(hits[0].cycle < hits[1].cycle);
+        assert_eq!(
+            hits[1].describe(),
+            format!(
+                "write $0B01 = $11 by $C005, cycle {} scanline {} dot {}",
+                hits[1].cycle, hits[1].scanline, hits[1].dot
+            )
+        );
+    }
+}
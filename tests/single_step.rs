@@ -0,0 +1,68 @@
+use std::{env, path::Path};
+
+use nesmulator::{
+    cpu::CpuVariant,
+    singlestep::{self, OpcodeStatus},
+};
+
+// Runs every opcode file in `dir`, only opcodes the CPU has must pass.
+// Returns how many tests ran
+fn check(dir: &Path, variant: CpuVariant) -> usize {
+    let results = singlestep::run_directory(dir, variant).unwrap();
+    println!("{}", singlestep::format_table(&results));
+    // Opcodes the CPU doesn't have are listed, only the others must pass
+    let with_status = |status| {
+        results
+            .iter()
+            .filter(move |result| result.status() == status)
+    };
+    let skipped: Vec<String> = with_status(OpcodeStatus::NotImplemented)
+        .map(|result| format!("{:02X}", result.opcode))
+        .collect();
+    if !skipped.is_empty() {
+        println!("Not implemented, skipped: {}", skipped.join(" "));
+    }
+    let tested = results.len() - skipped.len();
+    assert!(
+        tested > 0,
+        "No implemented opcode has tests in {}",
+        dir.display()
+    );
+    let failed: Vec<String> = with_status(OpcodeStatus::Failed)
+        .map(|result| format!("{:02X}", result.opcode))
+        .collect();
+    assert!(
+        failed.is_empty(),
+        "{} failed on the {:?}",
+        failed.join(" "),
+        variant
+    );
+    results.iter().map(|result| result.tests).sum()
+}
+
+// A few hand checked vectors in the same format, including decimal mode on
+// the NMOS 6502 that the 2A03 ignores
+#[test]
+fn fixture() {
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/single_step");
+    assert_eq!(check(&fixture.join("nes6502"), CpuVariant::Ricoh2A03), 5);
+    assert_eq!(check(&fixture.join("6502"), CpuVariant::Nmos6502), 4);
+}
+
+// The vectors are hundreds of megabytes, point NES_SINGLE_STEP_TESTS at the
+// nes6502/v1 directory of https://github.com/SingleStepTests/65x02 and
+// NMOS_SINGLE_STEP_TESTS at 6502/v1
+#[test]
+fn single_step() {
+    let sets = [
+        ("NES_SINGLE_STEP_TESTS", CpuVariant::Ricoh2A03),
+        ("NMOS_SINGLE_STEP_TESTS", CpuVariant::Nmos6502),
+    ];
+    for (var, variant) in sets {
+        let Ok(dir) = env::var(var) else {
+            eprintln!("{} is not set, skipping those single step tests", var);
+            continue;
+        };
+        check(Path::new(&dir), variant);
+    }
+}
@@ -0,0 +1,19 @@
+use std::{env, path::Path};
+
+use nesmulator::testrom;
+
+// Accuracy test ROMs are not shipped with the crate, point NES_TEST_ROMS at a
+// checkout of https://github.com/christopherpow/nes-test-roms or a part of it
+#[test]
+fn test_roms() {
+    let Ok(dir) = env::var("NES_TEST_ROMS") else {
+        eprintln!("NES_TEST_ROMS is not set, skipping the test ROMs");
+        return;
+    };
+    let dir = Path::new(&dir);
+
+    let results = testrom::run_directory(dir, testrom::DEFAULT_FRAME_LIMIT).unwrap();
+    println!("{}", testrom::format_table(dir, &results));
+    let failed = results.iter().filter(|result| !result.passed()).count();
+    assert_eq!(failed, 0, "{} test ROMs failed", failed);
+}